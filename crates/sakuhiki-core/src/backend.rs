@@ -4,9 +4,14 @@ use std::{
     ops::RangeBounds,
 };
 
+use eyre::WrapErr as _;
+use futures_util::TryStreamExt as _;
 use waaa::Future;
 
-use crate::{Db, IndexedDatum, Mode};
+use crate::{
+    CfOperationError, Db, Error, IndexedDatum, Migration, Mode,
+    metadata::{self, METADATA_CF},
+};
 
 const SAKUHIKI_PREFIX: &str = "__sakuhiki";
 
@@ -39,6 +44,17 @@ where
         'op: 'keys,
         R: ?Sized + AsRef<[u8]>;
 
+    /// Same as `scan`, but streams the keys in descending order
+    fn scan_rev<'op, 'keys, R>(
+        &'op self,
+        cf: &'op B::TransactionCf<'t>,
+        keys: impl 'keys + RangeBounds<R>,
+    ) -> waaa::BoxStream<'keys, eyre::Result<(B::Key<'op>, B::Value<'op>)>>
+    where
+        't: 'op,
+        'op: 'keys,
+        R: ?Sized + AsRef<[u8]>;
+
     fn scan_prefix<'op, 'key>(
         &'op self,
         cf: &'op B::TransactionCf<'t>,
@@ -48,23 +64,25 @@ where
         't: 'op,
         'op: 'key,
     {
-        fn plus_one(prefix: &mut [u8]) -> bool {
-            let mut prefix = prefix.to_owned();
-            for b in prefix.iter_mut().rev() {
-                if *b < 0xFF {
-                    *b += 1;
-                    return true;
-                } else {
-                    *b = 0;
-                }
-            }
-            false
+        match prefix_plus_one(prefix) {
+            Some(prefix_plus_one) => self.scan(cf, prefix.to_owned()..prefix_plus_one),
+            None => self.scan(cf, prefix..),
         }
-        let mut prefix_plus_one = prefix.to_owned();
-        if plus_one(&mut prefix_plus_one) {
-            self.scan(cf, prefix.to_owned()..prefix_plus_one)
-        } else {
-            self.scan(cf, prefix..)
+    }
+
+    /// Same as `scan_prefix`, but streams the keys in descending order
+    fn scan_prefix_rev<'op, 'key>(
+        &'op self,
+        cf: &'op B::TransactionCf<'t>,
+        prefix: &'key [u8],
+    ) -> waaa::BoxStream<'key, eyre::Result<(B::Key<'op>, B::Value<'op>)>>
+    where
+        't: 'op,
+        'op: 'key,
+    {
+        match prefix_plus_one(prefix) {
+            Some(prefix_plus_one) => self.scan_rev(cf, prefix.to_owned()..prefix_plus_one),
+            None => self.scan_rev(cf, prefix..),
         }
     }
 
@@ -93,6 +111,40 @@ where
     ) -> waaa::BoxFuture<'op, eyre::Result<()>>;
 }
 
+/// Returns the smallest key that is greater than all the keys starting with `prefix`
+///
+/// Returns `None` if there is no such key, ie. if `prefix` is only made of `0xFF` bytes.
+pub fn prefix_plus_one(prefix: &[u8]) -> Option<Vec<u8>> {
+    let mut res = prefix.to_owned();
+    while let Some(last) = res.pop() {
+        if last != u8::MAX {
+            res.push(last + 1);
+            return Some(res);
+        }
+    }
+    None
+}
+
+/// Bound on the actions run in a transaction, and on their result
+///
+/// On the web, this requires them to be `'static`. The IndexedDB backend drives transactions from
+/// the IndexedDB callbacks, which would keep running the actions after the end of their borrows
+/// if the transaction future were leaked. On all other targets, this is implemented for all types.
+#[cfg(all(target_arch = "wasm32", target_os = "unknown"))]
+pub trait MaybeStatic: 'static {}
+#[cfg(all(target_arch = "wasm32", target_os = "unknown"))]
+impl<T: 'static> MaybeStatic for T {}
+
+/// Bound on the actions run in a transaction, and on their result
+///
+/// On the web, this requires them to be `'static`. The IndexedDB backend drives transactions from
+/// the IndexedDB callbacks, which would keep running the actions after the end of their borrows
+/// if the transaction future were leaked. On all other targets, this is implemented for all types.
+#[cfg(not(all(target_arch = "wasm32", target_os = "unknown")))]
+pub trait MaybeStatic {}
+#[cfg(not(all(target_arch = "wasm32", target_os = "unknown")))]
+impl<T: ?Sized> MaybeStatic for T {}
+
 pub trait Backend: 'static {
     type Builder: BackendBuilder<Target = Self>;
 
@@ -106,6 +158,13 @@ pub trait Backend: 'static {
     type Transaction<'t>: waaa::Send + waaa::Sync + Transaction<'t, Self>;
     type TransactionCf<'t>: BackendCf;
 
+    /// Run `actions` inside a transaction over `cfs`.
+    ///
+    /// The transaction is committed iff `actions` returns `Ok`. If it returns `Err`, panics or
+    /// is dropped before completion, the transaction is rolled back and none of its writes are
+    /// visible.
+    ///
+    /// `actions` and its result must implement `MaybeStatic`, which only restricts them on the web.
     fn transaction<'fut, 'db, Bcf, F, Ret>(
         &'fut self,
         mode: Mode,
@@ -116,11 +175,12 @@ pub trait Backend: 'static {
         Bcf: 'fut + waaa::Send + waaa::Sync + Borrow<Self::Cf<'db>>,
         F: 'fut
             + waaa::Send
+            + MaybeStatic
             + for<'t> FnOnce(
-                &'t (),
-                Self::Transaction<'t>,
+                &'t Self::Transaction<'t>,
                 Vec<Self::TransactionCf<'t>>,
-            ) -> waaa::BoxFuture<'t, Ret>;
+            ) -> waaa::BoxFuture<'t, eyre::Result<Ret>>,
+        Ret: MaybeStatic;
 
     type Key<'op>: waaa::Send + waaa::Sync + AsRef<[u8]>;
     type Value<'op>: waaa::Send + waaa::Sync + AsRef<[u8]>;
@@ -145,11 +205,12 @@ pub enum CfOptions<B: Backend> {
     NotConfigured,
 }
 
-pub struct IndexRebuilder<B: Backend> {
-    pub datum_cf: &'static str,
-    pub index_cfs: &'static [&'static str],
+struct IndexRebuilder<B: Backend> {
+    index_cfs: &'static [&'static str],
+    foreign_cfs: Vec<&'static str>,
+    fingerprint: Vec<u8>,
     #[allow(clippy::type_complexity)]
-    pub rebuilder: Box<
+    rebuilder: Box<
         dyn Send
             + for<'fut, 't> FnOnce(
                 &'fut B::Transaction<'t>,
@@ -159,16 +220,124 @@ pub struct IndexRebuilder<B: Backend> {
     >,
 }
 
+/// Everything needed to bring the data of one datum type up to date when building the database
+struct DatumSetup<B: Backend> {
+    cf: &'static str,
+    migrations: &'static [Migration],
+    index_rebuilders: Vec<IndexRebuilder<B>>,
+}
+
+impl<B: Backend> DatumSetup<B> {
+    /// Run pending migrations, and rebuild the indexes that need it
+    ///
+    /// All indexes are rebuilt after a migration, as their content may depend on the datum's
+    /// serialization. Otherwise, only the indexes that were never built or whose fingerprint
+    /// changed are.
+    async fn run(self, backend: &B) -> eyre::Result<()> {
+        let num_index_cfs = self
+            .index_rebuilders
+            .iter()
+            .map(|i| i.index_cfs.len() + i.foreign_cfs.len());
+        let mut cfs = Vec::with_capacity(2 + num_index_cfs.sum::<usize>());
+        for cf in [METADATA_CF, self.cf].into_iter().chain(
+            self.index_rebuilders
+                .iter()
+                .flat_map(|i| i.index_cfs.iter().chain(&i.foreign_cfs).copied()),
+        ) {
+            cfs.push(
+                backend
+                    .cf_handle(cf)
+                    .await
+                    .wrap_err_with(|| CfOperationError::retrieving_cf(cf))?,
+            );
+        }
+        backend
+            .transaction(Mode::IndexRebuilding, &cfs, move |t, cfs| {
+                Box::pin(async move {
+                    let (metadata_cf, cfs) = cfs.split_first().unwrap();
+                    let (datum_cf, mut index_cfs) = cfs.split_first().unwrap();
+                    let migrated =
+                        Self::migrate(t, metadata_cf, datum_cf, self.cf, self.migrations).await?;
+                    for i in self.index_rebuilders {
+                        let this_index_cfs;
+                        (this_index_cfs, index_cfs) =
+                            index_cfs.split_at(i.index_cfs.len() + i.foreign_cfs.len());
+                        let built_fingerprint =
+                            metadata::index_fingerprint::<B>(t, metadata_cf, i.index_cfs).await?;
+                        if migrated || built_fingerprint.as_ref() != Some(&i.fingerprint) {
+                            (i.rebuilder)(t, this_index_cfs, datum_cf)
+                                .await
+                                .wrap_err_with(|| {
+                                    format!("Rebuilding index with CFs {:?}", i.index_cfs)
+                                })?;
+                            metadata::set_index_fingerprint::<B>(
+                                t,
+                                metadata_cf,
+                                i.index_cfs,
+                                &i.fingerprint,
+                            )
+                            .await?;
+                        }
+                    }
+                    Ok(())
+                })
+            })
+            .await
+            .wrap_err_with(|| format!("Failed setting up datum in CF {}", self.cf))
+    }
+
+    /// Returns `true` iff at least one migration was run
+    ///
+    /// This does not take `&self`, as the index rebuilders are not `Sync`.
+    async fn migrate<'t>(
+        t: &B::Transaction<'t>,
+        metadata_cf: &B::TransactionCf<'t>,
+        datum_cf: &B::TransactionCf<'t>,
+        cf: &'static str,
+        migrations: &'static [Migration],
+    ) -> eyre::Result<bool> {
+        let stored = metadata::schema_version::<B>(t, metadata_cf, cf).await?;
+        let latest = u64::try_from(migrations.len()).unwrap();
+        if stored > latest {
+            return Err(Error::UnknownSchemaVersion { cf, stored, latest }.into());
+        }
+        if stored == latest {
+            return Ok(false);
+        }
+        // TODO(low): migrate by batches, to avoid loading the whole CF in memory at once
+        let all_data = t
+            .scan::<[u8]>(datum_cf, ..)
+            .map_ok(|(key, datum)| (key.as_ref().to_vec(), datum.as_ref().to_vec()))
+            .try_collect::<Vec<_>>()
+            .await
+            .wrap_err_with(|| CfOperationError::new("Failed scanning through", datum_cf.name()))?;
+        let pending = &migrations[usize::try_from(stored).unwrap()..];
+        for (key, mut datum) in all_data {
+            for (version, migration) in (stored..).zip(pending) {
+                datum = migration(&datum).wrap_err_with(|| {
+                    format!("Failed migrating {key:?} from schema version {version}")
+                })?;
+            }
+            t.put(datum_cf, &key, &datum)
+                .await
+                .wrap_err_with(|| CfOperationError::new("Failed writing to", datum_cf.name()))?;
+        }
+        metadata::set_schema_version::<B>(t, metadata_cf, cf, latest).await?;
+        Ok(true)
+    }
+}
+
 pub struct BuilderConfig<B: Backend> {
     pub cfs: HashMap<&'static str, CfOptions<B>>,
     pub drop_unknown_cfs: bool,
-    pub index_rebuilders: Vec<IndexRebuilder<B>>,
 }
 
 pub struct Builder<B: Backend> {
     builder: Option<B::Builder>,
     config: Option<BuilderConfig<B>>,
+    datums: Vec<DatumSetup<B>>,
     used_cfs: HashSet<&'static str>,
+    foreign_cfs: HashSet<&'static str>,
     require_all_cfs_configured: bool,
     allow_extra_cf_config: bool,
 }
@@ -180,9 +349,10 @@ impl<B: Backend> Builder<B> {
             config: Some(BuilderConfig {
                 cfs: HashMap::new(),
                 drop_unknown_cfs: false,
-                index_rebuilders: Vec::new(),
             }),
+            datums: Vec::new(),
             used_cfs: HashSet::new(),
+            foreign_cfs: HashSet::new(),
             require_all_cfs_configured: false,
             allow_extra_cf_config: false,
         }
@@ -243,26 +413,44 @@ impl<B: Backend> Builder<B> {
             assert!(new_insert, "Multiple datum types require the same CF {cf}");
         }
 
-        let config = self.config.as_mut().expect("Reusing consumed builder");
         require_cf(&mut self.used_cfs, D::CF);
+        let mut index_rebuilders = Vec::with_capacity(D::INDEXES.len());
         for i in D::INDEXES {
             for cf in i.cfs() {
                 require_cf(&mut self.used_cfs, cf);
             }
-            config.index_rebuilders.push(IndexRebuilder {
-                datum_cf: D::CF,
+            let foreign_cfs = i.foreign_cfs();
+            self.foreign_cfs.extend(foreign_cfs.iter().copied());
+            // Fingerprints are keyed by index CFs: indexers without any store nothing to rebuild
+            if i.cfs().is_empty() {
+                continue;
+            }
+            index_rebuilders.push(IndexRebuilder {
                 index_cfs: i.cfs(),
+                foreign_cfs,
+                fingerprint: i.fingerprint(),
                 rebuilder: Box::new(move |t, index_cfs, datum_cf| {
                     Box::pin(async move { i.rebuild(t, index_cfs, datum_cf).await })
                 }),
             });
         }
+        self.datums.push(DatumSetup {
+            cf: D::CF,
+            migrations: D::MIGRATIONS,
+            index_rebuilders,
+        });
         self
     }
 
     pub async fn build(&mut self) -> eyre::Result<Db<B>> {
         let mut config = self.config.take().expect("Reusing consumed builder");
         let builder = self.builder.take().expect("Reusing consumed builder");
+        for cf in &self.foreign_cfs {
+            assert!(
+                self.used_cfs.contains(cf),
+                "An index requires CF {cf}, but no datum uses it"
+            );
+        }
         if self.require_all_cfs_configured {
             for cf in &self.used_cfs {
                 assert!(
@@ -285,6 +473,11 @@ impl<B: Backend> Builder<B> {
                 );
             }
         }
-        builder.build(config).await.map(Db::new)
+        config.cfs.insert(METADATA_CF, CfOptions::NotConfigured);
+        let backend = builder.build(config).await?;
+        for datum in std::mem::take(&mut self.datums) {
+            datum.run(&backend).await?;
+        }
+        Ok(Db::new(backend))
     }
 }
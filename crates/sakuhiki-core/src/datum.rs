@@ -1,8 +1,29 @@
-use crate::{Backend, Indexer};
+use crate::{Backend, Error, Indexer};
+
+/// Converts a serialized datum from one schema version to the next one
+pub type Migration = fn(&[u8]) -> eyre::Result<Vec<u8>>;
 
 pub trait Datum: 'static + Send + Sync + Sized {
     const CF: &'static str;
+
+    /// Migrations from the previous schema versions of this datum
+    ///
+    /// `MIGRATIONS[n]` converts a datum from schema version `n` to schema version `n + 1`, so the
+    /// current schema version is `MIGRATIONS.len()`. New migrations must only ever be appended.
+    ///
+    /// Pending migrations are run when building the `Db`, before which `from_slice` is never
+    /// called with data from a previous schema version.
+    const MIGRATIONS: &'static [Migration] = &[];
+
     fn from_slice(datum: &[u8]) -> eyre::Result<Self>;
+
+    /// Serialize this datum, such that `from_slice` parses it back
+    ///
+    /// This is only used by `put_datum`. Datums that are always written as raw bytes can keep the
+    /// default implementation, which fails.
+    fn to_bytes(&self) -> eyre::Result<Vec<u8>> {
+        Err(Error::DatumNotSerializable { cf: Self::CF }.into())
+    }
 }
 
 pub trait IndexedDatum<B: Backend>: 'static + Send + Sync + Datum {
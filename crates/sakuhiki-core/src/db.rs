@@ -6,8 +6,8 @@ use futures_util::{StreamExt as _, TryStreamExt as _, stream};
 use waaa::Stream;
 
 use crate::{
-    Backend, CfOperationError, Datum, IndexedDatum, Indexer, Mode,
-    backend::{BackendCf as _, Transaction as _},
+    Backend, CfOperationError, Datum, Error, Index, IndexedDatum, Indexer, Mode,
+    backend::{BackendCf as _, MaybeStatic, Transaction as _},
 };
 
 pub struct Db<B> {
@@ -26,7 +26,7 @@ where
     ///
     /// This can help recover from data corruption.
     pub async fn rebuild_index<I: Indexer<B>>(&self, index: &'static I) -> eyre::Result<()> {
-        let mut all_cfs = stream::iter(index.cfs())
+        let mut all_cfs = stream::iter(index.cfs().iter().copied().chain(index.foreign_cfs()))
             .then(|cf| async move {
                 self.backend
                     .cf_handle(cf)
@@ -43,13 +43,13 @@ where
         );
         let all_cfs = all_cfs.iter().collect::<Vec<_>>();
         self.backend
-            .transaction(Mode::ReadWrite, &all_cfs, move |_, t, mut cfs| {
+            .transaction(Mode::IndexRebuilding, &all_cfs, move |t, mut cfs| {
                 let datum_cf = cfs.pop().unwrap();
                 let index_cfs = cfs;
-                Box::pin(async move { index.rebuild(&t, &index_cfs, &datum_cf).await })
+                Box::pin(async move { index.rebuild(t, &index_cfs, &datum_cf).await })
             })
             .await
-            .wrap_err("Failed running index rebuilding transaction")?
+            .wrap_err("Failed running index rebuilding transaction")
     }
 
     pub async fn cf_handle<D>(&self) -> eyre::Result<Cf<'_, B>>
@@ -64,7 +64,7 @@ where
                 .wrap_err_with(|| CfOperationError::retrieving_cf(D::CF))?,
             indexes_cfs: stream::iter(D::INDEXES)
                 .then(|i| {
-                    stream::iter(i.cfs())
+                    stream::iter(i.cfs().iter().copied().chain(i.foreign_cfs()))
                         .then(async |cf| {
                             self.backend
                                 .cf_handle(cf)
@@ -78,6 +78,11 @@ where
         })
     }
 
+    /// Run `actions` inside a transaction over `cfs`.
+    ///
+    /// The transaction is committed iff `actions` returns `Ok`, and rolled back otherwise.
+    ///
+    /// `actions` and its result must implement `MaybeStatic`, see `Backend::transaction`.
     pub async fn transaction<'fut, const CFS: usize, F, Ret>(
         &'fut self,
         mode: Mode,
@@ -87,10 +92,12 @@ where
     where
         F: 'fut
             + waaa::Send
+            + MaybeStatic
             + for<'t> FnOnce(
                 Transaction<'t, B>,
                 [TransactionCf<'t, B>; CFS],
-            ) -> waaa::BoxFuture<'t, Ret>,
+            ) -> waaa::BoxFuture<'t, eyre::Result<Ret>>,
+        Ret: MaybeStatic,
     {
         let backend_cfs = cfs
             .iter()
@@ -99,16 +106,21 @@ where
             })
             .collect::<Vec<_>>();
         let num_backend_cfs = backend_cfs.len();
+        // Number of CFs of each index of each datum, to split `backend_cfs` back
+        let indexes_lens = cfs
+            .iter()
+            .map(|cf| cf.indexes_cfs.iter().map(Vec::len).collect::<Vec<_>>())
+            .collect::<Vec<_>>();
         self.backend
-            .transaction(mode, &backend_cfs, move |_, transaction, backend_cfs| {
+            .transaction(mode, &backend_cfs, move |transaction, backend_cfs| {
                 debug_assert!(num_backend_cfs == backend_cfs.len());
                 let mut backend_cfs = VecDeque::from(backend_cfs);
                 let mut frontend_cfs = Vec::with_capacity(CFS);
-                for cf in cfs {
+                for lens in indexes_lens {
                     let datum_cf = backend_cfs.pop_front().unwrap();
-                    let mut indexes_cfs = Vec::with_capacity(cf.indexes_cfs.len());
-                    for i in cf.indexes_cfs.iter() {
-                        indexes_cfs.push(backend_cfs.drain(0..i.len()).collect());
+                    let mut indexes_cfs = Vec::with_capacity(lens.len());
+                    for len in lens {
+                        indexes_cfs.push(backend_cfs.drain(0..len).collect());
                     }
                     frontend_cfs.push(TransactionCf {
                         datum_cf,
@@ -137,7 +149,7 @@ pub struct Transaction<'t, B>
 where
     B: 't + Backend,
 {
-    transaction: B::Transaction<'t>,
+    transaction: &'t B::Transaction<'t>,
 }
 
 pub struct TransactionCf<'t, B>
@@ -173,7 +185,112 @@ where
         self.transaction.scan(&cf.datum_cf, keys)
     }
 
-    // TODO(med): rename into put_slice, add put
+    /// Same as `scan`, but streams the keys in descending order
+    pub fn scan_rev<'op, 'keys, Keys, R>(
+        &'op self,
+        cf: &'op TransactionCf<'t, B>,
+        keys: Keys,
+    ) -> impl Stream<Item = eyre::Result<(B::Key<'op>, B::Value<'op>)>> + use<'t, 'op, 'keys, B, Keys, R>
+    where
+        'op: 'keys,
+        Keys: 'keys + RangeBounds<R>,
+        R: ?Sized + AsRef<[u8]>,
+    {
+        self.transaction.scan_rev(&cf.datum_cf, keys)
+    }
+
+    /// Run `query` against `index`, which must be one of the indexes of the datum in `cf`.
+    ///
+    /// Returns the stream of the keys and values of the matching objects.
+    #[allow(clippy::type_complexity)]
+    pub fn query<'op, 'q, I>(
+        &'op self,
+        cf: &'op TransactionCf<'t, B>,
+        index: &'q I,
+        query: &'q I::Query<'q>,
+    ) -> eyre::Result<waaa::BoxStream<'q, eyre::Result<(I::QueryKey<'op>, B::Value<'op>)>>>
+    where
+        'op: 'q,
+        I: Index<B>,
+    {
+        let cfs = Self::index_cfs(cf, index)?;
+        Ok(index.query(query, self.transaction, &cf.datum_cf, cfs))
+    }
+
+    /// Same as `query`, but only returns the keys of the matching objects, without reading them
+    pub fn query_keys<'op, 'q, I>(
+        &'op self,
+        cf: &'op TransactionCf<'t, B>,
+        index: &'q I,
+        query: &'q I::Query<'q>,
+    ) -> eyre::Result<waaa::BoxStream<'q, eyre::Result<I::QueryKey<'op>>>>
+    where
+        'op: 'q,
+        I: Index<B>,
+    {
+        let cfs = Self::index_cfs(cf, index)?;
+        Ok(index.query_keys(query, self.transaction, &cf.datum_cf, cfs))
+    }
+
+    /// Count the results of `query` against `index`, which must be one of the indexes of the
+    /// datum in `cf`.
+    pub async fn count<'op, 'q, I>(
+        &'op self,
+        cf: &'op TransactionCf<'t, B>,
+        index: &'q I,
+        query: &'q I::Query<'q>,
+    ) -> eyre::Result<usize>
+    where
+        'op: 'q,
+        I: Index<B>,
+    {
+        let cfs = Self::index_cfs(cf, index)?;
+        index
+            .count(query, self.transaction, &cf.datum_cf, cfs)
+            .await
+    }
+
+    /// Returns the CFs of `index` in `cf`
+    fn index_cfs<'op, I>(
+        cf: &'op TransactionCf<'t, B>,
+        index: &I,
+    ) -> eyre::Result<&'op [B::TransactionCf<'t>]>
+    where
+        I: Index<B>,
+    {
+        let index_cfs = index.cfs();
+        let all_cfs = index_cfs.iter().copied().chain(index.foreign_cfs());
+        let datum_cf = cf.datum_cf.name();
+        let cfs = cf
+            .indexes_cfs
+            .iter()
+            .find(|cfs| cfs.iter().map(|cf| cf.name()).eq(all_cfs.clone()));
+        match cfs {
+            Some(cfs) if datum_cf == I::Datum::CF => Ok(cfs),
+            _ => Err(eyre::Report::from(Error::IndexNotInDatum {
+                index_cfs,
+                datum_cf,
+            })),
+        }
+    }
+
+    /// Get the value of `key`, parsed as a `D`
+    pub async fn get_datum<D>(
+        &self,
+        cf: &TransactionCf<'t, B>,
+        key: &[u8],
+    ) -> eyre::Result<Option<D>>
+    where
+        D: Datum,
+    {
+        let Some(value) = self.get(cf, key).await? else {
+            return Ok(None);
+        };
+        D::from_slice(value.as_ref())
+            .map(Some)
+            .wrap_err("Failed parsing datum")
+    }
+
     // TODO(med): add sanity-check that the provided cf is the right one for D indeed, and same everywhere else
     pub async fn put<'op, 'kv, D>(
         &'op self,
@@ -185,6 +302,15 @@ where
         D: IndexedDatum<B>,
     {
         debug_assert!(D::INDEXES.len() == cf.indexes_cfs.len());
+        self.transaction.current_mode().check_writable()?;
+        let datum = if D::INDEXES.is_empty() {
+            None
+        } else {
+            Some(D::from_slice(value).wrap_err("Failed parsing new datum")?)
+        };
+        if let Some(datum) = &datum {
+            self.check_write(cf, key, Some(datum)).await?;
+        }
         let old = self
             .transaction
             .put(&cf.datum_cf, key, value)
@@ -192,19 +318,47 @@ where
             .wrap_err_with(|| {
                 CfOperationError::new("Failed putting value into", cf.datum_cf.name())
             })?;
-        for (i, cfs) in D::INDEXES.iter().zip(cf.indexes_cfs.iter()) {
-            if let Some(old) = &old {
-                i.unindex_from_slice(key, old.as_ref(), &self.transaction, cfs)
-                    .await
-                    .wrap_err("Failed unindexing old value")?;
-            }
-            i.index_from_slice(key, value, &self.transaction, cfs)
-                .await
-                .wrap_err("Failed indexing new value")?;
+        if let Some(datum) = datum {
+            let old_datum = old
+                .as_ref()
+                .map(|old| D::from_slice(old.as_ref()))
+                .transpose()
+                .wrap_err("Failed parsing old datum")?;
+            self.reindex(cf, key, old_datum.as_ref(), Some(&datum))
+                .await?;
         }
         Ok(old)
     }
 
+    /// Serialize `datum` and put it at `key`, returning the previous datum if any
+    pub async fn put_datum<D>(
+        &self,
+        cf: &TransactionCf<'t, B>,
+        key: &[u8],
+        datum: &D,
+    ) -> eyre::Result<Option<D>>
+    where
+        D: IndexedDatum<B>,
+    {
+        debug_assert!(D::INDEXES.len() == cf.indexes_cfs.len());
+        self.transaction.current_mode().check_writable()?;
+        let value = datum.to_bytes().wrap_err("Failed serializing datum")?;
+        self.check_write(cf, key, Some(datum)).await?;
+        let old = self
+            .transaction
+            .put(&cf.datum_cf, key, &value)
+            .await
+            .wrap_err_with(|| {
+                CfOperationError::new("Failed putting value into", cf.datum_cf.name())
+            })?;
+        let old = old
+            .map(|old| D::from_slice(old.as_ref()))
+            .transpose()
+            .wrap_err("Failed parsing old datum")?;
+        self.reindex(cf, key, old.as_ref(), Some(datum)).await?;
+        Ok(old)
+    }
+
     pub async fn delete<'op, 'key, D>(
         &'op self,
         cf: &'op TransactionCf<'t, B>,
@@ -214,18 +368,73 @@ where
         D: IndexedDatum<B>,
     {
         debug_assert!(D::INDEXES.len() == cf.indexes_cfs.len());
+        self.transaction.current_mode().check_writable()?;
+        if !D::INDEXES.is_empty() {
+            self.check_write::<D>(cf, key, None).await?;
+        }
         let old = self
             .transaction
             .delete(&cf.datum_cf, key)
             .await
             .wrap_err_with(|| CfOperationError::new("Failed deleting from", cf.datum_cf.name()))?;
         if let Some(old) = &old {
-            for (i, cfs) in D::INDEXES.iter().zip(cf.indexes_cfs.iter()) {
-                i.unindex_from_slice(key, old.as_ref(), &self.transaction, cfs)
-                    .await
-                    .wrap_err("Failed unindexing old value")?;
+            if !D::INDEXES.is_empty() {
+                let old_datum = D::from_slice(old.as_ref()).wrap_err("Failed parsing old datum")?;
+                self.reindex(cf, key, Some(&old_datum), None).await?;
             }
         }
         Ok(old)
     }
+
+    /// Check with all the indexes of `D` that `key` can go to `new`, before writing anything
+    async fn check_write<D>(
+        &self,
+        cf: &TransactionCf<'t, B>,
+        key: &[u8],
+        new: Option<&D>,
+    ) -> eyre::Result<()>
+    where
+        D: IndexedDatum<B>,
+    {
+        for (i, cfs) in D::INDEXES.iter().zip(cf.indexes_cfs.iter()) {
+            i.check_write(key, new, self.transaction, cfs)
+                .await
+                .wrap_err("Index refused the write")?;
+        }
+        Ok(())
+    }
+
+    /// Update all the indexes of `D` for `key` going from `old` to `new`
+    async fn reindex<D>(
+        &self,
+        cf: &TransactionCf<'t, B>,
+        key: &[u8],
+        old: Option<&D>,
+        new: Option<&D>,
+    ) -> eyre::Result<()>
+    where
+        D: IndexedDatum<B>,
+    {
+        for (i, cfs) in D::INDEXES.iter().zip(cf.indexes_cfs.iter()) {
+            match (old, new) {
+                (Some(old), Some(new)) => {
+                    i.reindex(key, old, new, self.transaction, cfs)
+                        .await
+                        .wrap_err("Failed reindexing value")?;
+                }
+                (Some(old), None) => {
+                    i.unindex(key, old, self.transaction, cfs)
+                        .await
+                        .wrap_err("Failed unindexing old value")?;
+                }
+                (None, Some(new)) => {
+                    i.index(key, new, self.transaction, cfs)
+                        .await
+                        .wrap_err("Failed indexing new value")?;
+                }
+                (None, None) => (),
+            }
+        }
+        Ok(())
+    }
 }
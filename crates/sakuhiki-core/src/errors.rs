@@ -11,6 +11,27 @@ use crate::Mode;
 pub enum Error {
     #[error("Invalid transaction mode: expected {expected:?}, got {actual:?}")]
     InvalidTransactionMode { expected: Mode, actual: Mode },
+
+    #[error("Index with CFs {index_cfs:?} is not an index of the datum in CF {datum_cf}")]
+    IndexNotInDatum {
+        index_cfs: &'static [&'static str],
+        datum_cf: &'static str,
+    },
+
+    #[error(
+        "Datum in CF {cf} has schema version {stored}, but only versions up to {latest} are known"
+    )]
+    UnknownSchemaVersion {
+        cf: &'static str,
+        stored: u64,
+        latest: u64,
+    },
+
+    #[error("Datum in CF {cf} cannot be serialized, as it does not implement `to_bytes`")]
+    DatumNotSerializable { cf: &'static str },
+
+    #[error("Metadata key {key:?} has invalid value {value:?}")]
+    InvalidMetadata { key: Vec<u8>, value: Vec<u8> },
 }
 
 pub struct CfOperationError {
@@ -1,8 +1,7 @@
+use futures_util::{TryStreamExt as _, future};
+
 use crate::{Backend, Indexer};
 
-// TODO(med): add FTS with eg. sakuhiki-index-tantivy?
-// See https://github.com/quickwit-oss/tantivy/issues/2659 for the doability
-// Unfortunately that might require having not only KV but also an object store
 pub trait Index<B: Backend>: 'static + Indexer<B> {
     type Query<'q>;
     type QueryKey<'k>: AsRef<[u8]>;
@@ -14,4 +13,36 @@ pub trait Index<B: Backend>: 'static + Indexer<B> {
         object_cf: &'op B::TransactionCf<'t>,
         cfs: &'op [B::TransactionCf<'t>],
     ) -> waaa::BoxStream<'q, eyre::Result<(Self::QueryKey<'op>, B::Value<'op>)>>;
+
+    /// Returns the keys of the objects `query` would return, without reading the objects
+    ///
+    /// Indexes should override this to avoid reading the matching objects.
+    fn query_keys<'q, 'op: 'q, 't: 'op>(
+        &'q self,
+        query: &'q Self::Query<'q>,
+        transaction: &'op B::Transaction<'t>,
+        object_cf: &'op B::TransactionCf<'t>,
+        cfs: &'op [B::TransactionCf<'t>],
+    ) -> waaa::BoxStream<'q, eyre::Result<Self::QueryKey<'op>>> {
+        Box::pin(
+            self.query(query, transaction, object_cf, cfs)
+                .map_ok(|(key, _)| key),
+        )
+    }
+
+    /// Returns the number of results `query` would return
+    ///
+    /// Indexes should override this to avoid reading the matching objects.
+    fn count<'q, 'op: 'q, 't: 'op>(
+        &'q self,
+        query: &'q Self::Query<'q>,
+        transaction: &'op B::Transaction<'t>,
+        object_cf: &'op B::TransactionCf<'t>,
+        cfs: &'op [B::TransactionCf<'t>],
+    ) -> waaa::BoxFuture<'q, eyre::Result<usize>> {
+        Box::pin(
+            self.query(query, transaction, object_cf, cfs)
+                .try_fold(0, |count, _| future::ready(Ok(count + 1))),
+        )
+    }
 }
@@ -2,7 +2,7 @@ use eyre::WrapErr as _;
 use futures_util::StreamExt as _;
 
 use crate::{
-    Backend, CfOperationError, Datum, Error, Mode,
+    Backend, CfOperationError, Datum,
     backend::{BackendCf as _, Transaction as _},
 };
 
@@ -11,6 +11,38 @@ pub trait Indexer<B: Backend>: Send + Sync {
 
     fn cfs(&self) -> &'static [&'static str];
 
+    /// CFs of other datums that this indexer needs to access, eg. to check a referenced object
+    ///
+    /// They are passed to all the functions taking `cfs`, right after the CFs from `cfs()`.
+    /// Unlike those, they are not owned by the index, and thus never cleared by `rebuild`.
+    fn foreign_cfs(&self) -> Vec<&'static str> {
+        Vec::new()
+    }
+
+    /// Identifies the layout of the data this index stores in its CFs
+    ///
+    /// It must change whenever the index CFs would have different contents for the same datums,
+    /// eg. after changing how keys are extracted. Building the `Db` rebuilds all the indexes
+    /// whose fingerprint changed since the last time they were built. Indexers without any CF
+    /// store nothing, and are thus neither fingerprinted nor rebuilt upon building the `Db`.
+    fn fingerprint(&self) -> Vec<u8>;
+
+    /// Checks that the object at `object_key` can be set to `new`, or deleted if `None`
+    ///
+    /// This is called before anything gets written. Indexes that can refuse a write, eg. because
+    /// of a constraint, must do so here rather than in `index` or `unindex`: by then the datum
+    /// was already written, and a caller recovering from the error would commit a datum that is
+    /// inconsistent with its indexes.
+    fn check_write<'fut, 't>(
+        &'fut self,
+        _object_key: &'fut [u8],
+        _new: Option<&'fut Self::Datum>,
+        _transaction: &'fut B::Transaction<'t>,
+        _cfs: &'fut [B::TransactionCf<'t>],
+    ) -> waaa::BoxFuture<'fut, eyre::Result<()>> {
+        Box::pin(async { Ok(()) })
+    }
+
     fn index<'fut, 't>(
         &'fut self,
         object_key: &'fut [u8],
@@ -27,6 +59,27 @@ pub trait Indexer<B: Backend>: Send + Sync {
         cfs: &'fut [B::TransactionCf<'t>],
     ) -> waaa::BoxFuture<'fut, eyre::Result<()>>;
 
+    /// Replaces the entries of `old` with the entries of `new`, for the same `object_key`
+    ///
+    /// Indexes should override this to avoid rewriting the entries that did not change.
+    fn reindex<'fut, 't>(
+        &'fut self,
+        object_key: &'fut [u8],
+        old: &'fut Self::Datum,
+        new: &'fut Self::Datum,
+        transaction: &'fut B::Transaction<'t>,
+        cfs: &'fut [B::TransactionCf<'t>],
+    ) -> waaa::BoxFuture<'fut, eyre::Result<()>> {
+        Box::pin(async move {
+            self.unindex(object_key, old, transaction, cfs)
+                .await
+                .wrap_err("Failed to unindex old datum")?;
+            self.index(object_key, new, transaction, cfs)
+                .await
+                .wrap_err("Failed to index new datum")
+        })
+    }
+
     fn index_from_slice<'fut, 't>(
         &'fut self,
         object_key: &'fut [u8],
@@ -77,13 +130,8 @@ where
     B: Backend,
     I: ?Sized + Indexer<B>,
 {
-    if transaction.current_mode() != Mode::IndexRebuilding {
-        return Err(eyre::Report::from(Error::InvalidTransactionMode {
-            expected: Mode::IndexRebuilding,
-            actual: transaction.current_mode(),
-        }));
-    }
-    for cf in index_cfs {
+    transaction.current_mode().check_index_rebuilding()?;
+    for cf in &index_cfs[..this.cfs().len()] {
         transaction
             .clear(cf)
             .await
@@ -4,10 +4,10 @@ pub mod backend;
 pub use backend::{Backend, BackendBuilder};
 
 mod datum;
-pub use datum::{Datum, IndexedDatum};
+pub use datum::{Datum, IndexedDatum, Migration};
 
 mod db;
-pub use db::{Db, Transaction};
+pub use db::{Db, Transaction, TransactionCf};
 
 mod errors;
 pub use errors::{CfOperationError, Error};
@@ -18,5 +18,18 @@ pub use index::Index;
 pub mod indexer;
 pub use indexer::Indexer;
 
+mod metadata;
+
 mod mode;
 pub use mode::Mode;
+
+#[cfg(feature = "serde")]
+pub mod serde_datum;
+#[cfg(feature = "serde")]
+pub use serde_datum::SerdeDatum;
+
+#[cfg(feature = "test-utils")]
+pub mod test_utils;
+
+#[cfg(test)]
+mod tests;
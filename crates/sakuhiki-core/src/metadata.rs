@@ -0,0 +1,84 @@
+//! Metadata that sakuhiki keeps about the database, in a reserved CF
+
+use eyre::WrapErr as _;
+
+use crate::{Backend, CfOperationError, Error, backend::Transaction as _};
+
+/// CF in which all the metadata is stored
+pub(crate) const METADATA_CF: &str = "__sakuhiki-metadata";
+
+fn schema_version_key(datum_cf: &str) -> Vec<u8> {
+    format!("schema-version/{datum_cf}").into_bytes()
+}
+
+fn index_key(index_cfs: &[&str]) -> Vec<u8> {
+    format!("index/{index_cfs:?}").into_bytes()
+}
+
+/// Returns the schema version of the data stored in `datum_cf`
+///
+/// Data that was stored before sakuhiki started tracking schema versions is at version 0.
+pub(crate) async fn schema_version<'t, B: Backend>(
+    t: &B::Transaction<'t>,
+    metadata_cf: &B::TransactionCf<'t>,
+    datum_cf: &str,
+) -> eyre::Result<u64> {
+    let key = schema_version_key(datum_cf);
+    let Some(value) = t
+        .get(metadata_cf, &key)
+        .await
+        .wrap_err_with(|| CfOperationError::new("Failed reading from", METADATA_CF))?
+    else {
+        return Ok(0);
+    };
+    let value = value.as_ref();
+    match <[u8; 8]>::try_from(value) {
+        Ok(version) => Ok(u64::from_be_bytes(version)),
+        Err(_) => Err(Error::InvalidMetadata {
+            key,
+            value: value.to_vec(),
+        }
+        .into()),
+    }
+}
+
+pub(crate) async fn set_schema_version<'t, B: Backend>(
+    t: &B::Transaction<'t>,
+    metadata_cf: &B::TransactionCf<'t>,
+    datum_cf: &str,
+    version: u64,
+) -> eyre::Result<()> {
+    t.put(
+        metadata_cf,
+        &schema_version_key(datum_cf),
+        &version.to_be_bytes(),
+    )
+    .await
+    .wrap_err_with(|| CfOperationError::new("Failed writing to", METADATA_CF))?;
+    Ok(())
+}
+
+/// Returns the fingerprint of the index stored in `index_cfs`, if it was ever built
+pub(crate) async fn index_fingerprint<'t, B: Backend>(
+    t: &B::Transaction<'t>,
+    metadata_cf: &B::TransactionCf<'t>,
+    index_cfs: &[&str],
+) -> eyre::Result<Option<Vec<u8>>> {
+    let value = t
+        .get(metadata_cf, &index_key(index_cfs))
+        .await
+        .wrap_err_with(|| CfOperationError::new("Failed reading from", METADATA_CF))?;
+    Ok(value.map(|v| v.as_ref().to_vec()))
+}
+
+pub(crate) async fn set_index_fingerprint<'t, B: Backend>(
+    t: &B::Transaction<'t>,
+    metadata_cf: &B::TransactionCf<'t>,
+    index_cfs: &[&str],
+    fingerprint: &[u8],
+) -> eyre::Result<()> {
+    t.put(metadata_cf, &index_key(index_cfs), fingerprint)
+        .await
+        .wrap_err_with(|| CfOperationError::new("Failed writing to", METADATA_CF))?;
+    Ok(())
+}
@@ -1,6 +1,34 @@
+use crate::Error;
+
 #[derive(Debug, Clone, Copy, Eq, PartialEq)]
 pub enum Mode {
     ReadOnly,
     ReadWrite,
     IndexRebuilding,
 }
+
+impl Mode {
+    /// Returns an error if transactions in this mode are not allowed to write
+    pub fn check_writable(self) -> Result<(), Error> {
+        match self {
+            Mode::ReadOnly => Err(Error::InvalidTransactionMode {
+                expected: Mode::ReadWrite,
+                actual: self,
+            }),
+            Mode::ReadWrite | Mode::IndexRebuilding => Ok(()),
+        }
+    }
+
+    /// Returns an error if transactions in this mode are not allowed to rebuild indexes
+    ///
+    /// This includes clearing whole CFs.
+    pub fn check_index_rebuilding(self) -> Result<(), Error> {
+        match self {
+            Mode::IndexRebuilding => Ok(()),
+            Mode::ReadOnly | Mode::ReadWrite => Err(Error::InvalidTransactionMode {
+                expected: Mode::IndexRebuilding,
+                actual: self,
+            }),
+        }
+    }
+}
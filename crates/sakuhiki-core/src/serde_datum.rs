@@ -0,0 +1,106 @@
+//! Automatic [`Datum`] implementation for any type implementing serde's traits.
+//!
+//! Implement [`SerdeDatum`] for a type, pick a [`Codec`], and the type becomes a [`Datum`].
+
+use serde::{Serialize, de::DeserializeOwned};
+
+use crate::{Datum, Migration};
+
+/// Serialization format used to store a [`SerdeDatum`]
+pub trait Codec: 'static {
+    fn serialize<T: Serialize>(value: &T) -> eyre::Result<Vec<u8>>;
+    fn deserialize<T: DeserializeOwned>(bytes: &[u8]) -> eyre::Result<T>;
+}
+
+/// Any type implementing `SerdeDatum` is a [`Datum`], stored with `Self::Codec`
+pub trait SerdeDatum: 'static + Send + Sync + Serialize + DeserializeOwned {
+    const CF: &'static str;
+    /// See [`Datum::MIGRATIONS`]; migrations operate on the `Codec`-serialized data
+    const MIGRATIONS: &'static [Migration] = &[];
+    type Codec: Codec;
+}
+
+impl<T> Datum for T
+where
+    T: SerdeDatum,
+{
+    const CF: &'static str = <T as SerdeDatum>::CF;
+    const MIGRATIONS: &'static [Migration] = <T as SerdeDatum>::MIGRATIONS;
+
+    fn from_slice(datum: &[u8]) -> eyre::Result<Self> {
+        T::Codec::deserialize(datum)
+    }
+
+    fn to_bytes(&self) -> eyre::Result<Vec<u8>> {
+        T::Codec::serialize(self)
+    }
+}
+
+#[cfg(feature = "postcard")]
+pub struct Postcard;
+
+#[cfg(feature = "postcard")]
+impl Codec for Postcard {
+    fn serialize<T: Serialize>(value: &T) -> eyre::Result<Vec<u8>> {
+        Ok(postcard::to_stdvec(value)?)
+    }
+
+    fn deserialize<T: DeserializeOwned>(bytes: &[u8]) -> eyre::Result<T> {
+        Ok(postcard::from_bytes(bytes)?)
+    }
+}
+
+/// Bincode with its standard configuration
+#[cfg(feature = "bincode")]
+pub struct Bincode;
+
+#[cfg(feature = "bincode")]
+impl Codec for Bincode {
+    fn serialize<T: Serialize>(value: &T) -> eyre::Result<Vec<u8>> {
+        Ok(bincode::serde::encode_to_vec(
+            value,
+            bincode::config::standard(),
+        )?)
+    }
+
+    fn deserialize<T: DeserializeOwned>(bytes: &[u8]) -> eyre::Result<T> {
+        let (value, len) = bincode::serde::decode_from_slice(bytes, bincode::config::standard())?;
+        if len != bytes.len() {
+            return Err(eyre::eyre!(
+                "Trailing bytes after bincode-encoded datum: read {len} out of {} bytes",
+                bytes.len()
+            ));
+        }
+        Ok(value)
+    }
+}
+
+#[cfg(feature = "cbor")]
+pub struct Cbor;
+
+#[cfg(feature = "cbor")]
+impl Codec for Cbor {
+    fn serialize<T: Serialize>(value: &T) -> eyre::Result<Vec<u8>> {
+        let mut res = Vec::new();
+        ciborium::into_writer(value, &mut res)?;
+        Ok(res)
+    }
+
+    fn deserialize<T: DeserializeOwned>(bytes: &[u8]) -> eyre::Result<T> {
+        Ok(ciborium::from_reader(bytes)?)
+    }
+}
+
+#[cfg(feature = "json")]
+pub struct Json;
+
+#[cfg(feature = "json")]
+impl Codec for Json {
+    fn serialize<T: Serialize>(value: &T) -> eyre::Result<Vec<u8>> {
+        Ok(serde_json::to_vec(value)?)
+    }
+
+    fn deserialize<T: DeserializeOwned>(bytes: &[u8]) -> eyre::Result<T> {
+        Ok(serde_json::from_slice(bytes)?)
+    }
+}
@@ -0,0 +1,211 @@
+//! Conformance tests that all backends must pass
+//!
+//! Each of these functions runs against a freshly built `db`, that must have an empty `foo` CF.
+
+use futures_util::TryStreamExt as _;
+
+use crate::{Backend, Error, Mode, backend::Transaction as _};
+
+/// CF that the tests run on
+pub const CF: &str = "foo";
+
+fn bytes(value: Option<impl AsRef<[u8]>>) -> Option<Vec<u8>> {
+    value.map(|v| v.as_ref().to_vec())
+}
+
+pub async fn transaction_operations<B: Backend>(db: &B) {
+    let cf = db.cf_handle(CF).await.unwrap();
+    db.transaction(Mode::ReadWrite, &[&cf], |t, cfs| {
+        Box::pin(async move {
+            let cf = &cfs[0];
+            assert_eq!(t.current_mode(), Mode::ReadWrite);
+            assert_eq!(bytes(t.get(cf, b"a").await.unwrap()), None);
+            assert_eq!(bytes(t.put(cf, b"a", b"1").await.unwrap()), None);
+            assert_eq!(
+                bytes(t.put(cf, b"a", b"2").await.unwrap()),
+                Some(b"1".to_vec())
+            );
+            assert_eq!(bytes(t.get(cf, b"a").await.unwrap()), Some(b"2".to_vec()));
+            assert_eq!(
+                bytes(t.delete(cf, b"a").await.unwrap()),
+                Some(b"2".to_vec())
+            );
+            assert_eq!(bytes(t.delete(cf, b"a").await.unwrap()), None);
+            assert_eq!(bytes(t.get(cf, b"a").await.unwrap()), None);
+            Ok(())
+        })
+    })
+    .await
+    .unwrap();
+}
+
+pub async fn scan_and_clear<B: Backend>(db: &B) {
+    let cf = db.cf_handle(CF).await.unwrap();
+    db.transaction(Mode::IndexRebuilding, &[&cf], |t, cfs| {
+        Box::pin(async move {
+            let cf = &cfs[0];
+            // Use many keys, to exercise the backends that scan by batches
+            for i in 0..1000u32 {
+                t.put(cf, &i.to_be_bytes(), &(2 * i).to_be_bytes())
+                    .await
+                    .unwrap();
+            }
+            let all = t
+                .scan::<[u8]>(cf, ..)
+                .map_ok(|(k, v)| (k.as_ref().to_vec(), v.as_ref().to_vec()))
+                .try_collect::<Vec<_>>()
+                .await
+                .unwrap();
+            assert_eq!(all.len(), 1000);
+            for (i, (k, v)) in all.into_iter().enumerate() {
+                let i = u32::try_from(i).unwrap();
+                assert_eq!(k, i.to_be_bytes());
+                assert_eq!(v, (2 * i).to_be_bytes());
+            }
+            let some = t
+                .scan(cf, 10u32.to_be_bytes()..=300u32.to_be_bytes())
+                .map_ok(|(k, _)| k.as_ref().to_vec())
+                .try_collect::<Vec<_>>()
+                .await
+                .unwrap();
+            assert_eq!(some.len(), 291);
+            assert_eq!(some[0], 10u32.to_be_bytes());
+            assert_eq!(some[290], 300u32.to_be_bytes());
+            let prefixed = t
+                .scan_prefix(cf, &[0, 0, 1])
+                .try_collect::<Vec<_>>()
+                .await
+                .unwrap();
+            assert_eq!(prefixed.len(), 256);
+            let some_rev = t
+                .scan_rev(cf, 10u32.to_be_bytes()..300u32.to_be_bytes())
+                .map_ok(|(k, _)| k.as_ref().to_vec())
+                .try_collect::<Vec<_>>()
+                .await
+                .unwrap();
+            assert_eq!(some_rev.len(), 290);
+            assert_eq!(some_rev[0], 299u32.to_be_bytes());
+            assert_eq!(some_rev[289], 10u32.to_be_bytes());
+            let all_rev = t
+                .scan_rev::<[u8]>(cf, ..)
+                .map_ok(|(k, _)| k.as_ref().to_vec())
+                .try_collect::<Vec<_>>()
+                .await
+                .unwrap();
+            assert_eq!(all_rev.len(), 1000);
+            for (i, k) in all_rev.into_iter().enumerate() {
+                assert_eq!(k, (999 - u32::try_from(i).unwrap()).to_be_bytes());
+            }
+            let prefixed_rev = t
+                .scan_prefix_rev(cf, &[0, 0, 1])
+                .map_ok(|(k, _)| k.as_ref().to_vec())
+                .try_collect::<Vec<_>>()
+                .await
+                .unwrap();
+            assert_eq!(prefixed_rev.len(), 256);
+            assert_eq!(prefixed_rev[0], 511u32.to_be_bytes());
+            t.clear(cf).await.unwrap();
+            let all = t
+                .scan::<[u8]>(cf, ..)
+                .try_collect::<Vec<_>>()
+                .await
+                .unwrap();
+            assert!(all.is_empty());
+            Ok(())
+        })
+    })
+    .await
+    .unwrap();
+}
+
+pub async fn commit_and_rollback<B: Backend>(db: &B) {
+    let cf = db.cf_handle(CF).await.unwrap();
+    db.transaction(Mode::ReadWrite, &[&cf], |t, cfs| {
+        Box::pin(async move {
+            t.put(&cfs[0], b"committed", b"1").await?;
+            Ok(())
+        })
+    })
+    .await
+    .unwrap();
+    let err = db
+        .transaction(Mode::ReadWrite, &[&cf], |t, cfs| {
+            Box::pin(async move {
+                t.put(&cfs[0], b"rolled-back", b"2").await?;
+                t.delete(&cfs[0], b"committed").await?;
+                assert_eq!(
+                    bytes(t.get(&cfs[0], b"rolled-back").await?),
+                    Some(b"2".to_vec())
+                );
+                assert_eq!(bytes(t.get(&cfs[0], b"committed").await?), None);
+                Err::<(), _>(eyre::eyre!("abort"))
+            })
+        })
+        .await
+        .unwrap_err();
+    assert_eq!(err.to_string(), "abort");
+    let err = db
+        .transaction(Mode::IndexRebuilding, &[&cf], |t, cfs| {
+            Box::pin(async move {
+                t.clear(&cfs[0]).await?;
+                t.put(&cfs[0], b"after-clear", b"3").await?;
+                assert_eq!(bytes(t.get(&cfs[0], b"committed").await?), None);
+                Err::<(), _>(eyre::eyre!("abort"))
+            })
+        })
+        .await
+        .unwrap_err();
+    assert_eq!(err.to_string(), "abort");
+    db.transaction(Mode::ReadOnly, &[&cf], |t, cfs| {
+        Box::pin(async move {
+            assert_eq!(
+                bytes(t.get(&cfs[0], b"committed").await?),
+                Some(b"1".to_vec())
+            );
+            assert_eq!(bytes(t.get(&cfs[0], b"rolled-back").await?), None);
+            assert_eq!(bytes(t.get(&cfs[0], b"after-clear").await?), None);
+            Ok(())
+        })
+    })
+    .await
+    .unwrap();
+}
+
+pub async fn mode_enforcement<B: Backend>(db: &B) {
+    let cf = db.cf_handle(CF).await.unwrap();
+    db.transaction(Mode::ReadOnly, &[&cf], |t, cfs| {
+        Box::pin(async move {
+            for res in [
+                t.put(&cfs[0], b"a", b"1").await.map(bytes),
+                t.delete(&cfs[0], b"a").await.map(bytes),
+            ] {
+                assert!(matches!(
+                    res.unwrap_err().downcast_ref::<Error>(),
+                    Some(Error::InvalidTransactionMode {
+                        expected: Mode::ReadWrite,
+                        actual: Mode::ReadOnly,
+                    })
+                ));
+            }
+            Ok(())
+        })
+    })
+    .await
+    .unwrap();
+    for mode in [Mode::ReadOnly, Mode::ReadWrite] {
+        db.transaction(mode, &[&cf], |t, cfs| {
+            Box::pin(async move {
+                assert!(matches!(
+                    t.clear(&cfs[0]).await.unwrap_err().downcast_ref::<Error>(),
+                    Some(Error::InvalidTransactionMode {
+                        expected: Mode::IndexRebuilding,
+                        ..
+                    })
+                ));
+                Ok(())
+            })
+        })
+        .await
+        .unwrap();
+    }
+}
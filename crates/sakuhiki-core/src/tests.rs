@@ -0,0 +1,85 @@
+#[cfg(feature = "serde")]
+mod serde_datum {
+    use serde::{Deserialize, Serialize};
+
+    use crate::{Datum, SerdeDatum, serde_datum::Codec};
+
+    #[derive(Serialize, Deserialize)]
+    struct User<C> {
+        name: String,
+        age: u8,
+        #[serde(skip)]
+        _codec: std::marker::PhantomData<fn(C)>,
+    }
+
+    impl<C: Codec> SerdeDatum for User<C> {
+        const CF: &'static str = "users";
+        type Codec = C;
+    }
+
+    fn roundtrip<C: Codec>() {
+        let user = User::<C> {
+            name: String::from("Alice"),
+            age: 42,
+            _codec: std::marker::PhantomData,
+        };
+        let bytes = user.to_bytes().unwrap();
+        let parsed = User::<C>::from_slice(&bytes).unwrap();
+        assert_eq!((parsed.name, parsed.age), (user.name, user.age));
+        assert_eq!(<User<C> as Datum>::CF, "users");
+    }
+
+    #[cfg(feature = "bincode")]
+    #[test]
+    fn bincode_roundtrip() {
+        roundtrip::<crate::serde_datum::Bincode>();
+    }
+
+    #[cfg(feature = "cbor")]
+    #[test]
+    fn cbor_roundtrip() {
+        roundtrip::<crate::serde_datum::Cbor>();
+    }
+
+    #[cfg(feature = "json")]
+    #[test]
+    fn json_roundtrip() {
+        roundtrip::<crate::serde_datum::Json>();
+    }
+
+    #[cfg(feature = "postcard")]
+    #[test]
+    fn postcard_roundtrip() {
+        roundtrip::<crate::serde_datum::Postcard>();
+    }
+}
+
+#[test]
+fn test_prefix_plus_one() {
+    use crate::backend::prefix_plus_one;
+
+    assert_eq!(prefix_plus_one(b"ab"), Some(b"ac".to_vec()));
+    assert_eq!(prefix_plus_one(b"a\xFF\xFF"), Some(b"b".to_vec()));
+    assert_eq!(prefix_plus_one(b"\xFF\xFF"), None);
+    assert_eq!(prefix_plus_one(b""), None);
+}
+
+#[test]
+fn test_datum_not_serializable() {
+    use crate::{Datum, Error};
+
+    struct ReadOnly;
+
+    impl Datum for ReadOnly {
+        const CF: &'static str = "read-only";
+
+        fn from_slice(_datum: &[u8]) -> eyre::Result<Self> {
+            Ok(ReadOnly)
+        }
+    }
+
+    assert!(matches!(
+        ReadOnly.to_bytes().unwrap_err().downcast_ref::<Error>(),
+        Some(Error::DatumNotSerializable { cf: "read-only" })
+    ));
+}
@@ -0,0 +1,225 @@
+//! Derive macros for `sakuhiki`, to be used through the `derive` feature of the `sakuhiki` crate.
+//!
+//! ```ignore
+//! #[derive(serde::Deserialize, serde::Serialize, sakuhiki::Datum, sakuhiki::IndexedDatum)]
+//! #[sakuhiki(cf = "users")]
+//! struct User {
+//!     #[index(btree)]
+//!     email: String,
+//!     name: String,
+//! }
+//! ```
+//!
+//! This makes `User` a `Datum` stored in CF `users`, and generates a `User::INDEX_EMAIL` BTree
+//! index stored in CF `users-email`.
+
+use proc_macro::TokenStream;
+use proc_macro2::TokenStream as TokenStream2;
+use quote::{format_ident, quote};
+use syn::{Data, DeriveInput, Fields, LitStr, Path, ext::IdentExt as _, parse_macro_input};
+
+/// Implements `Datum` through `SerdeDatum`.
+///
+/// The type must also implement `serde::Serialize` and `serde::Deserialize`.
+///
+/// Attributes:
+/// - `#[sakuhiki(cf = "name")]` (required) sets the CF in which the datum is stored
+/// - `#[sakuhiki(codec = path::to::Codec)]` sets the codec used to serialize the datum, defaults
+///   to `sakuhiki::serde_datum::Postcard`
+/// - `#[sakuhiki(migrations = path::to::MIGRATIONS)]` sets the datum's schema migrations, see
+///   `Datum::MIGRATIONS`
+#[proc_macro_derive(Datum, attributes(sakuhiki))]
+pub fn derive_datum(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    expand_datum(&input)
+        .unwrap_or_else(syn::Error::into_compile_error)
+        .into()
+}
+
+/// Implements `IndexedDatum` for all backends, with one BTree index per `#[index(btree)]` field.
+///
+/// Each index is available as an `INDEX_<FIELD>` associated constant, and uses CF
+/// `<datum cf>-<field>` unless overridden with `#[index(btree, cf = "name")]`. The indexed fields
+/// must implement `sakuhiki::KeyField`. `#[index(btree, version = 1)]` sets the index version, to
+/// be bumped whenever the indexed field changes while keeping the same CF.
+#[proc_macro_derive(IndexedDatum, attributes(sakuhiki, index))]
+pub fn derive_indexed_datum(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    expand_indexed_datum(&input)
+        .unwrap_or_else(syn::Error::into_compile_error)
+        .into()
+}
+
+struct DatumAttrs {
+    cf: LitStr,
+    codec: Option<Path>,
+    migrations: Option<Path>,
+}
+
+fn parse_datum_attrs(input: &DeriveInput) -> syn::Result<DatumAttrs> {
+    let mut cf = None;
+    let mut codec = None;
+    let mut migrations = None;
+    for attr in &input.attrs {
+        if !attr.path().is_ident("sakuhiki") {
+            continue;
+        }
+        attr.parse_nested_meta(|meta| {
+            if meta.path.is_ident("cf") {
+                cf = Some(meta.value()?.parse()?);
+                Ok(())
+            } else if meta.path.is_ident("codec") {
+                codec = Some(meta.value()?.parse()?);
+                Ok(())
+            } else if meta.path.is_ident("migrations") {
+                migrations = Some(meta.value()?.parse()?);
+                Ok(())
+            } else {
+                Err(meta.error("unsupported sakuhiki attribute"))
+            }
+        })?;
+    }
+    let cf = cf.ok_or_else(|| {
+        syn::Error::new_spanned(
+            &input.ident,
+            "missing `#[sakuhiki(cf = \"...\")]` attribute",
+        )
+    })?;
+    Ok(DatumAttrs {
+        cf,
+        codec,
+        migrations,
+    })
+}
+
+fn expand_datum(input: &DeriveInput) -> syn::Result<TokenStream2> {
+    let attrs = parse_datum_attrs(input)?;
+    let name = &input.ident;
+    let (impl_generics, ty_generics, where_clause) = input.generics.split_for_impl();
+    let cf = &attrs.cf;
+    let codec = match &attrs.codec {
+        Some(codec) => quote!(#codec),
+        None => quote!(::sakuhiki::serde_datum::Postcard),
+    };
+    let migrations = attrs.migrations.as_ref().map(
+        |migrations| quote!(const MIGRATIONS: &'static [::sakuhiki::Migration] = #migrations;),
+    );
+    Ok(quote! {
+        impl #impl_generics ::sakuhiki::SerdeDatum for #name #ty_generics #where_clause {
+            const CF: &'static str = #cf;
+            #migrations
+            type Codec = #codec;
+        }
+    })
+}
+
+struct Index {
+    field: syn::Ident,
+    ty: syn::Type,
+    cf: LitStr,
+    version: syn::LitInt,
+}
+
+fn parse_indexes(input: &DeriveInput, datum_cf: &LitStr) -> syn::Result<Vec<Index>> {
+    let Data::Struct(data) = &input.data else {
+        return Err(syn::Error::new_spanned(
+            &input.ident,
+            "IndexedDatum can only be derived for structs",
+        ));
+    };
+    let Fields::Named(fields) = &data.fields else {
+        return Err(syn::Error::new_spanned(
+            &input.ident,
+            "IndexedDatum can only be derived for structs with named fields",
+        ));
+    };
+    let mut indexes = Vec::new();
+    for field in &fields.named {
+        let field_name = field.ident.clone().expect("named fields have a name");
+        for attr in &field.attrs {
+            if !attr.path().is_ident("index") {
+                continue;
+            }
+            let mut is_btree = false;
+            let mut cf = None;
+            let mut version = None;
+            attr.parse_nested_meta(|meta| {
+                if meta.path.is_ident("btree") {
+                    is_btree = true;
+                    Ok(())
+                } else if meta.path.is_ident("cf") {
+                    cf = Some(meta.value()?.parse()?);
+                    Ok(())
+                } else if meta.path.is_ident("version") {
+                    version = Some(meta.value()?.parse()?);
+                    Ok(())
+                } else {
+                    Err(meta.error("unsupported index attribute"))
+                }
+            })?;
+            if !is_btree {
+                return Err(syn::Error::new_spanned(
+                    attr,
+                    "missing index kind, eg. `#[index(btree)]`",
+                ));
+            }
+            let cf = cf.unwrap_or_else(|| {
+                LitStr::new(
+                    &format!("{}-{}", datum_cf.value(), field_name.unraw()),
+                    field_name.span(),
+                )
+            });
+            let version = version.unwrap_or_else(|| syn::LitInt::new("0", field_name.span()));
+            indexes.push(Index {
+                field: field_name.clone(),
+                ty: field.ty.clone(),
+                cf,
+                version,
+            });
+        }
+    }
+    Ok(indexes)
+}
+
+fn expand_indexed_datum(input: &DeriveInput) -> syn::Result<TokenStream2> {
+    if !input.generics.params.is_empty() {
+        return Err(syn::Error::new_spanned(
+            &input.generics,
+            "IndexedDatum cannot be derived for generic types",
+        ));
+    }
+    let attrs = parse_datum_attrs(input)?;
+    let indexes = parse_indexes(input, &attrs.cf)?;
+    let name = &input.ident;
+    let vis = &input.vis;
+    let consts = indexes
+        .iter()
+        .map(|i| format_ident!("INDEX_{}", i.field.unraw().to_string().to_uppercase()))
+        .collect::<Vec<_>>();
+    let index_defs = indexes.iter().zip(&consts).map(|(i, c)| {
+        let Index {
+            field,
+            ty,
+            cf,
+            version,
+        } = i;
+        quote! {
+            #vis const #c: &'static ::sakuhiki::BTreeIndex<::sakuhiki::FieldKey<Self, #ty>> =
+                &::sakuhiki::BTreeIndex::new_versioned(
+                    &[#cf],
+                    ::sakuhiki::FieldKey::new(|d| &d.#field),
+                    #version,
+                );
+        }
+    });
+    Ok(quote! {
+        impl #name {
+            #(#index_defs)*
+        }
+
+        impl<B: ::sakuhiki::Backend> ::sakuhiki::IndexedDatum<B> for #name {
+            const INDEXES: &'static [&'static dyn ::sakuhiki::Indexer<B, Datum = Self>] =
+                &[#(Self::#consts),*];
+        }
+    })
+}
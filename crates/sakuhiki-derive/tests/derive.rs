@@ -0,0 +1,119 @@
+use futures_util::TryStreamExt as _;
+use sakuhiki::{BTreeQuery, Datum, IndexedDatum, KeyField as _, Mode};
+
+#[derive(Debug, Eq, PartialEq, serde::Deserialize, serde::Serialize, Datum, IndexedDatum)]
+#[sakuhiki(cf = "users")]
+struct User {
+    #[index(btree)]
+    email: String,
+    #[index(btree, cf = "users-by-age", version = 2)]
+    age: u8,
+    name: String,
+}
+
+#[derive(Debug, Eq, PartialEq, serde::Deserialize, serde::Serialize, Datum)]
+#[sakuhiki(cf = "notes", codec = sakuhiki::serde_datum::Json, migrations = NOTE_MIGRATIONS)]
+struct Note {
+    text: String,
+}
+
+#[derive(serde::Deserialize, serde::Serialize, Datum, IndexedDatum)]
+#[sakuhiki(cf = "items")]
+struct Item {
+    #[index(btree)]
+    r#type: String,
+}
+
+const NOTE_MIGRATIONS: &[sakuhiki::Migration] = &[|d| Ok(d.to_vec())];
+
+impl User {
+    fn new(email: &str, age: u8, name: &str) -> Self {
+        Self {
+            email: email.to_string(),
+            age,
+            name: name.to_string(),
+        }
+    }
+}
+
+#[test]
+fn test_generated_cfs() {
+    use sakuhiki::{Datum as _, Indexer};
+
+    assert_eq!(User::CF, "users");
+    assert_eq!(Note::CF, "notes");
+    assert_eq!(User::MIGRATIONS.len(), 0);
+    assert_eq!(Note::MIGRATIONS.len(), 1);
+    assert_eq!(
+        <_ as Indexer<sakuhiki_memdb::MemDb>>::cfs(User::INDEX_EMAIL),
+        &["users-email"]
+    );
+    assert_eq!(
+        <_ as Indexer<sakuhiki_memdb::MemDb>>::cfs(User::INDEX_AGE),
+        &["users-by-age"]
+    );
+    assert_eq!(
+        <_ as Indexer<sakuhiki_memdb::MemDb>>::cfs(Item::INDEX_TYPE),
+        &["items-type"]
+    );
+    // Same field type and version: the fingerprints do not depend on the datum type, as they are
+    // stored per index CF anyway
+    assert_eq!(
+        <_ as Indexer<sakuhiki_memdb::MemDb>>::fingerprint(User::INDEX_EMAIL),
+        <_ as Indexer<sakuhiki_memdb::MemDb>>::fingerprint(Item::INDEX_TYPE),
+    );
+    assert!(<_ as Indexer<sakuhiki_memdb::MemDb>>::fingerprint(User::INDEX_AGE).ends_with(b"/v2"));
+    assert_eq!(
+        <User as sakuhiki::IndexedDatum<sakuhiki_memdb::MemDb>>::INDEXES.len(),
+        2
+    );
+    let note = Note {
+        text: String::from("hello"),
+    };
+    assert_eq!(note.to_bytes().unwrap(), br#"{"text":"hello"}"#);
+}
+
+#[tokio::test]
+async fn test_derived_indexes() {
+    let db = sakuhiki_memdb::MemDb::builder()
+        .datum::<User>()
+        .build()
+        .await
+        .unwrap();
+    let users = db.cf_handle::<User>().await.unwrap();
+    db.transaction(Mode::ReadWrite, &[&users], |t, [users]| {
+        Box::pin(async move {
+            let alice = User::new("alice@example.org", 30, "Alice");
+            let bob = User::new("bob@example.org", 25, "Bob");
+            t.put_datum(&users, b"alice", &alice).await?;
+            t.put_datum(&users, b"bob", &bob).await?;
+            assert_eq!(t.get_datum::<User>(&users, b"bob").await?, Some(bob));
+
+            let email = String::from("alice@example.org").to_key();
+            let by_email = t
+                .query(&users, User::INDEX_EMAIL, &BTreeQuery::equal(&email))?
+                .try_collect::<Vec<_>>()
+                .await?;
+            assert_eq!(by_email.len(), 1);
+            assert_eq!(by_email[0].1, alice.to_bytes()?);
+
+            let young = 26u8.to_key();
+            let by_age = t
+                .query(
+                    &users,
+                    User::INDEX_AGE,
+                    &BTreeQuery::range(..young.as_slice()),
+                )?
+                .try_collect::<Vec<_>>()
+                .await?;
+            assert_eq!(by_age.len(), 1);
+            assert_eq!(
+                User::from_slice(&by_age[0].1)?,
+                User::new("bob@example.org", 25, "Bob")
+            );
+            Ok(())
+        })
+    })
+    .await
+    .unwrap();
+}
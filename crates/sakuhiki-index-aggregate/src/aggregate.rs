@@ -0,0 +1,70 @@
+use eyre::eyre;
+
+/// Length of an encoded `Aggregate`
+pub(crate) const ENCODED_LEN: usize = 40;
+
+/// Aggregated values of all the datums of a group
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct Aggregate {
+    pub count: u64,
+    pub sum: i128,
+    pub min: i64,
+    pub max: i64,
+}
+
+impl Aggregate {
+    pub(crate) fn single(value: i64) -> Self {
+        Self {
+            count: 1,
+            sum: i128::from(value),
+            min: value,
+            max: value,
+        }
+    }
+
+    /// Merges the datums of `other` into this aggregate, eg. to aggregate a range of groups
+    pub fn merge(&mut self, other: &Aggregate) {
+        self.count += other.count;
+        self.sum += other.sum;
+        self.min = self.min.min(other.min);
+        self.max = self.max.max(other.max);
+    }
+
+    /// Mean of the values, `None` if there are none
+    pub fn mean(&self) -> Option<f64> {
+        (self.count != 0).then(|| self.sum as f64 / self.count as f64)
+    }
+
+    pub(crate) fn encode(&self) -> [u8; ENCODED_LEN] {
+        let mut encoded = [0; ENCODED_LEN];
+        encoded[..8].copy_from_slice(&self.count.to_be_bytes());
+        encoded[8..24].copy_from_slice(&self.sum.to_be_bytes());
+        encoded[24..32].copy_from_slice(&self.min.to_be_bytes());
+        encoded[32..].copy_from_slice(&self.max.to_be_bytes());
+        encoded
+    }
+
+    /// Decodes an aggregate, as returned in the values of `AggregateIndex` queries
+    pub fn decode(encoded: &[u8]) -> eyre::Result<Self> {
+        if encoded.len() != ENCODED_LEN {
+            return Err(eyre!("Aggregate has invalid length {}", encoded.len()));
+        }
+        Ok(Self {
+            count: u64::from_be_bytes(encoded[..8].try_into().unwrap()),
+            sum: i128::from_be_bytes(encoded[8..24].try_into().unwrap()),
+            min: i64::from_be_bytes(encoded[24..32].try_into().unwrap()),
+            max: i64::from_be_bytes(encoded[32..].try_into().unwrap()),
+        })
+    }
+}
+
+/// Encodes `value` so that the encodings sort like the values
+pub(crate) fn encode_value(value: i64) -> [u8; 8] {
+    (value as u64 ^ (1 << 63)).to_be_bytes()
+}
+
+pub(crate) fn decode_value(encoded: &[u8]) -> eyre::Result<i64> {
+    let encoded = <[u8; 8]>::try_from(encoded)
+        .map_err(|_| eyre!("Expected an 8-byte value, got {encoded:?}"))?;
+    Ok((u64::from_be_bytes(encoded) ^ (1 << 63)) as i64)
+}
@@ -0,0 +1,448 @@
+use eyre::{WrapErr as _, eyre};
+use futures_util::{StreamExt as _, TryStreamExt as _, future};
+use sakuhiki_core::{
+    Backend, CfOperationError, Datum as _, Index, Indexer, Transaction, TransactionCf,
+    backend::{BackendCf as _, Transaction as _},
+    indexer,
+};
+use sakuhiki_index_btree::{Key, escape};
+
+use crate::{
+    Aggregate, AggregateQuery,
+    aggregate::{decode_value, encode_value},
+};
+
+pub type ValueExtractor<D> = fn(&D) -> i64;
+
+/// Length of the value part of the keys of the values CF
+const VALUE_LEN: usize = 8;
+
+/// Index maintaining the count, sum, min and max of a value over each group of datums
+///
+/// The first CF maps each group key, as extracted by `K`, to its encoded `Aggregate`. The second
+/// CF stores the escaped group key, followed by the sortable value and the object key, so that
+/// min and max can be recomputed when the datum holding them goes away.
+pub struct AggregateIndex<K>
+where
+    K: Key,
+{
+    cfs: &'static [&'static str; 2],
+    group: K,
+    value: ValueExtractor<K::Datum>,
+    version: u64,
+}
+
+impl<K> AggregateIndex<K>
+where
+    K: Key,
+{
+    /// `group` extracts the group of each datum, and `value` the value to aggregate over it
+    pub const fn new(
+        cfs: &'static [&'static str; 2],
+        group: K,
+        value: ValueExtractor<K::Datum>,
+    ) -> Self {
+        Self::new_versioned(cfs, group, value, 0)
+    }
+
+    /// Same as `new`, but with a version for the extraction, which `new` sets to 0
+    ///
+    /// The version must be bumped whenever `group` or `value` changes what it extracts, so that
+    /// the index gets rebuilt upon building the `Db`.
+    pub const fn new_versioned(
+        cfs: &'static [&'static str; 2],
+        group: K,
+        value: ValueExtractor<K::Datum>,
+        version: u64,
+    ) -> Self {
+        Self {
+            cfs,
+            group,
+            value,
+            version,
+        }
+    }
+
+    /// Aggregate of all the groups matching `query`, or `None` if there are none
+    pub async fn aggregate<'op, 't, B>(
+        &'static self,
+        transaction: &'op Transaction<'t, B>,
+        cf: &'op TransactionCf<'t, B>,
+        query: &'op AggregateQuery<'op>,
+    ) -> eyre::Result<Option<Aggregate>>
+    where
+        B: Backend,
+    {
+        transaction
+            .query_keys(cf, self, query)?
+            .try_fold(None, |total: Option<Aggregate>, group| {
+                future::ready(Ok(Some(match total {
+                    Some(mut total) => {
+                        total.merge(&group.aggregate);
+                        total
+                    }
+                    None => group.aggregate,
+                })))
+            })
+            .await
+    }
+
+    fn entry(&self, datum: &K::Datum) -> Option<(Vec<u8>, i64)> {
+        let mut group = Vec::with_capacity(self.group.len_hint(datum));
+        self.group
+            .extract_key(datum, &mut group)
+            .then(|| (group, (self.value)(datum)))
+    }
+
+    fn entry_from_slice(&self, slice: &[u8]) -> eyre::Result<Option<(Vec<u8>, i64)>> {
+        let datum = K::Datum::from_slice(slice).wrap_err("Failed to parse datum")?;
+        Ok(self.entry(&datum))
+    }
+}
+
+fn values_key(group: &[u8], value: i64, object_key: &[u8]) -> Vec<u8> {
+    let mut key = escape(group);
+    key.extend_from_slice(&encode_value(value));
+    key.extend_from_slice(object_key);
+    key
+}
+
+async fn get_aggregate<'t, B>(
+    group: &[u8],
+    transaction: &B::Transaction<'t>,
+    cf: &B::TransactionCf<'t>,
+) -> eyre::Result<Option<Aggregate>>
+where
+    B: Backend,
+{
+    let aggregate = transaction
+        .get(cf, group)
+        .await
+        .wrap_err_with(|| CfOperationError::new("Failed getting aggregate from", cf.name()))?;
+    aggregate
+        .map(|a| Aggregate::decode(a.as_ref()))
+        .transpose()
+        .wrap_err("Failed decoding aggregate")
+}
+
+async fn put_aggregate<'t, B>(
+    group: &[u8],
+    aggregate: &Aggregate,
+    transaction: &B::Transaction<'t>,
+    cf: &B::TransactionCf<'t>,
+) -> eyre::Result<()>
+where
+    B: Backend,
+{
+    transaction
+        .put(cf, group, &aggregate.encode())
+        .await
+        .wrap_err_with(|| CfOperationError::new("Failed putting aggregate into", cf.name()))?;
+    Ok(())
+}
+
+/// Returns the smallest (or largest if `rev`) value still stored for the group with `prefix`
+async fn extremum<'t, B>(
+    prefix: &[u8],
+    rev: bool,
+    transaction: &B::Transaction<'t>,
+    cf: &B::TransactionCf<'t>,
+) -> eyre::Result<i64>
+where
+    B: Backend,
+{
+    let mut values = match rev {
+        false => transaction.scan_prefix(cf, prefix),
+        true => transaction.scan_prefix_rev(cf, prefix),
+    };
+    let (key, _) = values
+        .next()
+        .await
+        .ok_or_else(|| eyre!("Group has a non-zero count but no values"))?
+        .wrap_err_with(|| CfOperationError::new("Failed scanning", cf.name()))?;
+    decode_value(&key.as_ref()[prefix.len()..prefix.len() + VALUE_LEN])
+}
+
+async fn add_entry<'t, B>(
+    (group, value): (Vec<u8>, i64),
+    object_key: &[u8],
+    transaction: &B::Transaction<'t>,
+    cfs: &[B::TransactionCf<'t>],
+) -> eyre::Result<()>
+where
+    B: Backend,
+{
+    transaction
+        .put(&cfs[1], &values_key(&group, value, object_key), &[])
+        .await
+        .wrap_err_with(|| CfOperationError::new("Failed putting value into", cfs[1].name()))?;
+    let aggregate = match get_aggregate::<B>(&group, transaction, &cfs[0]).await? {
+        Some(mut aggregate) => {
+            aggregate.merge(&Aggregate::single(value));
+            aggregate
+        }
+        None => Aggregate::single(value),
+    };
+    put_aggregate::<B>(&group, &aggregate, transaction, &cfs[0]).await
+}
+
+async fn remove_entry<'t, B>(
+    (group, value): (Vec<u8>, i64),
+    object_key: &[u8],
+    transaction: &B::Transaction<'t>,
+    cfs: &[B::TransactionCf<'t>],
+) -> eyre::Result<()>
+where
+    B: Backend,
+{
+    transaction
+        .delete(&cfs[1], &values_key(&group, value, object_key))
+        .await
+        .wrap_err_with(|| CfOperationError::new("Failed deleting value from", cfs[1].name()))?;
+    let mut aggregate = get_aggregate::<B>(&group, transaction, &cfs[0])
+        .await?
+        .ok_or_else(|| eyre!("Unindexing datum from a group that has no aggregate"))?;
+    aggregate.count -= 1;
+    if aggregate.count == 0 {
+        transaction
+            .delete(&cfs[0], &group)
+            .await
+            .wrap_err_with(|| {
+                CfOperationError::new("Failed deleting aggregate from", cfs[0].name())
+            })?;
+        return Ok(());
+    }
+    aggregate.sum -= i128::from(value);
+    let prefix = escape(&group);
+    if value == aggregate.min {
+        aggregate.min = extremum::<B>(&prefix, false, transaction, &cfs[1]).await?;
+    }
+    if value == aggregate.max {
+        aggregate.max = extremum::<B>(&prefix, true, transaction, &cfs[1]).await?;
+    }
+    put_aggregate::<B>(&group, &aggregate, transaction, &cfs[0]).await
+}
+
+#[warn(clippy::missing_trait_methods)]
+impl<B, K> Indexer<B> for AggregateIndex<K>
+where
+    B: Backend,
+    K: Key,
+{
+    type Datum = K::Datum;
+
+    fn cfs(&self) -> &'static [&'static str] {
+        self.cfs
+    }
+
+    fn foreign_cfs(&self) -> Vec<&'static str> {
+        Vec::new()
+    }
+
+    fn fingerprint(&self) -> Vec<u8> {
+        format!(
+            "aggregate/{}/v{}",
+            self.group.key_fingerprint(),
+            self.version
+        )
+        .into_bytes()
+    }
+
+    fn check_write<'fut, 't>(
+        &'fut self,
+        _object_key: &'fut [u8],
+        _new: Option<&'fut Self::Datum>,
+        _transaction: &'fut B::Transaction<'t>,
+        _cfs: &'fut [B::TransactionCf<'t>],
+    ) -> waaa::BoxFuture<'fut, eyre::Result<()>> {
+        Box::pin(async { Ok(()) })
+    }
+
+    fn index<'fut, 't>(
+        &'fut self,
+        object_key: &'fut [u8],
+        datum: &'fut Self::Datum,
+        transaction: &'fut B::Transaction<'t>,
+        cfs: &'fut [B::TransactionCf<'t>],
+    ) -> waaa::BoxFuture<'fut, eyre::Result<()>> {
+        Box::pin(async move {
+            if let Some(entry) = self.entry(datum) {
+                add_entry::<B>(entry, object_key, transaction, cfs).await?;
+            }
+            Ok(())
+        })
+    }
+
+    fn unindex<'fut, 't>(
+        &'fut self,
+        object_key: &'fut [u8],
+        datum: &'fut Self::Datum,
+        transaction: &'fut B::Transaction<'t>,
+        cfs: &'fut [B::TransactionCf<'t>],
+    ) -> waaa::BoxFuture<'fut, eyre::Result<()>> {
+        Box::pin(async move {
+            if let Some(entry) = self.entry(datum) {
+                remove_entry::<B>(entry, object_key, transaction, cfs).await?;
+            }
+            Ok(())
+        })
+    }
+
+    fn reindex<'fut, 't>(
+        &'fut self,
+        object_key: &'fut [u8],
+        old: &'fut Self::Datum,
+        new: &'fut Self::Datum,
+        transaction: &'fut B::Transaction<'t>,
+        cfs: &'fut [B::TransactionCf<'t>],
+    ) -> waaa::BoxFuture<'fut, eyre::Result<()>> {
+        Box::pin(async move {
+            let old = self.entry(old);
+            let new = self.entry(new);
+            if old != new {
+                if let Some(old) = old {
+                    remove_entry::<B>(old, object_key, transaction, cfs).await?;
+                }
+                if let Some(new) = new {
+                    add_entry::<B>(new, object_key, transaction, cfs).await?;
+                }
+            }
+            Ok(())
+        })
+    }
+
+    fn index_from_slice<'fut, 't>(
+        &'fut self,
+        object_key: &'fut [u8],
+        slice: &'fut [u8],
+        transaction: &'fut B::Transaction<'t>,
+        cfs: &'fut [B::TransactionCf<'t>],
+    ) -> waaa::BoxFuture<'fut, eyre::Result<()>> {
+        Box::pin(async move {
+            let entry = self
+                .entry_from_slice(slice)
+                .wrap_err("Failed extracting group from slice")?;
+            if let Some(entry) = entry {
+                add_entry::<B>(entry, object_key, transaction, cfs).await?;
+            }
+            Ok(())
+        })
+    }
+
+    fn unindex_from_slice<'fut, 't>(
+        &'fut self,
+        object_key: &'fut [u8],
+        slice: &'fut [u8],
+        transaction: &'fut B::Transaction<'t>,
+        cfs: &'fut [B::TransactionCf<'t>],
+    ) -> waaa::BoxFuture<'fut, eyre::Result<()>> {
+        Box::pin(async move {
+            let entry = self
+                .entry_from_slice(slice)
+                .wrap_err("Failed extracting group from slice")?;
+            if let Some(entry) = entry {
+                remove_entry::<B>(entry, object_key, transaction, cfs).await?;
+            }
+            Ok(())
+        })
+    }
+
+    fn rebuild<'fut, 't>(
+        &'fut self,
+        transaction: &'fut B::Transaction<'t>,
+        index_cfs: &'fut [B::TransactionCf<'t>],
+        datum_cf: &'fut B::TransactionCf<'t>,
+    ) -> waaa::BoxFuture<'fut, eyre::Result<()>> {
+        Box::pin(async move {
+            indexer::default_rebuild::<B, Self>(self, transaction, index_cfs, datum_cf).await
+        })
+    }
+}
+
+/// One group matching an `AggregateQuery`, alongside its aggregate
+pub struct AggregateGroup<'k, B>
+where
+    B: Backend,
+{
+    group: B::Key<'k>,
+    aggregate: Aggregate,
+}
+
+impl<B> AggregateGroup<'_, B>
+where
+    B: Backend,
+{
+    pub fn aggregate(&self) -> &Aggregate {
+        &self.aggregate
+    }
+}
+
+impl<B> AsRef<[u8]> for AggregateGroup<'_, B>
+where
+    B: Backend,
+{
+    fn as_ref(&self) -> &[u8] {
+        self.group.as_ref()
+    }
+}
+
+/// Querying an `AggregateIndex` returns one result per group rather than per object
+///
+/// The values of `query` are the encoded aggregates, that `Aggregate::decode` can parse.
+#[warn(clippy::missing_trait_methods)]
+impl<B, K> Index<B> for AggregateIndex<K>
+where
+    B: Backend,
+    K: Key,
+{
+    type Query<'q> = AggregateQuery<'q>;
+    type QueryKey<'k> = AggregateGroup<'k, B>;
+
+    fn query<'q, 'op: 'q, 't: 'op>(
+        &'q self,
+        query: &'q Self::Query<'q>,
+        transaction: &'op B::Transaction<'t>,
+        _object_cf: &'op B::TransactionCf<'t>,
+        cfs: &'op [B::TransactionCf<'t>],
+    ) -> waaa::BoxStream<'q, eyre::Result<(Self::QueryKey<'op>, B::Value<'op>)>> {
+        Box::pin(
+            transaction
+                .scan::<[u8]>(&cfs[0], (query.start, query.end))
+                .map(|res| {
+                    let (group, value) = res.wrap_err_with(|| {
+                        CfOperationError::new("Failed scanning", cfs[0].name())
+                    })?;
+                    let aggregate =
+                        Aggregate::decode(value.as_ref()).wrap_err("Failed decoding aggregate")?;
+                    Ok((AggregateGroup { group, aggregate }, value))
+                }),
+        )
+    }
+
+    fn query_keys<'q, 'op: 'q, 't: 'op>(
+        &'q self,
+        query: &'q Self::Query<'q>,
+        transaction: &'op B::Transaction<'t>,
+        object_cf: &'op B::TransactionCf<'t>,
+        cfs: &'op [B::TransactionCf<'t>],
+    ) -> waaa::BoxStream<'q, eyre::Result<Self::QueryKey<'op>>> {
+        Box::pin(
+            self.query(query, transaction, object_cf, cfs)
+                .map_ok(|(group, _)| group),
+        )
+    }
+
+    fn count<'q, 'op: 'q, 't: 'op>(
+        &'q self,
+        query: &'q Self::Query<'q>,
+        transaction: &'op B::Transaction<'t>,
+        _object_cf: &'op B::TransactionCf<'t>,
+        cfs: &'op [B::TransactionCf<'t>],
+    ) -> waaa::BoxFuture<'q, eyre::Result<usize>> {
+        Box::pin(
+            transaction
+                .scan::<[u8]>(&cfs[0], (query.start, query.end))
+                .try_fold(0, |count, _| future::ready(Ok(count + 1))),
+        )
+    }
+}
@@ -0,0 +1,11 @@
+mod aggregate;
+pub use aggregate::Aggregate;
+
+mod index;
+pub use index::{AggregateGroup, AggregateIndex, ValueExtractor};
+
+mod query;
+pub use query::AggregateQuery;
+
+#[cfg(test)]
+mod tests;
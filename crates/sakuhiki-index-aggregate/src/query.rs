@@ -0,0 +1,28 @@
+use std::ops::{Bound, RangeBounds};
+
+/// Query on an `AggregateIndex`, for the groups whose key is in a range
+///
+/// Group keys are the keys extracted by the `Key` of the index, eg. encoded with
+/// `KeyField::to_key` for a `FieldKey`.
+pub struct AggregateQuery<'q> {
+    pub(crate) start: Bound<&'q [u8]>,
+    pub(crate) end: Bound<&'q [u8]>,
+}
+
+impl<'q> AggregateQuery<'q> {
+    /// Matches only the group `group`
+    pub fn group(group: &'q [u8]) -> Self {
+        Self {
+            start: Bound::Included(group),
+            end: Bound::Included(group),
+        }
+    }
+
+    /// Matches all the groups whose key is in `range`
+    pub fn range(range: impl RangeBounds<&'q [u8]>) -> Self {
+        Self {
+            start: range.start_bound().cloned(),
+            end: range.end_bound().cloned(),
+        }
+    }
+}
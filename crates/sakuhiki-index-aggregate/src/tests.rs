@@ -0,0 +1,169 @@
+use futures_util::TryStreamExt as _;
+use sakuhiki_core::{Backend, Indexer, Mode};
+use sakuhiki_index_btree::{FieldKey, KeyField as _};
+
+use crate::*;
+
+struct Sale {
+    shop: u32,
+    amount: i64,
+}
+
+impl sakuhiki_core::Datum for Sale {
+    const CF: &'static str = "sale";
+
+    fn from_slice(datum: &[u8]) -> eyre::Result<Self> {
+        Ok(Self {
+            shop: u32::from_be_bytes(datum[..4].try_into()?),
+            amount: i64::from_be_bytes(datum[4..].try_into()?),
+        })
+    }
+
+    fn to_bytes(&self) -> eyre::Result<Vec<u8>> {
+        Ok([&self.shop.to_be_bytes()[..], &self.amount.to_be_bytes()].concat())
+    }
+}
+
+impl Sale {
+    const INDEX: &'static AggregateIndex<FieldKey<Sale, u32>> = &AggregateIndex::new(
+        &["sale-shop-aggregate", "sale-shop-values"],
+        FieldKey::new(|s| &s.shop),
+        |s| s.amount,
+    );
+}
+
+impl<B: Backend> sakuhiki_core::IndexedDatum<B> for Sale {
+    const INDEXES: &'static [&'static dyn Indexer<B, Datum = Self>] = &[Self::INDEX];
+}
+
+fn agg(count: u64, sum: i128, min: i64, max: i64) -> Option<Aggregate> {
+    Some(Aggregate {
+        count,
+        sum,
+        min,
+        max,
+    })
+}
+
+#[test]
+fn test_aggregate_encoding() {
+    let aggregate = agg(3, -12, i64::MIN, 42).unwrap();
+    assert_eq!(Aggregate::decode(&aggregate.encode()).unwrap(), aggregate);
+    assert!(Aggregate::decode(&[0; 3]).is_err());
+    let values = [i64::MIN, -1, 0, 1, i64::MAX].map(aggregate::encode_value);
+    assert!(values.windows(2).all(|w| w[0] < w[1]));
+    assert_eq!(aggregate::decode_value(&values[1]).unwrap(), -1);
+    assert_eq!(agg(4, 10, 0, 5).unwrap().mean(), Some(2.5));
+}
+
+#[tokio::test]
+async fn test_aggregate_index() {
+    let db = sakuhiki_memdb::MemDb::builder()
+        .datum::<Sale>()
+        .build()
+        .await
+        .unwrap();
+    let sale = db.cf_handle::<Sale>().await.unwrap();
+    db.transaction(Mode::ReadWrite, &[&sale], |t, [sale]| {
+        Box::pin(async move {
+            for (key, shop, amount) in [
+                (b"s1", 1, 10),
+                (b"s2", 1, 30),
+                (b"s3", 1, -5),
+                (b"s4", 2, 7),
+                (b"s5", 3, 100),
+                (b"s6", 3, 100),
+            ] {
+                t.put_datum(&sale, key, &Sale { shop, amount }).await?;
+            }
+            let (one, two, three) = (1u32.to_key(), 2u32.to_key(), 3u32.to_key());
+            let group = async |group: &[u8]| {
+                let query = AggregateQuery::group(group);
+                Sale::INDEX.aggregate(&t, &sale, &query).await.unwrap()
+            };
+            assert_eq!(group(&one).await, agg(3, 35, -5, 30));
+            assert_eq!(group(&two).await, agg(1, 7, 7, 7));
+            assert_eq!(group(&4u32.to_key()).await, None);
+            // Ranges of groups are merged into a single aggregate, or listed one result per group
+            let query = AggregateQuery::range(&one[..]..=&two[..]);
+            assert_eq!(
+                Sale::INDEX.aggregate(&t, &sale, &query).await?,
+                agg(4, 42, -5, 30)
+            );
+            let groups = t
+                .query_keys(&sale, Sale::INDEX, &AggregateQuery::range(..))?
+                .map_ok(|g| (g.as_ref().to_vec(), g.aggregate().count))
+                .try_collect::<Vec<_>>()
+                .await?;
+            assert_eq!(
+                groups,
+                [(one.clone(), 3), (two.clone(), 1), (three.clone(), 2)]
+            );
+            assert_eq!(
+                t.count(&sale, Sale::INDEX, &AggregateQuery::range(&two[..]..))
+                    .await?,
+                2
+            );
+            let (_, value) = t
+                .query(&sale, Sale::INDEX, &AggregateQuery::group(&two))?
+                .try_next()
+                .await?
+                .unwrap();
+            assert_eq!(Aggregate::decode(value.as_ref())?, agg(1, 7, 7, 7).unwrap());
+            // Removing the min or max recomputes it
+            t.delete::<Sale>(&sale, b"s3").await?;
+            assert_eq!(group(&one).await, agg(2, 40, 10, 30));
+            t.put_datum(
+                &sale,
+                b"s2",
+                &Sale {
+                    shop: 1,
+                    amount: 20,
+                },
+            )
+            .await?;
+            assert_eq!(group(&one).await, agg(2, 30, 10, 20));
+            // Duplicate values are kept track of
+            t.delete::<Sale>(&sale, b"s5").await?;
+            assert_eq!(group(&three).await, agg(1, 100, 100, 100));
+            // Moving a datum to another group updates both groups
+            t.put_datum(
+                &sale,
+                b"s1",
+                &Sale {
+                    shop: 2,
+                    amount: 10,
+                },
+            )
+            .await?;
+            assert_eq!(group(&one).await, agg(1, 20, 20, 20));
+            assert_eq!(group(&two).await, agg(2, 17, 7, 10));
+            // Emptied groups disappear
+            t.delete::<Sale>(&sale, b"s2").await?;
+            assert_eq!(group(&one).await, None);
+            assert_eq!(
+                t.count(&sale, Sale::INDEX, &AggregateQuery::range(..))
+                    .await?,
+                2
+            );
+            Ok(())
+        })
+    })
+    .await
+    .unwrap();
+    // Rebuilding recomputes the same aggregates
+    db.rebuild_index(Sale::INDEX).await.unwrap();
+    let sale = db.cf_handle::<Sale>().await.unwrap();
+    db.transaction(Mode::ReadOnly, &[&sale], |t, [sale]| {
+        Box::pin(async move {
+            let query = AggregateQuery::range(..);
+            assert_eq!(
+                Sale::INDEX.aggregate(&t, &sale, &query).await?,
+                agg(3, 117, 7, 100)
+            );
+            Ok(())
+        })
+    })
+    .await
+    .unwrap();
+}
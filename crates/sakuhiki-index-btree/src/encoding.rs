@@ -0,0 +1,186 @@
+//! Order-preserving encodings of the usual types, as `KeyField` implementations
+//!
+//! For all of these types, the encodings sort the same way as the values themselves, so they can
+//! be used both for indexing and for encoding query bounds with `KeyField::to_key`.
+
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use crate::{
+    KeyField,
+    escaped::{escape_into, escaped_len},
+};
+
+macro_rules! unsigned_key_field {
+    ($($t:ty),*) => {
+        $(
+            impl KeyField for $t {
+                const ENCODING: &'static str = stringify!($t);
+
+                fn len_hint(&self) -> usize {
+                    size_of::<$t>()
+                }
+
+                fn encode(&self, key: &mut Vec<u8>) {
+                    key.extend_from_slice(&self.to_be_bytes());
+                }
+
+                fn encoded_len(in_slice: &[u8]) -> usize {
+                    debug_assert!(in_slice.len() >= size_of::<$t>());
+                    size_of::<$t>()
+                }
+            }
+        )*
+    };
+}
+
+unsigned_key_field!(u8, u16, u32, u64, u128);
+
+/// Signed integers are encoded big-endian with the sign bit flipped, so negative numbers sort
+/// first.
+macro_rules! signed_key_field {
+    ($($t:ty => $u:ty),*) => {
+        $(
+            impl KeyField for $t {
+                const ENCODING: &'static str = stringify!($t);
+
+                fn len_hint(&self) -> usize {
+                    size_of::<$t>()
+                }
+
+                fn encode(&self, key: &mut Vec<u8>) {
+                    let flipped = (*self as $u) ^ (1 << (<$u>::BITS - 1));
+                    key.extend_from_slice(&flipped.to_be_bytes());
+                }
+
+                fn encoded_len(in_slice: &[u8]) -> usize {
+                    debug_assert!(in_slice.len() >= size_of::<$t>());
+                    size_of::<$t>()
+                }
+            }
+        )*
+    };
+}
+
+signed_key_field!(i8 => u8, i16 => u16, i32 => u32, i64 => u64, i128 => u128);
+
+/// Floats are encoded following `total_cmp`: negative numbers have all their bits flipped, and
+/// positive numbers only their sign bit.
+macro_rules! float_key_field {
+    ($($t:ty => $u:ty),*) => {
+        $(
+            impl KeyField for $t {
+                const ENCODING: &'static str = stringify!($t);
+
+                fn len_hint(&self) -> usize {
+                    size_of::<$t>()
+                }
+
+                fn encode(&self, key: &mut Vec<u8>) {
+                    let bits = self.to_bits();
+                    let sign = 1 << (<$u>::BITS - 1);
+                    let flipped = if bits & sign != 0 { !bits } else { bits ^ sign };
+                    key.extend_from_slice(&flipped.to_be_bytes());
+                }
+
+                fn encoded_len(in_slice: &[u8]) -> usize {
+                    debug_assert!(in_slice.len() >= size_of::<$t>());
+                    size_of::<$t>()
+                }
+            }
+        )*
+    };
+}
+
+float_key_field!(f32 => u32, f64 => u64);
+
+impl KeyField for bool {
+    const ENCODING: &'static str = "bool";
+
+    fn len_hint(&self) -> usize {
+        1
+    }
+
+    fn encode(&self, key: &mut Vec<u8>) {
+        key.push(u8::from(*self));
+    }
+
+    fn encoded_len(in_slice: &[u8]) -> usize {
+        debug_assert!(!in_slice.is_empty());
+        1
+    }
+}
+
+/// Timestamps are encoded as the signed number of nanoseconds since the UNIX epoch.
+impl KeyField for SystemTime {
+    const ENCODING: &'static str = "system-time";
+
+    fn len_hint(&self) -> usize {
+        size_of::<i128>()
+    }
+
+    fn encode(&self, key: &mut Vec<u8>) {
+        let nanos = match self.duration_since(UNIX_EPOCH) {
+            Ok(after) => after.as_nanos() as i128,
+            Err(before) => -(before.duration().as_nanos() as i128),
+        };
+        nanos.encode(key);
+    }
+
+    fn encoded_len(in_slice: &[u8]) -> usize {
+        i128::encoded_len(in_slice)
+    }
+}
+
+/// Durations are encoded as their number of nanoseconds.
+impl KeyField for Duration {
+    const ENCODING: &'static str = "duration";
+
+    fn len_hint(&self) -> usize {
+        size_of::<u128>()
+    }
+
+    fn encode(&self, key: &mut Vec<u8>) {
+        self.as_nanos().encode(key);
+    }
+
+    fn encoded_len(in_slice: &[u8]) -> usize {
+        u128::encoded_len(in_slice)
+    }
+}
+
+/// UUIDs are encoded as their 16 bytes, so that eg. v7 UUIDs sort by creation time.
+#[cfg(feature = "uuid")]
+impl KeyField for uuid::Uuid {
+    const ENCODING: &'static str = "uuid";
+
+    fn len_hint(&self) -> usize {
+        16
+    }
+
+    fn encode(&self, key: &mut Vec<u8>) {
+        key.extend_from_slice(self.as_bytes());
+    }
+
+    fn encoded_len(in_slice: &[u8]) -> usize {
+        debug_assert!(in_slice.len() >= 16);
+        16
+    }
+}
+
+/// Strings are encoded the same way as `EscapedKey`, so shorter strings collate as smaller than
+/// longer strings they are a prefix of.
+impl KeyField for String {
+    const ENCODING: &'static str = "string";
+
+    fn len_hint(&self) -> usize {
+        self.len() + 1
+    }
+
+    fn encode(&self, key: &mut Vec<u8>) {
+        escape_into(self.as_bytes(), key);
+    }
+
+    fn encoded_len(in_slice: &[u8]) -> usize {
+        escaped_len(in_slice)
+    }
+}
@@ -0,0 +1,116 @@
+use sakuhiki_core::Datum;
+
+use crate::Key;
+
+pub type EscapedKeyExtractor<D> = for<'d> fn(&'d D) -> Option<&'d [u8]>;
+pub type EscapedKeyExtractorFromSlice = fn(&[u8]) -> eyre::Result<Option<&[u8]>>;
+
+/// Variable-length key, encoded with a 0 terminator, `0` being escaped as `1 0` and `1` as `1 1`.
+///
+/// This means that shorter keys collate as smaller than longer keys they are a prefix of.
+pub struct EscapedKey<D>
+where
+    D: Datum,
+{
+    extractor: EscapedKeyExtractor<D>,
+    extractor_from_slice: Option<EscapedKeyExtractorFromSlice>,
+}
+
+impl<D> EscapedKey<D>
+where
+    D: Datum,
+{
+    /// `extractor` returns the unescaped key of `&D`, or `None` if it must not be indexed
+    pub const fn new(
+        extractor: EscapedKeyExtractor<D>,
+        extractor_from_slice: Option<EscapedKeyExtractorFromSlice>,
+    ) -> Self {
+        Self {
+            extractor,
+            extractor_from_slice,
+        }
+    }
+}
+
+/// Appends the escaped form of `raw` to `key`
+pub(crate) fn escape_into(raw: &[u8], key: &mut Vec<u8>) {
+    for &b in raw {
+        match b {
+            0 | 1 => key.extend_from_slice(&[1, b]),
+            _ => key.push(b),
+        }
+    }
+    key.push(0);
+}
+
+/// Returns the escaped form of `raw`, eg. to build a `BTreeQuery` on an `EscapedKey`
+pub fn escape(raw: &[u8]) -> Vec<u8> {
+    let mut key = Vec::with_capacity(raw.len() + 1);
+    escape_into(raw, &mut key);
+    key
+}
+
+/// Returns the length of the escaped key that is a prefix of `in_slice`
+pub(crate) fn escaped_len(in_slice: &[u8]) -> usize {
+    let mut i = 0;
+    loop {
+        match in_slice[i] {
+            0 => return i + 1,
+            1 => i += 2,
+            _ => i += 1,
+        }
+    }
+}
+
+#[warn(clippy::missing_trait_methods)]
+impl<D> Key for EscapedKey<D>
+where
+    D: Datum,
+{
+    type Datum = D;
+
+    fn len_hint(&self, datum: &D) -> usize {
+        (self.extractor)(datum).map_or(0, |raw| raw.len() + 1)
+    }
+
+    fn extract_key(&self, datum: &D, key: &mut Vec<u8>) -> bool {
+        match (self.extractor)(datum) {
+            Some(raw) => {
+                escape_into(raw, key);
+                true
+            }
+            None => false,
+        }
+    }
+
+    fn len_hint_from_slice(&self, slice: &[u8]) -> eyre::Result<usize> {
+        if let Some(extractor_from_slice) = self.extractor_from_slice {
+            Ok((extractor_from_slice)(slice)?.map_or(0, |raw| raw.len() + 1))
+        } else {
+            Ok(self.len_hint(&D::from_slice(slice)?))
+        }
+    }
+
+    fn extract_key_from_slice(&self, slice: &[u8], key: &mut Vec<u8>) -> eyre::Result<bool> {
+        if let Some(extractor_from_slice) = self.extractor_from_slice {
+            match (extractor_from_slice)(slice)? {
+                Some(raw) => {
+                    escape_into(raw, key);
+                    Ok(true)
+                }
+                None => Ok(false),
+            }
+        } else {
+            let datum = D::from_slice(slice)?;
+            Ok(self.extract_key(&datum, key))
+        }
+    }
+
+    fn key_len(&self, in_slice: &[u8]) -> usize {
+        escaped_len(in_slice)
+    }
+
+    fn key_fingerprint(&self) -> String {
+        String::from("escaped")
+    }
+}
@@ -0,0 +1,76 @@
+use eyre::WrapErr as _;
+use sakuhiki_core::Datum;
+
+use crate::Key;
+
+/// A value that can be encoded as a BTree index key, in an order-preserving way.
+pub trait KeyField: 'static + Send + Sync {
+    /// Name of this encoding, eg. `u32`, included in the fingerprint of the indexes using it.
+    ///
+    /// It must change whenever the encoding changes, and be stable across builds otherwise.
+    const ENCODING: &'static str;
+
+    /// Hint about the length of the encoded value.
+    fn len_hint(&self) -> usize;
+
+    /// Appends the encoded value to `key`.
+    fn encode(&self, key: &mut Vec<u8>);
+
+    /// Returns the length of the encoded value that is a prefix of `in_slice`.
+    fn encoded_len(in_slice: &[u8]) -> usize;
+
+    /// Returns the encoded value, eg. to build a `BTreeQuery`.
+    fn to_key(&self) -> Vec<u8> {
+        let mut key = Vec::with_capacity(self.len_hint());
+        self.encode(&mut key);
+        key
+    }
+}
+
+/// Key made of a single field of the datum.
+pub struct FieldKey<D, F> {
+    field: fn(&D) -> &F,
+}
+
+impl<D, F> FieldKey<D, F> {
+    /// `field` returns the field of `D` to index.
+    pub const fn new(field: fn(&D) -> &F) -> Self {
+        Self { field }
+    }
+}
+
+#[warn(clippy::missing_trait_methods)]
+impl<D, F> Key for FieldKey<D, F>
+where
+    D: Datum,
+    F: KeyField,
+{
+    type Datum = D;
+
+    fn len_hint(&self, datum: &D) -> usize {
+        (self.field)(datum).len_hint()
+    }
+
+    fn extract_key(&self, datum: &D, key: &mut Vec<u8>) -> bool {
+        (self.field)(datum).encode(key);
+        true
+    }
+
+    fn len_hint_from_slice(&self, slice: &[u8]) -> eyre::Result<usize> {
+        let datum = D::from_slice(slice).wrap_err("Failed to parse datum")?;
+        Ok(self.len_hint(&datum))
+    }
+
+    fn extract_key_from_slice(&self, slice: &[u8], key: &mut Vec<u8>) -> eyre::Result<bool> {
+        let datum = D::from_slice(slice).wrap_err("Failed to parse datum")?;
+        Ok(self.extract_key(&datum, key))
+    }
+
+    fn key_len(&self, in_slice: &[u8]) -> usize {
+        F::encoded_len(in_slice)
+    }
+
+    fn key_fingerprint(&self) -> String {
+        format!("field/{}", F::ENCODING)
+    }
+}
@@ -68,4 +68,8 @@ where
         debug_assert!(in_slice.len() >= self.len);
         self.len
     }
+
+    fn key_fingerprint(&self) -> String {
+        format!("fixed-len/{}", self.len)
+    }
 }
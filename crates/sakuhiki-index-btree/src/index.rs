@@ -1,29 +1,151 @@
-use eyre::WrapErr as _;
-use futures_util::StreamExt as _;
+use std::collections::BTreeSet;
+
+use eyre::{WrapErr as _, eyre};
+use futures_util::{TryStreamExt as _, future};
 use sakuhiki_core::{
-    Backend, CfOperationError, Index, Indexer,
+    Backend, CfOperationError, Datum as _, Index, Indexer,
     backend::{BackendCf as _, Transaction as _},
     indexer,
 };
 
-use crate::{BTreeQuery, Key, query::Query};
+use crate::{BTreeQuery, MultiKey};
+
+pub type Projection<D> = fn(&D, &mut Vec<u8>);
+pub type ProjectionFromSlice = fn(&[u8], &mut Vec<u8>) -> eyre::Result<()>;
 
-pub struct BTreeIndex<K> {
+pub struct BTreeIndex<K>
+where
+    K: MultiKey,
+{
     cf: &'static [&'static str; 1],
     key: K,
+    version: u64,
+    projection: Option<Projection<K::Datum>>,
+    projection_from_slice: Option<ProjectionFromSlice>,
 }
 
-impl<K> BTreeIndex<K> {
+impl<K> BTreeIndex<K>
+where
+    K: MultiKey,
+{
     pub const fn new(cf: &'static [&'static str; 1], key: K) -> Self {
-        Self { cf, key }
+        Self::new_versioned(cf, key, 0)
+    }
+
+    /// Same as `new`, but with a version for the key extraction, which `new` sets to 0
+    ///
+    /// The version must be bumped whenever `key` changes the keys it extracts, so that the index
+    /// gets rebuilt upon building the `Db`.
+    pub const fn new_versioned(cf: &'static [&'static str; 1], key: K, version: u64) -> Self {
+        Self {
+            cf,
+            key,
+            version,
+            projection: None,
+            projection_from_slice: None,
+        }
+    }
+
+    /// Same as `new_versioned`, but for a covering index, that stores the bytes `projection`
+    /// writes alongside each key
+    ///
+    /// They are then available with `BTreeQueryKey::projection`, so that key-only queries can
+    /// be served without reading the datums. As for keys, the version must be bumped whenever
+    /// `projection` changes the bytes it writes.
+    pub const fn new_covering(
+        cf: &'static [&'static str; 1],
+        key: K,
+        version: u64,
+        projection: Projection<K::Datum>,
+        projection_from_slice: Option<ProjectionFromSlice>,
+    ) -> Self {
+        Self {
+            cf,
+            key,
+            version,
+            projection: Some(projection),
+            projection_from_slice,
+        }
+    }
+
+    /// Returns the keys of the entries for `datum` in the index CF
+    fn entries(&self, object_key: &[u8], datum: &K::Datum) -> Vec<Vec<u8>> {
+        let mut keys = Vec::new();
+        self.key.extract_keys(datum, &mut keys);
+        for key in &mut keys {
+            key.extend(object_key);
+        }
+        keys
+    }
+
+    /// Returns the keys of the entries for `slice` in the index CF
+    fn entries_from_slice(&self, object_key: &[u8], slice: &[u8]) -> eyre::Result<Vec<Vec<u8>>> {
+        let mut keys = Vec::new();
+        self.key
+            .extract_keys_from_slice(slice, &mut keys)
+            .wrap_err("Failed extracting keys from slice")?;
+        for key in &mut keys {
+            key.extend(object_key);
+        }
+        Ok(keys)
+    }
+
+    fn project(&self, datum: &K::Datum) -> Vec<u8> {
+        let mut value = Vec::new();
+        if let Some(projection) = self.projection {
+            (projection)(datum, &mut value);
+        }
+        value
+    }
+
+    fn project_from_slice(&self, slice: &[u8]) -> eyre::Result<Vec<u8>> {
+        let mut value = Vec::new();
+        if let Some(projection_from_slice) = self.projection_from_slice {
+            (projection_from_slice)(slice, &mut value)?;
+        } else if let Some(projection) = self.projection {
+            let datum = K::Datum::from_slice(slice).wrap_err("Failed to parse datum")?;
+            (projection)(&datum, &mut value);
+        }
+        Ok(value)
     }
 }
 
+async fn put_entry<'t, B>(
+    key: &[u8],
+    value: &[u8],
+    transaction: &B::Transaction<'t>,
+    cf: &B::TransactionCf<'t>,
+) -> eyre::Result<()>
+where
+    B: Backend,
+{
+    transaction
+        .put(cf, key, value)
+        .await
+        .wrap_err_with(|| CfOperationError::new("Failed putting key into", cf.name()))?;
+    Ok(())
+}
+
+async fn delete_entry<'t, B>(
+    key: &[u8],
+    transaction: &B::Transaction<'t>,
+    cf: &B::TransactionCf<'t>,
+) -> eyre::Result<()>
+where
+    B: Backend,
+{
+    transaction
+        .delete(cf, key)
+        .await
+        .wrap_err_with(|| CfOperationError::new("Failed deleting key from", cf.name()))?;
+    Ok(())
+}
+
 #[warn(clippy::missing_trait_methods)]
 impl<B, K> Indexer<B> for BTreeIndex<K>
 where
     B: Backend,
-    K: Key,
+    K: MultiKey,
 {
     type Datum = K::Datum;
 
@@ -31,6 +153,34 @@ where
         self.cf
     }
 
+    fn foreign_cfs(&self) -> Vec<&'static str> {
+        Vec::new()
+    }
+
+    fn fingerprint(&self) -> Vec<u8> {
+        let kind = if self.projection.is_some() {
+            "btree-covering"
+        } else {
+            "btree"
+        };
+        format!(
+            "{kind}/{}/v{}",
+            self.key.extracted_key_fingerprint(),
+            self.version
+        )
+        .into_bytes()
+    }
+
+    fn check_write<'fut, 't>(
+        &'fut self,
+        _object_key: &'fut [u8],
+        _new: Option<&'fut Self::Datum>,
+        _transaction: &'fut B::Transaction<'t>,
+        _cfs: &'fut [B::TransactionCf<'t>],
+    ) -> waaa::BoxFuture<'fut, eyre::Result<()>> {
+        Box::pin(async { Ok(()) })
+    }
+
     fn index<'fut, 't>(
         &'fut self,
         object_key: &'fut [u8],
@@ -39,16 +189,9 @@ where
         cfs: &'fut [B::TransactionCf<'t>],
     ) -> waaa::BoxFuture<'fut, eyre::Result<()>> {
         Box::pin(async move {
-            let mut key = Vec::with_capacity(self.key.len_hint(datum) + object_key.len());
-            let do_index = self.key.extract_key(datum, &mut key);
-            if do_index {
-                key.extend(object_key);
-                transaction
-                    .put(&cfs[0], &key, &[])
-                    .await
-                    .wrap_err_with(|| {
-                        CfOperationError::new("Failed putting key into", cfs[0].name())
-                    })?;
+            let value = self.project(datum);
+            for key in self.entries(object_key, datum) {
+                put_entry::<B>(&key, &value, transaction, &cfs[0]).await?;
             }
             Ok(())
         })
@@ -62,13 +205,40 @@ where
         cfs: &'fut [B::TransactionCf<'t>],
     ) -> waaa::BoxFuture<'fut, eyre::Result<()>> {
         Box::pin(async move {
-            let mut key = Vec::with_capacity(self.key.len_hint(datum) + object_key.len());
-            let do_unindex = self.key.extract_key(datum, &mut key);
-            if do_unindex {
-                key.extend(object_key);
-                transaction.delete(&cfs[0], &key).await.wrap_err_with(|| {
-                    CfOperationError::new("Failed deleting key from", cfs[0].name())
-                })?;
+            for key in self.entries(object_key, datum) {
+                delete_entry::<B>(&key, transaction, &cfs[0]).await?;
+            }
+            Ok(())
+        })
+    }
+
+    fn reindex<'fut, 't>(
+        &'fut self,
+        object_key: &'fut [u8],
+        old: &'fut Self::Datum,
+        new: &'fut Self::Datum,
+        transaction: &'fut B::Transaction<'t>,
+        cfs: &'fut [B::TransactionCf<'t>],
+    ) -> waaa::BoxFuture<'fut, eyre::Result<()>> {
+        Box::pin(async move {
+            let old_keys = self
+                .entries(object_key, old)
+                .into_iter()
+                .collect::<BTreeSet<_>>();
+            let new_keys = self
+                .entries(object_key, new)
+                .into_iter()
+                .collect::<BTreeSet<_>>();
+            for key in old_keys.difference(&new_keys) {
+                delete_entry::<B>(key, transaction, &cfs[0]).await?;
+            }
+            // Entries that are kept only need to be rewritten if their projection changed
+            let value = self.project(new);
+            let rewrite_kept = self.projection.is_some() && self.project(old) != value;
+            for key in &new_keys {
+                if rewrite_kept || !old_keys.contains(key) {
+                    put_entry::<B>(key, &value, transaction, &cfs[0]).await?;
+                }
             }
             Ok(())
         })
@@ -82,24 +252,14 @@ where
         cfs: &'fut [B::TransactionCf<'t>],
     ) -> waaa::BoxFuture<'fut, eyre::Result<()>> {
         Box::pin(async move {
-            let mut key = Vec::with_capacity(
-                self.key
-                    .len_hint_from_slice(slice)
-                    .wrap_err("Failed estimating key length from slice")?
-                    + object_key.len(),
-            );
-            let do_index = self
-                .key
-                .extract_key_from_slice(slice, &mut key)
-                .wrap_err("Failed extracting key from slice")?;
-            if do_index {
-                key.extend(object_key);
-                transaction
-                    .put(&cfs[0], &key, &[])
-                    .await
-                    .wrap_err_with(|| {
-                        CfOperationError::new("Failed putting key into", cfs[0].name())
-                    })?;
+            let keys = self.entries_from_slice(object_key, slice)?;
+            if !keys.is_empty() {
+                let value = self
+                    .project_from_slice(slice)
+                    .wrap_err("Failed projecting slice")?;
+                for key in keys {
+                    put_entry::<B>(&key, &value, transaction, &cfs[0]).await?;
+                }
             }
             Ok(())
         })
@@ -113,21 +273,8 @@ where
         cfs: &'fut [B::TransactionCf<'t>],
     ) -> waaa::BoxFuture<'fut, eyre::Result<()>> {
         Box::pin(async move {
-            let mut key = Vec::with_capacity(
-                self.key
-                    .len_hint_from_slice(slice)
-                    .wrap_err("Failed estimating key length")?
-                    + object_key.len(),
-            );
-            let do_unindex = self
-                .key
-                .extract_key_from_slice(slice, &mut key)
-                .wrap_err("Failed extracting key from slice")?;
-            if do_unindex {
-                key.extend(object_key);
-                transaction.delete(&cfs[0], &key).await.wrap_err_with(|| {
-                    CfOperationError::new("Failed deleting key from", cfs[0].name())
-                })?;
+            for key in self.entries_from_slice(object_key, slice)? {
+                delete_entry::<B>(&key, transaction, &cfs[0]).await?;
             }
             Ok(())
         })
@@ -151,6 +298,22 @@ where
 {
     key: B::Key<'k>,
     start: usize,
+    value: B::Value<'k>,
+}
+
+impl<B> BTreeQueryKey<'_, B>
+where
+    B: Backend,
+{
+    /// Bytes stored alongside the key by a covering index, empty for other indexes
+    pub fn projection(&self) -> &[u8] {
+        self.value.as_ref()
+    }
+
+    /// Opaque cursor to resume the query after this result, with `BTreeQuery::after`
+    pub fn cursor(&self) -> Vec<u8> {
+        self.key.as_ref().to_vec()
+    }
 }
 
 impl<B> AsRef<[u8]> for BTreeQueryKey<'_, B>
@@ -166,7 +329,7 @@ where
 impl<B, K> Index<B> for BTreeIndex<K>
 where
     B: Backend,
-    K: Key,
+    K: MultiKey,
 {
     type Query<'q> = BTreeQuery<'q, K>;
     type QueryKey<'k> = BTreeQueryKey<'k, B>;
@@ -178,29 +341,54 @@ where
         object_cf: &'op B::TransactionCf<'t>,
         cfs: &'op [B::TransactionCf<'t>],
     ) -> waaa::BoxStream<'q, eyre::Result<(Self::QueryKey<'op>, B::Value<'op>)>> {
-        let on_each_result = async |res: eyre::Result<(B::Key<'op>, B::Value<'op>)>| -> eyre::Result<
-            (Self::QueryKey<'op>, B::Value<'op>)
-        > {
-            let (index_key, _) = res.wrap_err_with(|| CfOperationError::new("Failed scanning", cfs[0].name()))?;
-            let key_len = self.key.key_len(index_key.as_ref());
-            let object_key = BTreeQueryKey {
-                key: index_key,
-                start: key_len,
-            };
-            let object_value = transaction
-                .get(object_cf, object_key.as_ref())
-                .await
-            .wrap_err_with(|| CfOperationError::new("Failed getting object", object_cf.name()))?
-                .expect("Object was present in index but not in real table");
-            Ok((object_key, object_value))
-        };
-        Box::pin(match query.query {
-            Query::Prefix(prefix) => transaction
-                .scan_prefix(&cfs[0], prefix)
-                .then(on_each_result),
-            Query::Range { start, end } => transaction
-                .scan::<[u8]>(&cfs[0], (start, end))
-                .then(on_each_result),
-        })
+        Box::pin(
+            self.query_keys(query, transaction, object_cf, cfs)
+                .and_then(async |object_key| {
+                    let object_value = transaction
+                        .get(object_cf, object_key.as_ref())
+                        .await
+                        .and_then(|value| {
+                            value.ok_or_else(|| {
+                                eyre!("Object was present in index but not in real table")
+                            })
+                        })
+                        .wrap_err_with(|| {
+                            CfOperationError::new("Failed getting object", object_cf.name())
+                        })?;
+                    Ok((object_key, object_value))
+                }),
+        )
+    }
+
+    fn query_keys<'q, 'op: 'q, 't: 'op>(
+        &'q self,
+        query: &'q Self::Query<'q>,
+        transaction: &'op B::Transaction<'t>,
+        _object_cf: &'op B::TransactionCf<'t>,
+        cfs: &'op [B::TransactionCf<'t>],
+    ) -> waaa::BoxStream<'q, eyre::Result<Self::QueryKey<'op>>> {
+        Box::pin(
+            query
+                .scan::<B>(&self.key, transaction, &cfs[0])
+                .map_ok(|(key, value)| BTreeQueryKey {
+                    start: self.key.extracted_key_len(key.as_ref()),
+                    key,
+                    value,
+                }),
+        )
+    }
+
+    fn count<'q, 'op: 'q, 't: 'op>(
+        &'q self,
+        query: &'q Self::Query<'q>,
+        transaction: &'op B::Transaction<'t>,
+        _object_cf: &'op B::TransactionCf<'t>,
+        cfs: &'op [B::TransactionCf<'t>],
+    ) -> waaa::BoxFuture<'q, eyre::Result<usize>> {
+        Box::pin(
+            query
+                .scan::<B>(&self.key, transaction, &cfs[0])
+                .try_fold(0, |count, _| future::ready(Ok(count + 1))),
+        )
     }
 }
@@ -32,4 +32,14 @@ pub trait Key: 'static + waaa::Send + waaa::Sync {
     /// The actual key is a prefix of `in_slice`, and this function must return thelength it
     /// occupies.
     fn key_len(&self, in_slice: &[u8]) -> usize;
+
+    /// Describes how this key is encoded, for inclusion in the fingerprint of indexes using it.
+    ///
+    /// It must change whenever the encoding of the keys changes, eg. with the type of the key
+    /// or its parameters. Changes to the logic of the extractors cannot be detected here, and
+    /// must be signalled with the index's version instead.
+    ///
+    /// This is persisted in the database, so it must be stable across builds: in particular, it
+    /// must not rely on `std::any::type_name`.
+    fn key_fingerprint(&self) -> String;
 }
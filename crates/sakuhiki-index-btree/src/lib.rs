@@ -1,16 +1,34 @@
+mod encoding;
+
+mod escaped;
+pub use escaped::{EscapedKey, EscapedKeyExtractorFromSlice, escape};
+
+mod field;
+pub use field::{FieldKey, KeyField};
+
 mod fixed_len;
 pub use fixed_len::{FixedLenKey, FixedLenKeyExtractorFromSlice};
 
 mod index;
-pub use index::BTreeIndex;
-// TODO(med): add escaped key: 0 for the end, 10 for 0, 11 for 1. Document that shorter strings collate as smaller.
-// TODO(med): add composite keys: a tuple of keys.
+pub use index::{BTreeIndex, BTreeQueryKey, ProjectionFromSlice};
 
 mod key;
 pub use key::Key;
 
+mod multi;
+pub use multi::{FieldsKey, MultiKey};
+
 mod query;
 pub use query::BTreeQuery;
 
+mod reversed;
+pub use reversed::{Reversed, reverse};
+
+mod tuple;
+pub use tuple::TupleKey;
+
+mod unique;
+pub use unique::{UniqueBTreeIndex, UniqueQueryKey, UniqueViolation};
+
 #[cfg(test)]
 mod tests;
@@ -0,0 +1,109 @@
+use eyre::WrapErr as _;
+use sakuhiki_core::Datum;
+
+use crate::{Key, KeyField};
+
+/// Key that can index a datum under any number of keys, eg. one per tag of the datum.
+///
+/// All the keys are implemented as `MultiKey`s that yield at most one key.
+pub trait MultiKey: 'static + waaa::Send + waaa::Sync {
+    type Datum: Datum;
+
+    /// Pushes into `keys` all the keys under which `datum` must be indexed.
+    fn extract_keys(&self, datum: &Self::Datum, keys: &mut Vec<Vec<u8>>);
+
+    /// Pushes into `keys` all the keys under which `datum` must be indexed.
+    fn extract_keys_from_slice(&self, datum: &[u8], keys: &mut Vec<Vec<u8>>) -> eyre::Result<()> {
+        let datum = Self::Datum::from_slice(datum).wrap_err("Failed to parse datum")?;
+        self.extract_keys(&datum, keys);
+        Ok(())
+    }
+
+    /// Returns the length of the key in the `in_slice` slice.
+    ///
+    /// The actual key is a prefix of `in_slice`, and this function must return the length it
+    /// occupies. This is the same as `Key::key_len`, named differently to not be ambiguous
+    /// with it.
+    fn extracted_key_len(&self, in_slice: &[u8]) -> usize;
+
+    /// Describes how the keys are encoded, for inclusion in the fingerprint of indexes using
+    /// them. This is the same as `Key::key_fingerprint`, named differently to not be ambiguous
+    /// with it.
+    fn extracted_key_fingerprint(&self) -> String;
+}
+
+#[warn(clippy::missing_trait_methods)]
+impl<K> MultiKey for K
+where
+    K: Key,
+{
+    type Datum = K::Datum;
+
+    fn extract_keys(&self, datum: &Self::Datum, keys: &mut Vec<Vec<u8>>) {
+        let mut key = Vec::with_capacity(self.len_hint(datum));
+        if self.extract_key(datum, &mut key) {
+            keys.push(key);
+        }
+    }
+
+    fn extract_keys_from_slice(&self, datum: &[u8], keys: &mut Vec<Vec<u8>>) -> eyre::Result<()> {
+        let mut key = Vec::with_capacity(
+            self.len_hint_from_slice(datum)
+                .wrap_err("Failed estimating key length from slice")?,
+        );
+        if self
+            .extract_key_from_slice(datum, &mut key)
+            .wrap_err("Failed extracting key from slice")?
+        {
+            keys.push(key);
+        }
+        Ok(())
+    }
+
+    fn extracted_key_len(&self, in_slice: &[u8]) -> usize {
+        self.key_len(in_slice)
+    }
+
+    fn extracted_key_fingerprint(&self) -> String {
+        self.key_fingerprint()
+    }
+}
+
+/// Key made of each of the values of a field of the datum, eg. to index a list of tags.
+pub struct FieldsKey<D, F> {
+    fields: fn(&D) -> &[F],
+}
+
+impl<D, F> FieldsKey<D, F> {
+    /// `fields` returns the values of `D` to index.
+    pub const fn new(fields: fn(&D) -> &[F]) -> Self {
+        Self { fields }
+    }
+}
+
+#[warn(clippy::missing_trait_methods)]
+impl<D, F> MultiKey for FieldsKey<D, F>
+where
+    D: Datum,
+    F: KeyField,
+{
+    type Datum = D;
+
+    fn extract_keys(&self, datum: &D, keys: &mut Vec<Vec<u8>>) {
+        keys.extend((self.fields)(datum).iter().map(KeyField::to_key));
+    }
+
+    fn extract_keys_from_slice(&self, slice: &[u8], keys: &mut Vec<Vec<u8>>) -> eyre::Result<()> {
+        let datum = D::from_slice(slice).wrap_err("Failed to parse datum")?;
+        self.extract_keys(&datum, keys);
+        Ok(())
+    }
+
+    fn extracted_key_len(&self, in_slice: &[u8]) -> usize {
+        F::encoded_len(in_slice)
+    }
+
+    fn extracted_key_fingerprint(&self) -> String {
+        format!("fields/{}", F::ENCODING)
+    }
+}
@@ -1,51 +1,282 @@
 use std::{
+    borrow::Cow,
     marker::PhantomData,
     ops::{Bound, RangeBounds},
 };
 
-use crate::Key;
+use eyre::WrapErr as _;
+use futures_util::{Stream, StreamExt as _, TryStreamExt as _, future};
+use sakuhiki_core::{
+    Backend, CfOperationError, Datum,
+    backend::{BackendCf as _, Transaction as _, prefix_plus_one},
+};
+
+use crate::{FieldKey, KeyField, MultiKey, TupleKey};
 
 pub struct BTreeQuery<'q, K>
 where
-    K: Key,
+    K: MultiKey,
 {
     pub(crate) query: Query<'q>,
+    pub(crate) rev: bool,
+    pub(crate) after: Option<&'q [u8]>,
+    pub(crate) offset: usize,
+    pub(crate) limit: Option<usize>,
     _phantom: PhantomData<fn(K::Datum)>,
 }
 
 pub(crate) enum Query<'q> {
-    Prefix(&'q [u8]),
+    /// Only the entries whose key, as delimited by `Key::key_len`, is exactly this one
+    Equal(Cow<'q, [u8]>),
+    Prefix(Cow<'q, [u8]>),
     Range {
-        start: Bound<&'q [u8]>,
-        end: Bound<&'q [u8]>,
+        start: Bound<Cow<'q, [u8]>>,
+        end: Bound<Cow<'q, [u8]>>,
     },
 }
 
 impl<'q, K> BTreeQuery<'q, K>
 where
-    K: Key,
+    K: MultiKey,
 {
-    pub fn equal(key: &'q [u8]) -> Self {
+    fn new(query: Query<'q>) -> Self {
         Self {
-            query: Query::Prefix(key),
+            query,
+            rev: false,
+            after: None,
+            offset: 0,
+            limit: None,
             _phantom: PhantomData,
         }
     }
 
+    /// Matches all the keys that are exactly `key`, as delimited by `Key::key_len`
+    pub fn equal(key: &'q [u8]) -> Self {
+        Self::new(Query::Equal(Cow::Borrowed(key)))
+    }
+
     pub fn prefix(prefix: &'q [u8]) -> Self {
-        Self {
-            query: Query::Prefix(prefix),
-            _phantom: PhantomData,
-        }
+        Self::new(Query::Prefix(Cow::Borrowed(prefix)))
     }
 
     pub fn range(range: impl RangeBounds<&'q [u8]>) -> Self {
-        Self {
-            query: Query::Range {
-                start: range.start_bound().cloned(),
-                end: range.end_bound().cloned(),
-            },
-            _phantom: PhantomData,
+        Self::new(Query::Range {
+            start: range.start_bound().map(|b| Cow::Borrowed(*b)),
+            end: range.end_bound().map(|b| Cow::Borrowed(*b)),
+        })
+    }
+}
+
+impl<'q, K> BTreeQuery<'q, K>
+where
+    K: MultiKey,
+{
+    /// Return the results in descending order of keys, instead of ascending
+    pub fn rev(mut self) -> Self {
+        self.rev = !self.rev;
+        self
+    }
+
+    /// Only return the results that come after `cursor`, as returned by `BTreeQueryKey::cursor`
+    ///
+    /// This allows resuming a paginated query where the previous page stopped, even if entries
+    /// got added or removed in-between. The cursor only ever narrows the query: a cursor from
+    /// before the start of the query is ignored, and one from after its end returns no results.
+    pub fn after(mut self, cursor: &'q [u8]) -> Self {
+        self.after = Some(cursor);
+        self
+    }
+
+    /// Skip the first `offset` results
+    ///
+    /// Skipped results are still scanned through, so `after` should be preferred for pagination.
+    pub fn offset(mut self, offset: usize) -> Self {
+        self.offset = offset;
+        self
+    }
+
+    /// Return at most `limit` results
+    pub fn limit(mut self, limit: usize) -> Self {
+        self.limit = Some(limit);
+        self
+    }
+
+    /// Returns the length of the keys that match, if only exact matches must be returned
+    pub(crate) fn exact_len(&self) -> Option<usize> {
+        match &self.query {
+            Query::Equal(key) => Some(key.len()),
+            Query::Prefix(_) | Query::Range { .. } => None,
         }
     }
+
+    /// Returns the range of index keys to scan, taking `after` into account
+    ///
+    /// Returns `None` if there is nothing to scan, eg. if the cursor is past the end of the query.
+    pub(crate) fn scan_range(&self) -> Option<ScanRange> {
+        let (start, end) = match &self.query {
+            Query::Equal(prefix) | Query::Prefix(prefix) => (
+                Bound::Included(prefix.to_vec()),
+                prefix_plus_one(prefix).map_or(Bound::Unbounded, Bound::Excluded),
+            ),
+            Query::Range { start, end } => (
+                start.as_ref().map(|b| b.to_vec()),
+                end.as_ref().map(|b| b.to_vec()),
+            ),
+        };
+        // The cursor could come from another query, so it must narrow the range and not replace it
+        let (start, end) = match (self.after, self.rev) {
+            (None, _) => (start, end),
+            (Some(after), false) => {
+                let after_start = match &start {
+                    Bound::Included(s) => after >= s.as_slice(),
+                    Bound::Excluded(s) => after > s.as_slice(),
+                    Bound::Unbounded => true,
+                };
+                if after_start {
+                    (Bound::Excluded(after.to_vec()), end)
+                } else {
+                    (start, end)
+                }
+            }
+            (Some(after), true) => {
+                let before_end = match &end {
+                    Bound::Included(e) => after <= e.as_slice(),
+                    Bound::Excluded(e) => after < e.as_slice(),
+                    Bound::Unbounded => true,
+                };
+                if before_end {
+                    (start, Bound::Excluded(after.to_vec()))
+                } else {
+                    (start, end)
+                }
+            }
+        };
+        let is_empty = match (&start, &end) {
+            (Bound::Included(s), Bound::Included(e)) => s > e,
+            (Bound::Included(s) | Bound::Excluded(s), Bound::Included(e) | Bound::Excluded(e)) => {
+                s >= e
+            }
+            (Bound::Unbounded, _) | (_, Bound::Unbounded) => false,
+        };
+        (!is_empty).then_some((start, end))
+    }
+
+    /// Returns the entries of `cf`, indexed by `key`, that match this query, with pagination
+    /// applied
+    pub(crate) fn scan<'op, 't, B>(
+        &'q self,
+        key: &'q K,
+        transaction: &'op B::Transaction<'t>,
+        cf: &'op B::TransactionCf<'t>,
+    ) -> impl 'q + Stream<Item = eyre::Result<(B::Key<'op>, B::Value<'op>)>>
+    where
+        'op: 'q,
+        't: 'op,
+        B: Backend,
+    {
+        let entries = match self.scan_range() {
+            None => Box::pin(futures_util::stream::empty()),
+            Some(range) if self.rev => transaction.scan_rev(cf, range),
+            Some(range) => transaction.scan(cf, range),
+        };
+        let exact_len = self.exact_len();
+        let mut to_skip = self.offset;
+        entries
+            .map(|res| res.wrap_err_with(|| CfOperationError::new("Failed scanning", cf.name())))
+            // Variable-length keys can have the searched key as a prefix without being equal
+            .try_filter(move |(index_key, _)| {
+                future::ready(
+                    exact_len.is_none_or(|len| key.extracted_key_len(index_key.as_ref()) == len),
+                )
+            })
+            .try_filter(move |_| {
+                let skip = to_skip > 0;
+                to_skip = to_skip.saturating_sub(1);
+                future::ready(!skip)
+            })
+            .take(self.limit.unwrap_or(usize::MAX))
+    }
+}
+
+impl<K> BTreeQuery<'static, K>
+where
+    K: TupleKey,
+{
+    /// Matches all the keys whose first components are `components`
+    ///
+    /// Each component must already be encoded by its own key, eg. with `KeyField::to_key`.
+    /// Results are ordered by the remaining components.
+    pub fn tuple_prefix(components: &[&[u8]]) -> Self {
+        assert!(
+            components.len() <= K::ARITY,
+            "Tuple prefix has more components than the key"
+        );
+        Self::new(Query::Prefix(Cow::Owned(components.concat())))
+    }
+
+    /// Matches all the keys whose first components are `prefix`, and whose next component is in
+    /// `range`
+    ///
+    /// All components and bounds must already be encoded by their own key. Bounds are on the
+    /// whole component, so an included end bound also matches all the keys that have this exact
+    /// value for the component.
+    pub fn tuple_range<'r>(prefix: &[&[u8]], range: impl RangeBounds<&'r [u8]>) -> Self {
+        assert!(
+            prefix.len() < K::ARITY,
+            "Tuple prefix leaves no component for the range"
+        );
+        Self::new(component_range(prefix.concat(), range))
+    }
+}
+
+impl<D, F> BTreeQuery<'static, FieldKey<D, F>>
+where
+    D: Datum,
+    F: KeyField,
+{
+    /// Matches all the keys whose field is `value`
+    pub fn field_equal(value: &F) -> Self {
+        Self::new(Query::Equal(Cow::Owned(value.to_key())))
+    }
+
+    /// Matches all the keys whose field is in `range`
+    pub fn field_range(range: impl RangeBounds<F>) -> Self {
+        let start = range.start_bound().map(KeyField::to_key);
+        let end = range.end_bound().map(KeyField::to_key);
+        Self::new(component_range(
+            Vec::new(),
+            (
+                start.as_ref().map(Vec::as_slice),
+                end.as_ref().map(Vec::as_slice),
+            ),
+        ))
+    }
+}
+
+/// Bounds of the index keys to scan
+type ScanRange = (Bound<Vec<u8>>, Bound<Vec<u8>>);
+
+/// Query for all the keys starting with `prefix` followed by a component in `range`
+///
+/// Bounds are on the whole component, so an included end bound also matches all the keys that
+/// continue after the component.
+fn component_range<'r>(prefix: Vec<u8>, range: impl RangeBounds<&'r [u8]>) -> Query<'static> {
+    let with_prefix = |b: &[u8]| [&prefix, b].concat();
+    let start = match range.start_bound() {
+        Bound::Included(b) => Bound::Included(Cow::Owned(with_prefix(b))),
+        Bound::Excluded(b) => match prefix_plus_one(&with_prefix(b)) {
+            Some(s) => Bound::Included(Cow::Owned(s)),
+            None => Bound::Excluded(Cow::Owned(with_prefix(b))),
+        },
+        Bound::Unbounded => Bound::Included(Cow::Owned(prefix.clone())),
+    };
+    let end = match range.end_bound() {
+        Bound::Included(b) => prefix_plus_one(&with_prefix(b))
+            .map_or(Bound::Unbounded, |s| Bound::Excluded(Cow::Owned(s))),
+        Bound::Excluded(b) => Bound::Excluded(Cow::Owned(with_prefix(b))),
+        Bound::Unbounded => {
+            prefix_plus_one(&prefix).map_or(Bound::Unbounded, |s| Bound::Excluded(Cow::Owned(s)))
+        }
+    };
+    Query::Range { start, end }
 }
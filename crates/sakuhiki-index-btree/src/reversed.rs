@@ -0,0 +1,76 @@
+use crate::Key;
+
+/// Key that sorts in the opposite order as `K`, eg. to list the newest datums first.
+///
+/// The encoding of `K` is bitwise-inverted. This requires `K` to be prefix-free, ie. that no key of
+/// `K` is a prefix of another one, which all the keys of this crate are: otherwise, inverting
+/// would not reverse the order of such keys.
+pub struct Reversed<K> {
+    key: K,
+}
+
+impl<K> Reversed<K> {
+    pub const fn new(key: K) -> Self {
+        Self { key }
+    }
+}
+
+fn invert(key: &mut [u8]) {
+    for b in key {
+        *b = !*b;
+    }
+}
+
+/// Returns the reversed form of `key`, a key encoded by `K`, eg. to build a `BTreeQuery` on a
+/// `Reversed<K>`
+///
+/// Note that reversing keys also reverses ranges: the reversed form of the upper bound becomes
+/// the lower bound, and conversely.
+pub fn reverse(key: &[u8]) -> Vec<u8> {
+    key.iter().map(|b| !b).collect()
+}
+
+#[warn(clippy::missing_trait_methods)]
+impl<K> Key for Reversed<K>
+where
+    K: Key,
+{
+    type Datum = K::Datum;
+
+    fn len_hint(&self, datum: &Self::Datum) -> usize {
+        self.key.len_hint(datum)
+    }
+
+    fn extract_key(&self, datum: &Self::Datum, key: &mut Vec<u8>) -> bool {
+        let start = key.len();
+        if !self.key.extract_key(datum, key) {
+            return false;
+        }
+        invert(&mut key[start..]);
+        true
+    }
+
+    fn len_hint_from_slice(&self, datum: &[u8]) -> eyre::Result<usize> {
+        self.key.len_hint_from_slice(datum)
+    }
+
+    fn extract_key_from_slice(&self, datum: &[u8], key: &mut Vec<u8>) -> eyre::Result<bool> {
+        let start = key.len();
+        if !self.key.extract_key_from_slice(datum, key)? {
+            return Ok(false);
+        }
+        invert(&mut key[start..]);
+        Ok(true)
+    }
+
+    fn key_len(&self, in_slice: &[u8]) -> usize {
+        // TODO(low): avoid this allocation, eg. by having `Key::key_len` take an iterator
+        let mut original = in_slice.to_vec();
+        invert(&mut original);
+        self.key.key_len(&original)
+    }
+
+    fn key_fingerprint(&self) -> String {
+        format!("reversed({})", self.key.key_fingerprint())
+    }
+}
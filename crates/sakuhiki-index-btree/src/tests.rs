@@ -1,4 +1,7 @@
+use std::ops::Bound;
+
 use eyre::eyre;
+use futures_util::TryStreamExt as _;
 use sakuhiki_core::{Backend, Datum as _, Indexer, Mode};
 
 use crate::*;
@@ -13,13 +16,6 @@ impl Datum {
     fn new(foo: u32, bar: u32) -> Self {
         Self { foo, bar }
     }
-
-    fn to_array(&self) -> [u8; 8] {
-        let mut array = [0; 8];
-        array[..4].copy_from_slice(&self.foo.to_be_bytes());
-        array[4..].copy_from_slice(&self.bar.to_be_bytes());
-        array
-    }
 }
 
 impl sakuhiki_core::Datum for Datum {
@@ -33,6 +29,13 @@ impl sakuhiki_core::Datum for Datum {
             bar: u32::from_be_bytes(datum[4..].try_into().unwrap()),
         })
     }
+
+    fn to_bytes(&self) -> eyre::Result<Vec<u8>> {
+        let mut bytes = Vec::with_capacity(8);
+        bytes.extend_from_slice(&self.foo.to_be_bytes());
+        bytes.extend_from_slice(&self.bar.to_be_bytes());
+        Ok(bytes)
+    }
 }
 
 impl Datum {
@@ -58,11 +61,31 @@ impl Datum {
             None,
         ),
     );
+    #[allow(clippy::type_complexity)]
+    const INDEX_FOO_BAR: &'static BTreeIndex<(FieldKey<Datum, u32>, FieldKey<Datum, u32>)> =
+        &BTreeIndex::new(
+            &["datum-foo-bar"],
+            (FieldKey::new(|d| &d.foo), FieldKey::new(|d| &d.bar)),
+        );
+    const INDEX_FOO_COVERING: &'static BTreeIndex<FieldKey<Datum, u32>> = &BTreeIndex::new_covering(
+        &["datum-foo-covering"],
+        FieldKey::new(|d| &d.foo),
+        0,
+        |d, value| value.extend_from_slice(&d.bar.to_be_bytes()),
+        Some(|slice, value| {
+            value.extend_from_slice(&slice[4..]);
+            Ok(())
+        }),
+    );
 }
 
 impl<B: Backend> sakuhiki_core::IndexedDatum<B> for Datum {
-    const INDEXES: &'static [&'static dyn Indexer<B, Datum = Self>] =
-        &[Self::INDEX_FOO, Self::INDEX_BAR];
+    const INDEXES: &'static [&'static dyn Indexer<B, Datum = Self>] = &[
+        Self::INDEX_FOO,
+        Self::INDEX_BAR,
+        Self::INDEX_FOO_BAR,
+        Self::INDEX_FOO_COVERING,
+    ];
 }
 
 #[tokio::test]
@@ -73,27 +96,1057 @@ async fn test_index() {
         .await
         .unwrap();
     let datum = db.cf_handle::<Datum>().await.unwrap();
-    db.transaction(Mode::ReadWrite, &[&datum], |t, [mut datum]| {
+    db.transaction(Mode::ReadWrite, &[&datum], |t, [datum]| {
         Box::pin(async move {
             let d12 = Datum::new(1, 2);
             let d21 = Datum::new(2, 1);
-            t.put::<Datum>(&mut datum, b"12", &d12.to_array())
+            t.put::<Datum>(&datum, b"12", &d12.to_bytes()?)
                 .await
                 .unwrap();
-            t.put::<Datum>(&mut datum, b"21", &d21.to_array())
+            assert_eq!(t.put_datum(&datum, b"21", &d12).await.unwrap(), None);
+            assert_eq!(t.put_datum(&datum, b"21", &d21).await.unwrap(), Some(d12));
+            assert_eq!(
+                Datum::from_slice(&t.get(&datum, b"12").await.unwrap().unwrap()).unwrap(),
+                Datum::new(1, 2)
+            );
+            assert_eq!(t.get_datum(&datum, b"21").await.unwrap(), Some(d21));
+            assert_eq!(t.get_datum::<Datum>(&datum, b"42").await.unwrap(), None);
+            // Overwriting 21 must have unindexed its old value
+            let foo_1 = t
+                .query(
+                    &datum,
+                    Datum::INDEX_FOO,
+                    &BTreeQuery::equal(&1u32.to_be_bytes()),
+                )?
+                .map_ok(|(k, _)| k.as_ref().to_vec())
+                .try_collect::<Vec<_>>()
+                .await?;
+            assert_eq!(foo_1, [b"12"]);
+            Ok(())
+        })
+    })
+    .await
+    .unwrap();
+    // TODO(med): test more and better
+}
+
+#[tokio::test]
+async fn test_query() {
+    let db = sakuhiki_memdb::MemDb::builder()
+        .datum::<Datum>()
+        .build()
+        .await
+        .unwrap();
+    let datum = db.cf_handle::<Datum>().await.unwrap();
+    db.transaction(Mode::ReadWrite, &[&datum], |t, [datum]| {
+        Box::pin(async move {
+            for (key, d) in [
+                (b"12", Datum::new(1, 2)),
+                (b"21", Datum::new(2, 1)),
+                (b"22", Datum::new(2, 2)),
+            ] {
+                t.put_datum(&datum, key, &d).await?;
+            }
+            let query_keys = async |index: &'static BTreeIndex<FixedLenKey<Datum>>,
+                                    query: BTreeQuery<'_, FixedLenKey<Datum>>|
+                   -> Vec<Vec<u8>> {
+                t.query(&datum, index, &query)
+                    .unwrap()
+                    .map_ok(|(k, _)| k.as_ref().to_vec())
+                    .try_collect()
+                    .await
+                    .unwrap()
+            };
+            assert_eq!(
+                query_keys(Datum::INDEX_FOO, BTreeQuery::equal(&2u32.to_be_bytes())).await,
+                [b"21", b"22"]
+            );
+            assert_eq!(
+                query_keys(Datum::INDEX_BAR, BTreeQuery::equal(&2u32.to_be_bytes())).await,
+                [b"12", b"22"]
+            );
+            assert_eq!(
+                query_keys(
+                    Datum::INDEX_BAR,
+                    BTreeQuery::equal(&2u32.to_be_bytes()).rev()
+                )
+                .await,
+                [b"22", b"12"]
+            );
+            // A prefix of a fixed-length key is not equal to it
+            assert_eq!(
+                query_keys(
+                    Datum::INDEX_FOO,
+                    BTreeQuery::prefix(&2u32.to_be_bytes()[..3])
+                )
+                .await,
+                [b"12", b"21", b"22"]
+            );
+            assert!(
+                query_keys(
+                    Datum::INDEX_FOO,
+                    BTreeQuery::equal(&2u32.to_be_bytes()[..3])
+                )
                 .await
+                .is_empty()
+            );
+            assert_eq!(
+                query_keys(Datum::INDEX_FOO, BTreeQuery::range(..).rev()).await,
+                [b"22", b"21", b"12"]
+            );
+            assert_eq!(
+                query_keys(
+                    Datum::INDEX_FOO,
+                    BTreeQuery::range(&1u32.to_be_bytes()[..]..&2u32.to_be_bytes()[..])
+                )
+                .await,
+                [b"12"]
+            );
+            let (_, value) = t
+                .query(
+                    &datum,
+                    Datum::INDEX_BAR,
+                    &BTreeQuery::equal(&1u32.to_be_bytes()),
+                )?
+                .try_next()
+                .await?
                 .unwrap();
+            assert_eq!(Datum::from_slice(&value)?, Datum::new(2, 1));
+            Ok(())
+        })
+    })
+    .await
+    .unwrap();
+}
+
+#[tokio::test]
+async fn test_query_keys() {
+    let db = sakuhiki_memdb::MemDb::builder()
+        .datum::<Datum>()
+        .build()
+        .await
+        .unwrap();
+    let datum = db.cf_handle::<Datum>().await.unwrap();
+    db.transaction(Mode::ReadWrite, &[&datum], |t, [datum]| {
+        Box::pin(async move {
+            for (key, d) in [
+                (b"12", Datum::new(1, 2)),
+                (b"21", Datum::new(2, 1)),
+                (b"23", Datum::new(2, 3)),
+            ] {
+                t.put_datum(&datum, key, &d).await?;
+            }
+            let keys = t
+                .query_keys(
+                    &datum,
+                    Datum::INDEX_FOO,
+                    &BTreeQuery::equal(&2u32.to_be_bytes()),
+                )?
+                .map_ok(|k| (k.as_ref().to_vec(), k.projection().to_vec()))
+                .try_collect::<Vec<_>>()
+                .await?;
+            assert_eq!(keys, [(b"21".to_vec(), vec![]), (b"23".to_vec(), vec![])]);
+            // Covering indexes return the projection without reading the datums
+            let projections = t
+                .query_keys(
+                    &datum,
+                    Datum::INDEX_FOO_COVERING,
+                    &BTreeQuery::field_equal(&2),
+                )?
+                .map_ok(|k| (k.as_ref().to_vec(), k.projection().to_vec()))
+                .try_collect::<Vec<_>>()
+                .await?;
             assert_eq!(
-                Datum::from_slice(&t.get(&mut datum, b"12").await.unwrap().unwrap()).unwrap(),
-                d12
+                projections,
+                [
+                    (b"21".to_vec(), 1u32.to_be_bytes().to_vec()),
+                    (b"23".to_vec(), 3u32.to_be_bytes().to_vec())
+                ]
             );
+            // Unindexing removes the projection along with the key
+            t.put_datum(&datum, b"23", &Datum::new(3, 4)).await?;
+            let projections = t
+                .query_keys(&datum, Datum::INDEX_FOO_COVERING, &BTreeQuery::range(..))?
+                .map_ok(|k| (k.as_ref().to_vec(), k.projection().to_vec()))
+                .try_collect::<Vec<_>>()
+                .await?;
             assert_eq!(
-                Datum::from_slice(&t.get(&mut datum, b"21").await.unwrap().unwrap()).unwrap(),
-                d21
+                projections,
+                [
+                    (b"12".to_vec(), 2u32.to_be_bytes().to_vec()),
+                    (b"21".to_vec(), 1u32.to_be_bytes().to_vec()),
+                    (b"23".to_vec(), 4u32.to_be_bytes().to_vec())
+                ]
             );
+            // Changing only the projection rewrites the kept entry
+            t.put_datum(&datum, b"21", &Datum::new(2, 5)).await?;
+            let projections = t
+                .query_keys(
+                    &datum,
+                    Datum::INDEX_FOO_COVERING,
+                    &BTreeQuery::field_equal(&2),
+                )?
+                .map_ok(|k| k.projection().to_vec())
+                .try_collect::<Vec<_>>()
+                .await?;
+            assert_eq!(projections, [5u32.to_be_bytes()]);
+            Ok(())
         })
     })
     .await
     .unwrap();
-    // TODO(med): test more and better
+    // Rebuilding goes through the projection from slice
+    db.rebuild_index(Datum::INDEX_FOO_COVERING).await.unwrap();
+    let datum = db.cf_handle::<Datum>().await.unwrap();
+    db.transaction(Mode::ReadOnly, &[&datum], |t, [datum]| {
+        Box::pin(async move {
+            let projections = t
+                .query_keys(&datum, Datum::INDEX_FOO_COVERING, &BTreeQuery::range(..))?
+                .map_ok(|k| k.projection().to_vec())
+                .try_collect::<Vec<_>>()
+                .await?;
+            assert_eq!(
+                projections,
+                [2u32.to_be_bytes(), 5u32.to_be_bytes(), 4u32.to_be_bytes()]
+            );
+            Ok(())
+        })
+    })
+    .await
+    .unwrap();
+}
+
+#[tokio::test]
+async fn test_pagination() {
+    let db = sakuhiki_memdb::MemDb::builder()
+        .datum::<Datum>()
+        .build()
+        .await
+        .unwrap();
+    let datum = db.cf_handle::<Datum>().await.unwrap();
+    db.transaction(Mode::ReadWrite, &[&datum], |t, [datum]| {
+        Box::pin(async move {
+            for (key, d) in [
+                (b"12", Datum::new(1, 2)),
+                (b"21", Datum::new(2, 1)),
+                (b"22", Datum::new(2, 2)),
+                (b"31", Datum::new(3, 1)),
+            ] {
+                t.put_datum(&datum, key, &d).await?;
+            }
+            let query_page = async |query: BTreeQuery<'_, FixedLenKey<Datum>>|
+                   -> (Vec<Vec<u8>>, Option<Vec<u8>>) {
+                let results = t
+                    .query(&datum, Datum::INDEX_FOO, &query)
+                    .unwrap()
+                    .map_ok(|(k, _)| (k.as_ref().to_vec(), k.cursor()))
+                    .try_collect::<Vec<_>>()
+                    .await
+                    .unwrap();
+                let cursor = results.last().map(|(_, c)| c.clone());
+                (results.into_iter().map(|(k, _)| k).collect(), cursor)
+            };
+            let count = async |query: BTreeQuery<'_, FixedLenKey<Datum>>| -> usize {
+                t.count(&datum, Datum::INDEX_FOO, &query).await.unwrap()
+            };
+
+            // Limit and offset
+            let (keys, _) = query_page(BTreeQuery::range(..).limit(2)).await;
+            assert_eq!(keys, [b"12", b"21"]);
+            let (keys, _) = query_page(BTreeQuery::range(..).offset(1).limit(2)).await;
+            assert_eq!(keys, [b"21", b"22"]);
+            let (keys, _) = query_page(BTreeQuery::range(..).offset(3)).await;
+            assert_eq!(keys, [b"31"]);
+            let (keys, _) = query_page(BTreeQuery::range(..).offset(5)).await;
+            assert!(keys.is_empty());
+            let (keys, _) = query_page(BTreeQuery::range(..).rev().offset(1).limit(2)).await;
+            assert_eq!(keys, [b"22", b"21"]);
+
+            // Cursor pagination, forward
+            let (keys, cursor) = query_page(BTreeQuery::range(..).limit(2)).await;
+            assert_eq!(keys, [b"12", b"21"]);
+            let cursor = cursor.unwrap();
+            let (keys, cursor) = query_page(BTreeQuery::range(..).after(&cursor).limit(2)).await;
+            assert_eq!(keys, [b"22", b"31"]);
+            let cursor = cursor.unwrap();
+            let (keys, cursor) = query_page(BTreeQuery::range(..).after(&cursor).limit(2)).await;
+            assert!(keys.is_empty());
+            assert!(cursor.is_none());
+
+            // Cursor pagination, within a prefix query
+            let (keys, cursor) = query_page(BTreeQuery::equal(&2u32.to_be_bytes()).limit(1)).await;
+            assert_eq!(keys, [b"21"]);
+            let cursor = cursor.unwrap();
+            let (keys, _) = query_page(BTreeQuery::equal(&2u32.to_be_bytes()).after(&cursor)).await;
+            assert_eq!(keys, [b"22"]);
+
+            // Cursor pagination, reversed
+            let (keys, cursor) = query_page(BTreeQuery::range(..).rev().limit(3)).await;
+            assert_eq!(keys, [b"31", b"22", b"21"]);
+            let cursor = cursor.unwrap();
+            let (keys, _) = query_page(BTreeQuery::range(..).rev().after(&cursor)).await;
+            assert_eq!(keys, [b"12"]);
+
+            // Cursor from another query, only narrowing the query
+            let (_, first) = query_page(BTreeQuery::range(..).limit(1)).await;
+            let first = first.unwrap();
+            let (_, last) = query_page(BTreeQuery::range(..).rev().limit(1)).await;
+            let last = last.unwrap();
+            let two = 2u32.to_be_bytes();
+            let (keys, _) = query_page(BTreeQuery::equal(&two).after(&first)).await;
+            assert_eq!(keys, [b"21", b"22"]);
+            let (keys, _) = query_page(BTreeQuery::equal(&two).after(&last)).await;
+            assert!(keys.is_empty());
+            let (keys, _) = query_page(BTreeQuery::equal(&two).rev().after(&last)).await;
+            assert_eq!(keys, [b"22", b"21"]);
+            let (keys, _) = query_page(BTreeQuery::equal(&two).rev().after(&first)).await;
+            assert!(keys.is_empty());
+            assert_eq!(count(BTreeQuery::equal(&two).after(&last)).await, 0);
+
+            // Count
+            assert_eq!(count(BTreeQuery::range(..)).await, 4);
+            assert_eq!(count(BTreeQuery::equal(&2u32.to_be_bytes())).await, 2);
+            assert_eq!(count(BTreeQuery::range(..).offset(1).limit(2)).await, 2);
+            assert_eq!(count(BTreeQuery::range(..).offset(3).limit(2)).await, 1);
+            Ok(())
+        })
+    })
+    .await
+    .unwrap();
+}
+
+#[tokio::test]
+async fn test_tuple_query() {
+    let db = sakuhiki_memdb::MemDb::builder()
+        .datum::<Datum>()
+        .build()
+        .await
+        .unwrap();
+    let datum = db.cf_handle::<Datum>().await.unwrap();
+    db.transaction(Mode::ReadWrite, &[&datum], |t, [datum]| {
+        Box::pin(async move {
+            for (key, d) in [
+                (b"23", Datum::new(2, 3)),
+                (b"12", Datum::new(1, 2)),
+                (b"21", Datum::new(2, 1)),
+                (b"24", Datum::new(2, 4)),
+                (b"31", Datum::new(3, 1)),
+            ] {
+                t.put_datum(&datum, key, &d).await?;
+            }
+            let query_keys = async |query: BTreeQuery<'_, _>| -> Vec<Vec<u8>> {
+                t.query(&datum, Datum::INDEX_FOO_BAR, &query)
+                    .unwrap()
+                    .map_ok(|(k, _)| k.as_ref().to_vec())
+                    .try_collect()
+                    .await
+                    .unwrap()
+            };
+            let (two, three) = (2u32.to_key(), 3u32.to_key());
+            assert_eq!(
+                query_keys(BTreeQuery::tuple_prefix(&[&two])).await,
+                [b"21", b"23", b"24"]
+            );
+            assert_eq!(
+                query_keys(BTreeQuery::tuple_prefix(&[&two, &three])).await,
+                [b"23"]
+            );
+            assert_eq!(
+                query_keys(BTreeQuery::tuple_range(&[&two], &three[..]..)).await,
+                [b"23", b"24"]
+            );
+            assert_eq!(
+                query_keys(BTreeQuery::tuple_range(&[&two], ..=&three[..])).await,
+                [b"21", b"23"]
+            );
+            assert_eq!(
+                query_keys(BTreeQuery::tuple_range(
+                    &[&two],
+                    (Bound::Excluded(&three[..]), Bound::Unbounded)
+                ))
+                .await,
+                [b"24"]
+            );
+            Ok(())
+        })
+    })
+    .await
+    .unwrap();
+}
+
+#[test]
+fn test_tuple_key_len() {
+    let key = (
+        EscapedKey::<Name>::new(|d| Some(&d.0), None),
+        FixedLenKey::<Name>::new(
+            1,
+            |d, key| {
+                key[0] = d.0.len() as u8;
+                true
+            },
+            None,
+        ),
+    );
+    let name = Name(b"a\0\x01b".to_vec());
+    let mut encoded = Vec::new();
+    assert!(key.extract_key(&name, &mut encoded));
+    assert_eq!(encoded, b"a\x01\0\x01\x01b\0\x04");
+    encoded.extend_from_slice(b"object key");
+    assert_eq!(key.key_len(&encoded), 8);
+
+    // Nothing is indexed if any component does not index the datum
+    let empty = (
+        EscapedKey::<Name>::new(|d| Some(&d.0), None),
+        EscapedKey::<Name>::new(|_| None, None),
+    );
+    let mut encoded = b"prefix".to_vec();
+    assert!(!empty.extract_key(&name, &mut encoded));
+    assert_eq!(encoded, b"prefix");
+}
+
+#[test]
+fn test_string_key_field() {
+    let strings = ["", "\0", "\0\0", "\x01", "a", "a\0", "a\x01b", "ab", "b"];
+    let keys = strings.map(|s| String::from(s).to_key());
+    for w in keys.windows(2) {
+        assert!(w[0] < w[1], "{:?} should sort before {:?}", w[0], w[1]);
+    }
+    for key in &keys {
+        let mut with_suffix = key.clone();
+        with_suffix.extend_from_slice(b"suffix");
+        assert_eq!(String::encoded_len(&with_suffix), key.len());
+    }
+}
+
+struct Name(Vec<u8>);
+
+impl sakuhiki_core::Datum for Name {
+    const CF: &'static str = "name";
+
+    fn from_slice(datum: &[u8]) -> eyre::Result<Self> {
+        Ok(Self(datum.to_vec()))
+    }
+
+    fn to_bytes(&self) -> eyre::Result<Vec<u8>> {
+        Ok(self.0.clone())
+    }
+}
+
+impl Name {
+    const INDEX: &'static BTreeIndex<EscapedKey<Name>> = &BTreeIndex::new(
+        &["name-escaped"],
+        EscapedKey::new(
+            |d| (!d.0.is_empty()).then_some(&d.0[..]),
+            Some(|slice| Ok((!slice.is_empty()).then_some(slice))),
+        ),
+    );
+    const INDEX_RAW: &'static BTreeIndex<RawNameKey> = &BTreeIndex::new(&["name-raw"], RawNameKey);
+}
+
+impl<B: Backend> sakuhiki_core::IndexedDatum<B> for Name {
+    const INDEXES: &'static [&'static dyn Indexer<B, Datum = Self>] =
+        &[Self::INDEX, Self::INDEX_RAW];
+}
+
+/// Unescaped name, which relies on all the object keys being one byte long to delimit the key
+struct RawNameKey;
+
+#[warn(clippy::missing_trait_methods)]
+impl Key for RawNameKey {
+    type Datum = Name;
+
+    fn len_hint(&self, datum: &Name) -> usize {
+        datum.0.len()
+    }
+
+    fn extract_key(&self, datum: &Name, key: &mut Vec<u8>) -> bool {
+        key.extend_from_slice(&datum.0);
+        true
+    }
+
+    fn len_hint_from_slice(&self, slice: &[u8]) -> eyre::Result<usize> {
+        Ok(slice.len())
+    }
+
+    fn extract_key_from_slice(&self, slice: &[u8], key: &mut Vec<u8>) -> eyre::Result<bool> {
+        key.extend_from_slice(slice);
+        Ok(true)
+    }
+
+    fn key_len(&self, in_slice: &[u8]) -> usize {
+        in_slice.len() - 1
+    }
+
+    fn key_fingerprint(&self) -> String {
+        String::from("raw-name")
+    }
+}
+
+#[tokio::test]
+async fn test_escaped_key() {
+    let db = sakuhiki_memdb::MemDb::builder()
+        .datum::<Name>()
+        .build()
+        .await
+        .unwrap();
+    let name = db.cf_handle::<Name>().await.unwrap();
+    db.transaction(Mode::ReadWrite, &[&name], |t, [name]| {
+        Box::pin(async move {
+            let names: [&[u8]; 7] = [b"b", b"a\x01b", b"", b"ab", b"a\0", b"a", b"\0"];
+            for (i, n) in names.iter().enumerate() {
+                t.put_datum(&name, &[u8::try_from(i).unwrap()], &Name(n.to_vec()))
+                    .await?;
+            }
+            let query_keys = async |query: BTreeQuery<'_, EscapedKey<Name>>| -> Vec<Vec<u8>> {
+                t.query(&name, Name::INDEX, &query)
+                    .unwrap()
+                    .map_ok(|(k, _)| k.as_ref().to_vec())
+                    .try_collect()
+                    .await
+                    .unwrap()
+            };
+            // Empty names are not indexed, and shorter names sort first
+            assert_eq!(
+                query_keys(BTreeQuery::range(..)).await,
+                [[6], [5], [4], [1], [3], [0]]
+            );
+            assert_eq!(query_keys(BTreeQuery::equal(&escape(b"a"))).await, [[5]]);
+            assert_eq!(query_keys(BTreeQuery::equal(&escape(b"a\0"))).await, [[4]]);
+            assert_eq!(
+                query_keys(BTreeQuery::prefix(b"a")).await,
+                [[5], [4], [1], [3]]
+            );
+            // Without a terminator, equal keys must be told apart from longer ones by their length
+            let query_raw = async |query: BTreeQuery<'_, RawNameKey>| -> Vec<Vec<u8>> {
+                t.query(&name, Name::INDEX_RAW, &query)
+                    .unwrap()
+                    .map_ok(|(k, _)| k.as_ref().to_vec())
+                    .try_collect()
+                    .await
+                    .unwrap()
+            };
+            assert_eq!(
+                query_raw(BTreeQuery::prefix(b"a")).await,
+                [[4], [1], [5], [3]]
+            );
+            assert_eq!(query_raw(BTreeQuery::equal(b"a")).await, [[5]]);
+            assert_eq!(query_raw(BTreeQuery::equal(b"a").rev()).await, [[5]]);
+            assert_eq!(query_raw(BTreeQuery::equal(b"ab")).await, [[3]]);
+            assert_eq!(query_raw(BTreeQuery::equal(b"")).await, [[2]]);
+            assert_eq!(
+                t.count(&name, Name::INDEX_RAW, &BTreeQuery::equal(b"a"))
+                    .await?,
+                1
+            );
+            Ok(())
+        })
+    })
+    .await
+    .unwrap();
+    // Rebuilding goes through the extractor from slice
+    db.rebuild_index(Name::INDEX).await.unwrap();
+    let name = db.cf_handle::<Name>().await.unwrap();
+    db.transaction(Mode::ReadOnly, &[&name], |t, [name]| {
+        Box::pin(async move {
+            let all = t
+                .query(&name, Name::INDEX, &BTreeQuery::range(..))?
+                .map_ok(|(k, _)| k.as_ref().to_vec())
+                .try_collect::<Vec<_>>()
+                .await?;
+            assert_eq!(all, [[6], [5], [4], [1], [3], [0]]);
+            Ok(())
+        })
+    })
+    .await
+    .unwrap();
+}
+
+fn assert_sorted_keys<F: KeyField + std::fmt::Debug>(values: &[F]) {
+    let keys = values.iter().map(KeyField::to_key).collect::<Vec<_>>();
+    for (w, v) in keys.windows(2).zip(values.windows(2)) {
+        assert!(w[0] < w[1], "{:?} should sort before {:?}", v[0], v[1]);
+    }
+    for (key, value) in keys.iter().zip(values) {
+        assert_eq!(F::encoded_len(key), key.len(), "{value:?}");
+        assert_eq!(value.len_hint(), key.len(), "{value:?}");
+    }
+}
+
+#[test]
+fn test_key_field_encodings() {
+    use std::time::{Duration, UNIX_EPOCH};
+
+    assert_sorted_keys(&[i8::MIN, -1, 0, 1, i8::MAX]);
+    assert_sorted_keys(&[i32::MIN, -256, -1, 0, 1, 256, i32::MAX]);
+    assert_sorted_keys(&[i128::MIN, -1, 0, 1, i128::MAX]);
+    assert_sorted_keys(&[
+        f64::NEG_INFINITY,
+        -1e300,
+        -1.5,
+        -f64::MIN_POSITIVE,
+        -0.0,
+        0.0,
+        f64::MIN_POSITIVE,
+        1.5,
+        1e300,
+        f64::INFINITY,
+        f64::NAN,
+    ]);
+    assert_sorted_keys(&[f32::NEG_INFINITY, -1.0, -0.0, 0.0, 1.0, f32::INFINITY]);
+    assert_sorted_keys(&[false, true]);
+    assert_sorted_keys(&[
+        UNIX_EPOCH - Duration::from_secs(10),
+        UNIX_EPOCH - Duration::from_nanos(1),
+        UNIX_EPOCH,
+        UNIX_EPOCH + Duration::from_nanos(1),
+        UNIX_EPOCH + Duration::from_secs(10),
+    ]);
+    assert_sorted_keys(&[
+        Duration::ZERO,
+        Duration::from_nanos(1),
+        Duration::from_secs(1),
+    ]);
+    #[cfg(feature = "uuid")]
+    assert_sorted_keys(&[
+        uuid::Uuid::nil(),
+        uuid::Uuid::from_u128(1),
+        uuid::Uuid::from_u128(1 << 64),
+        uuid::Uuid::max(),
+    ]);
+}
+
+struct Reading {
+    celsius: f64,
+}
+
+impl sakuhiki_core::Datum for Reading {
+    const CF: &'static str = "reading";
+
+    fn from_slice(datum: &[u8]) -> eyre::Result<Self> {
+        Ok(Self {
+            celsius: f64::from_be_bytes(datum.try_into()?),
+        })
+    }
+
+    fn to_bytes(&self) -> eyre::Result<Vec<u8>> {
+        Ok(self.celsius.to_be_bytes().to_vec())
+    }
+}
+
+impl Reading {
+    const INDEX_CELSIUS: &'static BTreeIndex<FieldKey<Reading, f64>> =
+        &BTreeIndex::new(&["reading-celsius"], FieldKey::new(|d| &d.celsius));
+}
+
+impl Reading {
+    const INDEX_CELSIUS_DESC: &'static BTreeIndex<Reversed<FieldKey<Reading, f64>>> =
+        &BTreeIndex::new(
+            &["reading-celsius-desc"],
+            Reversed::new(FieldKey::new(|d| &d.celsius)),
+        );
+}
+
+impl<B: Backend> sakuhiki_core::IndexedDatum<B> for Reading {
+    const INDEXES: &'static [&'static dyn Indexer<B, Datum = Self>] =
+        &[Self::INDEX_CELSIUS, Self::INDEX_CELSIUS_DESC];
+}
+
+#[tokio::test]
+async fn test_field_query() {
+    let db = sakuhiki_memdb::MemDb::builder()
+        .datum::<Reading>()
+        .build()
+        .await
+        .unwrap();
+    let reading = db.cf_handle::<Reading>().await.unwrap();
+    db.transaction(Mode::ReadWrite, &[&reading], |t, [reading]| {
+        Box::pin(async move {
+            for (key, celsius) in [(b"a", 12.5), (b"b", -3.0), (b"c", 0.0), (b"d", -3.0)] {
+                t.put_datum(&reading, key, &Reading { celsius }).await?;
+            }
+            let query_keys = async |query: BTreeQuery<'_, _>| -> Vec<Vec<u8>> {
+                t.query(&reading, Reading::INDEX_CELSIUS, &query)
+                    .unwrap()
+                    .map_ok(|(k, _)| k.as_ref().to_vec())
+                    .try_collect()
+                    .await
+                    .unwrap()
+            };
+            assert_eq!(
+                query_keys(BTreeQuery::field_equal(&-3.0)).await,
+                [b"b", b"d"]
+            );
+            assert_eq!(
+                query_keys(BTreeQuery::field_range(-3.0..0.0)).await,
+                [b"b", b"d"]
+            );
+            assert_eq!(
+                query_keys(BTreeQuery::field_range(..=0.0)).await,
+                [b"b", b"d", b"c"]
+            );
+            assert_eq!(
+                query_keys(BTreeQuery::field_range(-1.0..)).await,
+                [b"c", b"a"]
+            );
+            Ok(())
+        })
+    })
+    .await
+    .unwrap();
+}
+
+#[tokio::test]
+async fn test_reversed_query() {
+    let db = sakuhiki_memdb::MemDb::builder()
+        .datum::<Reading>()
+        .build()
+        .await
+        .unwrap();
+    let reading = db.cf_handle::<Reading>().await.unwrap();
+    db.transaction(Mode::ReadWrite, &[&reading], |t, [reading]| {
+        Box::pin(async move {
+            for (key, celsius) in [(b"a", 12.5), (b"b", -3.0), (b"c", 0.0), (b"d", -3.0)] {
+                t.put_datum(&reading, key, &Reading { celsius }).await?;
+            }
+            let query_keys = async |query: BTreeQuery<'_, _>| -> Vec<Vec<u8>> {
+                t.query(&reading, Reading::INDEX_CELSIUS_DESC, &query)
+                    .unwrap()
+                    .map_ok(|(k, _)| k.as_ref().to_vec())
+                    .try_collect()
+                    .await
+                    .unwrap()
+            };
+            assert_eq!(
+                query_keys(BTreeQuery::range(..)).await,
+                [b"a", b"c", b"b", b"d"]
+            );
+            let minus_three = reverse(&(-3.0f64).to_key());
+            assert_eq!(
+                query_keys(BTreeQuery::equal(&minus_three)).await,
+                [b"b", b"d"]
+            );
+            let zero = reverse(&0.0f64.to_key());
+            assert_eq!(
+                query_keys(BTreeQuery::range(&zero[..]..)).await,
+                [b"c", b"b", b"d"]
+            );
+            Ok(())
+        })
+    })
+    .await
+    .unwrap();
+}
+
+#[test]
+fn test_reversed_variable_len() {
+    let key = Reversed::new(EscapedKey::<Name>::new(|d| Some(&d.0), None));
+    // Includes keys that continue with `0x00` and `0xFF` after a shorter key
+    let names: [&[u8]; 8] = [
+        b"b", b"a\xff", b"ab\xff", b"ab", b"a\0\xff", b"a\0\0", b"a\0", b"a",
+    ];
+    let mut encoded = Vec::new();
+    for n in names {
+        let mut k = Vec::new();
+        assert!(key.extract_key(&Name(n.to_vec()), &mut k));
+        assert_eq!(k, reverse(&escape(n)));
+        k.extend_from_slice(b"object key");
+        assert_eq!(key.key_len(&k), k.len() - b"object key".len());
+        encoded.push(k);
+    }
+    for w in encoded.windows(2) {
+        assert!(w[0] < w[1], "{:?} should sort before {:?}", w[0], w[1]);
+    }
+}
+
+#[derive(Debug)]
+struct Account(Vec<u8>);
+
+impl sakuhiki_core::Datum for Account {
+    const CF: &'static str = "account";
+
+    fn from_slice(datum: &[u8]) -> eyre::Result<Self> {
+        Ok(Self(datum.to_vec()))
+    }
+
+    fn to_bytes(&self) -> eyre::Result<Vec<u8>> {
+        Ok(self.0.clone())
+    }
+}
+
+impl Account {
+    const INDEX_EMAIL: &'static UniqueBTreeIndex<EscapedKey<Account>> = &UniqueBTreeIndex::new(
+        &["account-email"],
+        EscapedKey::new(|d| Some(&d.0[..]), None),
+    );
+}
+
+impl<B: Backend> sakuhiki_core::IndexedDatum<B> for Account {
+    const INDEXES: &'static [&'static dyn Indexer<B, Datum = Self>] = &[Self::INDEX_EMAIL];
+}
+
+#[tokio::test]
+async fn test_unique_index() {
+    let db = sakuhiki_memdb::MemDb::builder()
+        .datum::<Account>()
+        .build()
+        .await
+        .unwrap();
+    let account = db.cf_handle::<Account>().await.unwrap();
+    db.transaction(Mode::ReadWrite, &[&account], |t, [account]| {
+        Box::pin(async move {
+            t.put_datum(&account, b"1", &Account(b"a@example.org".to_vec()))
+                .await?;
+            t.put_datum(&account, b"2", &Account(b"b@example.org".to_vec()))
+                .await?;
+            // Re-putting the same key for the same object is fine
+            t.put_datum(&account, b"2", &Account(b"b@example.org".to_vec()))
+                .await?;
+            Ok(())
+        })
+    })
+    .await
+    .unwrap();
+    let account = db.cf_handle::<Account>().await.unwrap();
+    let err = db
+        .transaction(Mode::ReadWrite, &[&account], |t, [account]| {
+            Box::pin(async move {
+                t.put_datum(&account, b"3", &Account(b"a@example.org".to_vec()))
+                    .await?;
+                Ok(())
+            })
+        })
+        .await
+        .unwrap_err();
+    match err.downcast_ref::<UniqueViolation>() {
+        Some(UniqueViolation {
+            cf,
+            key,
+            object_key,
+        }) => {
+            assert_eq!(*cf, "account-email");
+            assert_eq!(*key, escape(b"a@example.org"));
+            assert_eq!(object_key, b"1");
+        }
+        _ => panic!("unexpected error {err:?}"),
+    }
+    let account = db.cf_handle::<Account>().await.unwrap();
+    db.transaction(Mode::ReadWrite, &[&account], |t, [account]| {
+        Box::pin(async move {
+            // The failed transaction was rolled back
+            assert!(t.get(&account, b"3").await?.is_none());
+            let get_by_unique = async |email: &[u8]| -> Option<Vec<u8>> {
+                Account::INDEX_EMAIL
+                    .get_by_unique(&t, &account, &escape(email))
+                    .await
+                    .unwrap()
+                    .map(|(k, _)| k.as_ref().to_vec())
+            };
+            assert_eq!(get_by_unique(b"a@example.org").await.unwrap(), b"1");
+            assert_eq!(get_by_unique(b"b@example.org").await.unwrap(), b"2");
+            assert_eq!(get_by_unique(b"c@example.org").await, None);
+            // Changing the key frees the old one
+            t.put_datum(&account, b"1", &Account(b"c@example.org".to_vec()))
+                .await?;
+            assert_eq!(get_by_unique(b"a@example.org").await, None);
+            assert_eq!(get_by_unique(b"c@example.org").await.unwrap(), b"1");
+            t.put_datum(&account, b"3", &Account(b"a@example.org".to_vec()))
+                .await?;
+            let (_, value) = Account::INDEX_EMAIL
+                .get_by_unique(&t, &account, &escape(b"a@example.org"))
+                .await?
+                .unwrap();
+            assert_eq!(value, b"a@example.org");
+            let emails = t
+                .query(&account, Account::INDEX_EMAIL, &BTreeQuery::range(..))?
+                .map_ok(|(k, _)| k.as_ref().to_vec())
+                .try_collect::<Vec<_>>()
+                .await?;
+            assert_eq!(emails, [b"3", b"2", b"1"]);
+            Ok(())
+        })
+    })
+    .await
+    .unwrap();
+    // Recovering from violations and committing leaves the datums consistent with the index
+    let account = db.cf_handle::<Account>().await.unwrap();
+    db.transaction(Mode::ReadWrite, &[&account], |t, [account]| {
+        Box::pin(async move {
+            for (key, email) in [(b"2", b"a@example.org"), (b"4", b"c@example.org")] {
+                let err = t
+                    .put_datum(&account, key, &Account(email.to_vec()))
+                    .await
+                    .unwrap_err();
+                assert!(err.downcast_ref::<UniqueViolation>().is_some());
+            }
+            Ok(())
+        })
+    })
+    .await
+    .unwrap();
+    let account = db.cf_handle::<Account>().await.unwrap();
+    db.transaction(Mode::ReadOnly, &[&account], |t, [account]| {
+        Box::pin(async move {
+            assert_eq!(t.get(&account, b"2").await?.unwrap(), b"b@example.org");
+            assert!(t.get(&account, b"4").await?.is_none());
+            let entries = t
+                .query(&account, Account::INDEX_EMAIL, &BTreeQuery::range(..))?
+                .map_ok(|(k, v)| (k.as_ref().to_vec(), v))
+                .try_collect::<Vec<_>>()
+                .await?;
+            assert_eq!(
+                entries,
+                [
+                    (b"3".to_vec(), b"a@example.org".to_vec()),
+                    (b"2".to_vec(), b"b@example.org".to_vec()),
+                    (b"1".to_vec(), b"c@example.org".to_vec()),
+                ]
+            );
+            Ok(())
+        })
+    })
+    .await
+    .unwrap();
+}
+
+struct Post {
+    tags: Vec<String>,
+}
+
+impl Post {
+    fn new(tags: &[&str]) -> Self {
+        Self {
+            tags: tags.iter().map(|t| t.to_string()).collect(),
+        }
+    }
+}
+
+impl sakuhiki_core::Datum for Post {
+    const CF: &'static str = "post";
+
+    fn from_slice(datum: &[u8]) -> eyre::Result<Self> {
+        let tags = str::from_utf8(datum)?;
+        Ok(Self {
+            tags: tags
+                .split(',')
+                .filter(|t| !t.is_empty())
+                .map(String::from)
+                .collect(),
+        })
+    }
+
+    fn to_bytes(&self) -> eyre::Result<Vec<u8>> {
+        Ok(self.tags.join(",").into_bytes())
+    }
+}
+
+impl Post {
+    const INDEX_TAGS: &'static BTreeIndex<FieldsKey<Post, String>> =
+        &BTreeIndex::new(&["post-tags"], FieldsKey::new(|p| &p.tags));
+}
+
+impl<B: Backend> sakuhiki_core::IndexedDatum<B> for Post {
+    const INDEXES: &'static [&'static dyn Indexer<B, Datum = Self>] = &[Self::INDEX_TAGS];
+}
+
+#[tokio::test]
+async fn test_multi_key() {
+    let db = sakuhiki_memdb::MemDb::builder()
+        .datum::<Post>()
+        .build()
+        .await
+        .unwrap();
+    let post = db.cf_handle::<Post>().await.unwrap();
+    db.transaction(Mode::ReadWrite, &[&post], |t, [post]| {
+        Box::pin(async move {
+            let tagged = async |tag: &str| -> Vec<Vec<u8>> {
+                t.query_keys(
+                    &post,
+                    Post::INDEX_TAGS,
+                    &BTreeQuery::equal(&tag.to_string().to_key()),
+                )
+                .unwrap()
+                .map_ok(|k| k.as_ref().to_vec())
+                .try_collect()
+                .await
+                .unwrap()
+            };
+            t.put_datum(&post, b"1", &Post::new(&["rust", "db"]))
+                .await?;
+            t.put_datum(&post, b"2", &Post::new(&["rust"])).await?;
+            t.put_datum(&post, b"3", &Post::new(&[])).await?;
+            assert_eq!(tagged("rust").await, [b"1", b"2"]);
+            assert_eq!(tagged("db").await, [b"1"]);
+            assert_eq!(
+                t.count(&post, Post::INDEX_TAGS, &BTreeQuery::range(..))
+                    .await?,
+                3
+            );
+            // Only the removed and added tags change
+            t.put_datum(&post, b"1", &Post::new(&["db", "index"]))
+                .await?;
+            assert_eq!(tagged("rust").await, [b"2"]);
+            assert_eq!(tagged("db").await, [b"1"]);
+            assert_eq!(tagged("index").await, [b"1"]);
+            t.delete::<Post>(&post, b"2").await?;
+            assert!(tagged("rust").await.is_empty());
+            Ok(())
+        })
+    })
+    .await
+    .unwrap();
+    // Rebuilding goes through the keys from slice
+    db.rebuild_index(Post::INDEX_TAGS).await.unwrap();
+    let post = db.cf_handle::<Post>().await.unwrap();
+    db.transaction(Mode::ReadOnly, &[&post], |t, [post]| {
+        Box::pin(async move {
+            let all = t
+                .query_keys(&post, Post::INDEX_TAGS, &BTreeQuery::range(..))?
+                .map_ok(|k| k.as_ref().to_vec())
+                .try_collect::<Vec<_>>()
+                .await?;
+            assert_eq!(all, [b"1", b"1"]);
+            Ok(())
+        })
+    })
+    .await
+    .unwrap();
+}
+
+#[test]
+fn test_key_fingerprint() {
+    fn fingerprint<K: MultiKey>(key: K) -> Vec<u8> {
+        Indexer::<sakuhiki_memdb::MemDb>::fingerprint(&BTreeIndex::new(&["index"], key))
+    }
+    let fixed = |len| FixedLenKey::<Datum>::new(len, |_, _| true, None);
+    // Fingerprints are persisted, so they must not depend on eg. the names of the types
+    assert_eq!(fingerprint(fixed(4)), b"btree/fixed-len/4/v0");
+    assert_eq!(
+        fingerprint((
+            Reversed::new(FieldKey::<Datum, u32>::new(|d| &d.foo)),
+            EscapedKey::<Datum>::new(|_| None, None),
+        )),
+        b"btree/(reversed(field/u32), escaped)/v0"
+    );
+    assert_eq!(
+        fingerprint(FieldsKey::<Datum, String>::new(|_| &[])),
+        b"btree/fields/string/v0"
+    );
+    assert_ne!(fingerprint(fixed(4)), fingerprint(fixed(8)));
+    assert_ne!(fingerprint(fixed(4)), fingerprint(Reversed::new(fixed(4))));
+    assert_ne!(
+        fingerprint(Reversed::new(fixed(4))),
+        fingerprint(Reversed::new(fixed(8)))
+    );
+    assert_ne!(
+        fingerprint(FieldKey::<Datum, u32>::new(|d| &d.foo)),
+        fingerprint(FieldKey::<Datum, u64>::new(|_| &0)),
+    );
+    assert_ne!(
+        fingerprint((fixed(4), fixed(8))),
+        fingerprint((fixed(8), fixed(4)))
+    );
+    assert_ne!(
+        fingerprint(EscapedKey::<Name>::new(|d| Some(&d.0), None)),
+        fingerprint(RawNameKey)
+    );
 }
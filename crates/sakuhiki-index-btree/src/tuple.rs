@@ -0,0 +1,79 @@
+use sakuhiki_core::Datum;
+
+use crate::Key;
+
+/// Key made of a tuple of keys over the same datum, encoded as the concatenation of their keys.
+///
+/// The datum is indexed iff all the component keys would index it.
+pub trait TupleKey: Key {
+    /// Number of components of the tuple
+    const ARITY: usize;
+}
+
+macro_rules! tuple_key {
+    ($arity:literal: $($k:ident $i:tt),*) => {
+        #[warn(clippy::missing_trait_methods)]
+        impl<D, $($k),*> Key for ($($k,)*)
+        where
+            D: Datum,
+            $($k: Key<Datum = D>,)*
+        {
+            type Datum = D;
+
+            fn len_hint(&self, datum: &D) -> usize {
+                0 $(+ self.$i.len_hint(datum))*
+            }
+
+            fn extract_key(&self, datum: &D, key: &mut Vec<u8>) -> bool {
+                let len = key.len();
+                let do_index = $(self.$i.extract_key(datum, key))&&*;
+                if !do_index {
+                    key.truncate(len);
+                }
+                do_index
+            }
+
+            fn len_hint_from_slice(&self, slice: &[u8]) -> eyre::Result<usize> {
+                Ok(0 $(+ self.$i.len_hint_from_slice(slice)?)*)
+            }
+
+            fn extract_key_from_slice(&self, slice: &[u8], key: &mut Vec<u8>) -> eyre::Result<bool> {
+                let len = key.len();
+                $(
+                    if !self.$i.extract_key_from_slice(slice, key)? {
+                        key.truncate(len);
+                        return Ok(false);
+                    }
+                )*
+                Ok(true)
+            }
+
+            fn key_len(&self, in_slice: &[u8]) -> usize {
+                let mut len = 0;
+                $(
+                    len += self.$i.key_len(&in_slice[len..]);
+                )*
+                len
+            }
+
+            fn key_fingerprint(&self) -> String {
+                let fingerprints: [String; $arity] = [$(self.$i.key_fingerprint()),*];
+                format!("({})", fingerprints.join(", "))
+            }
+        }
+
+        impl<D, $($k),*> TupleKey for ($($k,)*)
+        where
+            D: Datum,
+            $($k: Key<Datum = D>,)*
+        {
+            const ARITY: usize = $arity;
+        }
+    };
+}
+
+tuple_key!(2: K0 0, K1 1);
+tuple_key!(3: K0 0, K1 1, K2 2);
+tuple_key!(4: K0 0, K1 1, K2 2, K3 3);
+tuple_key!(5: K0 0, K1 1, K2 2, K3 3, K4 4);
+tuple_key!(6: K0 0, K1 1, K2 2, K3 3, K4 4, K5 5);
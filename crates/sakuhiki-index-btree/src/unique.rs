@@ -0,0 +1,355 @@
+use eyre::{WrapErr as _, eyre};
+use futures_util::{TryStreamExt as _, future};
+use sakuhiki_core::{
+    Backend, CfOperationError, Index, Indexer, Transaction, TransactionCf,
+    backend::{BackendCf as _, Transaction as _},
+    indexer,
+};
+
+use crate::{BTreeQuery, Key};
+
+/// Error returned when putting an object whose key is already used by another object
+#[derive(Debug, thiserror::Error)]
+#[error("Key {key:?} of unique index in CF {cf} is already used by object {object_key:?}")]
+pub struct UniqueViolation {
+    pub cf: &'static str,
+    pub key: Vec<u8>,
+    pub object_key: Vec<u8>,
+}
+
+/// BTree index that allows at most one object per key
+///
+/// Each key is stored alongside the key of the object it indexes, and putting a datum whose key
+/// is already used by another object fails with `UniqueViolation`, before anything gets
+/// written.
+pub struct UniqueBTreeIndex<K> {
+    cf: &'static [&'static str; 1],
+    key: K,
+    version: u64,
+}
+
+impl<K> UniqueBTreeIndex<K> {
+    pub const fn new(cf: &'static [&'static str; 1], key: K) -> Self {
+        Self::new_versioned(cf, key, 0)
+    }
+
+    /// Same as `new`, but with a version for the key extraction, which `new` sets to 0
+    ///
+    /// See `BTreeIndex::new_versioned`.
+    pub const fn new_versioned(cf: &'static [&'static str; 1], key: K, version: u64) -> Self {
+        Self { cf, key, version }
+    }
+}
+
+impl<K> UniqueBTreeIndex<K>
+where
+    K: Key,
+{
+    /// Get the object whose key is `key`, which must be encoded like `BTreeQuery::equal`
+    pub async fn get_by_unique<'op, 't, B>(
+        &'static self,
+        transaction: &'op Transaction<'t, B>,
+        cf: &'op TransactionCf<'t, B>,
+        key: &[u8],
+    ) -> eyre::Result<Option<(UniqueQueryKey<'op, B>, B::Value<'op>)>>
+    where
+        B: Backend,
+    {
+        let query = BTreeQuery::equal(key);
+        transaction.query(cf, self, &query)?.try_next().await
+    }
+
+    /// Checks that `key` is not already used by another object than `object_key`
+    async fn check_unique<'t, B>(
+        &self,
+        key: &[u8],
+        object_key: &[u8],
+        transaction: &B::Transaction<'t>,
+        cf: &B::TransactionCf<'t>,
+    ) -> eyre::Result<()>
+    where
+        B: Backend,
+    {
+        let existing = transaction
+            .get(cf, key)
+            .await
+            .wrap_err_with(|| CfOperationError::new("Failed getting key from", cf.name()))?;
+        if let Some(existing) = existing.filter(|e| e.as_ref() != object_key) {
+            return Err(eyre::Report::from(UniqueViolation {
+                cf: cf.name(),
+                key: key.to_vec(),
+                object_key: existing.as_ref().to_vec(),
+            }));
+        }
+        Ok(())
+    }
+
+    async fn put_unique<'t, B>(
+        &self,
+        key: &[u8],
+        object_key: &[u8],
+        transaction: &B::Transaction<'t>,
+        cf: &B::TransactionCf<'t>,
+    ) -> eyre::Result<()>
+    where
+        B: Backend,
+    {
+        // Already checked by `check_write` on puts, but not when rebuilding the index
+        self.check_unique::<B>(key, object_key, transaction, cf)
+            .await?;
+        transaction
+            .put(cf, key, object_key)
+            .await
+            .wrap_err_with(|| CfOperationError::new("Failed putting key into", cf.name()))?;
+        Ok(())
+    }
+}
+
+#[warn(clippy::missing_trait_methods)]
+impl<B, K> Indexer<B> for UniqueBTreeIndex<K>
+where
+    B: Backend,
+    K: Key,
+{
+    type Datum = K::Datum;
+
+    fn cfs(&self) -> &'static [&'static str] {
+        self.cf
+    }
+
+    fn foreign_cfs(&self) -> Vec<&'static str> {
+        Vec::new()
+    }
+
+    fn fingerprint(&self) -> Vec<u8> {
+        format!(
+            "btree-unique/{}/v{}",
+            self.key.key_fingerprint(),
+            self.version
+        )
+        .into_bytes()
+    }
+
+    fn check_write<'fut, 't>(
+        &'fut self,
+        object_key: &'fut [u8],
+        new: Option<&'fut Self::Datum>,
+        transaction: &'fut B::Transaction<'t>,
+        cfs: &'fut [B::TransactionCf<'t>],
+    ) -> waaa::BoxFuture<'fut, eyre::Result<()>> {
+        Box::pin(async move {
+            let Some(new) = new else {
+                return Ok(());
+            };
+            let mut key = Vec::with_capacity(self.key.len_hint(new));
+            if self.key.extract_key(new, &mut key) {
+                self.check_unique::<B>(&key, object_key, transaction, &cfs[0])
+                    .await?;
+            }
+            Ok(())
+        })
+    }
+
+    fn index<'fut, 't>(
+        &'fut self,
+        object_key: &'fut [u8],
+        datum: &'fut Self::Datum,
+        transaction: &'fut B::Transaction<'t>,
+        cfs: &'fut [B::TransactionCf<'t>],
+    ) -> waaa::BoxFuture<'fut, eyre::Result<()>> {
+        Box::pin(async move {
+            let mut key = Vec::with_capacity(self.key.len_hint(datum));
+            if self.key.extract_key(datum, &mut key) {
+                self.put_unique::<B>(&key, object_key, transaction, &cfs[0])
+                    .await?;
+            }
+            Ok(())
+        })
+    }
+
+    fn unindex<'fut, 't>(
+        &'fut self,
+        _object_key: &'fut [u8],
+        datum: &'fut Self::Datum,
+        transaction: &'fut B::Transaction<'t>,
+        cfs: &'fut [B::TransactionCf<'t>],
+    ) -> waaa::BoxFuture<'fut, eyre::Result<()>> {
+        Box::pin(async move {
+            let mut key = Vec::with_capacity(self.key.len_hint(datum));
+            if self.key.extract_key(datum, &mut key) {
+                transaction.delete(&cfs[0], &key).await.wrap_err_with(|| {
+                    CfOperationError::new("Failed deleting key from", cfs[0].name())
+                })?;
+            }
+            Ok(())
+        })
+    }
+
+    fn reindex<'fut, 't>(
+        &'fut self,
+        object_key: &'fut [u8],
+        old: &'fut Self::Datum,
+        new: &'fut Self::Datum,
+        transaction: &'fut B::Transaction<'t>,
+        cfs: &'fut [B::TransactionCf<'t>],
+    ) -> waaa::BoxFuture<'fut, eyre::Result<()>> {
+        Box::pin(async move {
+            // Unindexing first, so that the new key can be the same as the old one
+            Indexer::<B>::unindex(self, object_key, old, transaction, cfs).await?;
+            Indexer::<B>::index(self, object_key, new, transaction, cfs).await
+        })
+    }
+
+    fn index_from_slice<'fut, 't>(
+        &'fut self,
+        object_key: &'fut [u8],
+        slice: &'fut [u8],
+        transaction: &'fut B::Transaction<'t>,
+        cfs: &'fut [B::TransactionCf<'t>],
+    ) -> waaa::BoxFuture<'fut, eyre::Result<()>> {
+        Box::pin(async move {
+            let mut key = Vec::with_capacity(
+                self.key
+                    .len_hint_from_slice(slice)
+                    .wrap_err("Failed estimating key length from slice")?,
+            );
+            let do_index = self
+                .key
+                .extract_key_from_slice(slice, &mut key)
+                .wrap_err("Failed extracting key from slice")?;
+            if do_index {
+                self.put_unique::<B>(&key, object_key, transaction, &cfs[0])
+                    .await?;
+            }
+            Ok(())
+        })
+    }
+
+    fn unindex_from_slice<'fut, 't>(
+        &'fut self,
+        _object_key: &'fut [u8],
+        slice: &'fut [u8],
+        transaction: &'fut B::Transaction<'t>,
+        cfs: &'fut [B::TransactionCf<'t>],
+    ) -> waaa::BoxFuture<'fut, eyre::Result<()>> {
+        Box::pin(async move {
+            let mut key = Vec::with_capacity(
+                self.key
+                    .len_hint_from_slice(slice)
+                    .wrap_err("Failed estimating key length")?,
+            );
+            let do_unindex = self
+                .key
+                .extract_key_from_slice(slice, &mut key)
+                .wrap_err("Failed extracting key from slice")?;
+            if do_unindex {
+                transaction.delete(&cfs[0], &key).await.wrap_err_with(|| {
+                    CfOperationError::new("Failed deleting key from", cfs[0].name())
+                })?;
+            }
+            Ok(())
+        })
+    }
+
+    fn rebuild<'fut, 't>(
+        &'fut self,
+        transaction: &'fut B::Transaction<'t>,
+        index_cfs: &'fut [B::TransactionCf<'t>],
+        datum_cf: &'fut B::TransactionCf<'t>,
+    ) -> waaa::BoxFuture<'fut, eyre::Result<()>> {
+        Box::pin(async move {
+            indexer::default_rebuild::<B, Self>(self, transaction, index_cfs, datum_cf).await
+        })
+    }
+}
+
+pub struct UniqueQueryKey<'k, B>
+where
+    B: Backend,
+{
+    key: B::Key<'k>,
+    object_key: B::Value<'k>,
+}
+
+impl<B> UniqueQueryKey<'_, B>
+where
+    B: Backend,
+{
+    /// Opaque cursor to resume the query after this result, with `BTreeQuery::after`
+    pub fn cursor(&self) -> Vec<u8> {
+        self.key.as_ref().to_vec()
+    }
+}
+
+impl<B> AsRef<[u8]> for UniqueQueryKey<'_, B>
+where
+    B: Backend,
+{
+    fn as_ref(&self) -> &[u8] {
+        self.object_key.as_ref()
+    }
+}
+
+#[warn(clippy::missing_trait_methods)]
+impl<B, K> Index<B> for UniqueBTreeIndex<K>
+where
+    B: Backend,
+    K: Key,
+{
+    type Query<'q> = BTreeQuery<'q, K>;
+    type QueryKey<'k> = UniqueQueryKey<'k, B>;
+
+    fn query<'q, 'op: 'q, 't: 'op>(
+        &'q self,
+        query: &'q Self::Query<'q>,
+        transaction: &'op B::Transaction<'t>,
+        object_cf: &'op B::TransactionCf<'t>,
+        cfs: &'op [B::TransactionCf<'t>],
+    ) -> waaa::BoxStream<'q, eyre::Result<(Self::QueryKey<'op>, B::Value<'op>)>> {
+        Box::pin(
+            self.query_keys(query, transaction, object_cf, cfs)
+                .and_then(async |object_key| {
+                    let object_value = transaction
+                        .get(object_cf, object_key.as_ref())
+                        .await
+                        .and_then(|value| {
+                            value.ok_or_else(|| {
+                                eyre!("Object was present in index but not in real table")
+                            })
+                        })
+                        .wrap_err_with(|| {
+                            CfOperationError::new("Failed getting object", object_cf.name())
+                        })?;
+                    Ok((object_key, object_value))
+                }),
+        )
+    }
+
+    fn query_keys<'q, 'op: 'q, 't: 'op>(
+        &'q self,
+        query: &'q Self::Query<'q>,
+        transaction: &'op B::Transaction<'t>,
+        _object_cf: &'op B::TransactionCf<'t>,
+        cfs: &'op [B::TransactionCf<'t>],
+    ) -> waaa::BoxStream<'q, eyre::Result<Self::QueryKey<'op>>> {
+        Box::pin(
+            query
+                .scan::<B>(&self.key, transaction, &cfs[0])
+                .map_ok(|(key, object_key)| UniqueQueryKey { key, object_key }),
+        )
+    }
+
+    fn count<'q, 'op: 'q, 't: 'op>(
+        &'q self,
+        query: &'q Self::Query<'q>,
+        transaction: &'op B::Transaction<'t>,
+        _object_cf: &'op B::TransactionCf<'t>,
+        cfs: &'op [B::TransactionCf<'t>],
+    ) -> waaa::BoxFuture<'q, eyre::Result<usize>> {
+        Box::pin(
+            query
+                .scan::<B>(&self.key, transaction, &cfs[0])
+                .try_fold(0, |count, _| future::ready(Ok(count + 1))),
+        )
+    }
+}
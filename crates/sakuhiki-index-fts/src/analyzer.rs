@@ -0,0 +1,134 @@
+/// Splits a text into tokens.
+pub trait Tokenizer: 'static + Send + Sync {
+    /// Appends the tokens of `text` to `tokens`, in order.
+    fn tokenize<'t>(&self, text: &'t str, tokens: &mut Vec<&'t str>);
+
+    /// Describes the tokenizer, for inclusion in the fingerprint of indexes using it.
+    ///
+    /// It must change whenever the produced tokens change, and be stable across builds
+    /// otherwise: in particular, it must not rely on `std::any::type_name`.
+    fn fingerprint(&self) -> String;
+}
+
+/// Transforms tokens into the terms that actually get indexed.
+pub trait Normalizer: 'static + Send + Sync {
+    /// Returns the normalized form of `token`, or `None` if it must not be indexed.
+    fn normalize(&self, token: String) -> Option<String>;
+
+    /// Describes the normalizer, for inclusion in the fingerprint of indexes using it.
+    ///
+    /// It must change whenever the produced terms change, and be stable across builds
+    /// otherwise: in particular, it must not rely on `std::any::type_name`.
+    fn fingerprint(&self) -> String;
+}
+
+/// Tokenizer that splits on all the non-alphanumeric characters.
+pub struct SimpleTokenizer;
+
+impl Tokenizer for SimpleTokenizer {
+    fn tokenize<'t>(&self, text: &'t str, tokens: &mut Vec<&'t str>) {
+        tokens.extend(
+            text.split(|c: char| !c.is_alphanumeric())
+                .filter(|t| !t.is_empty()),
+        );
+    }
+
+    fn fingerprint(&self) -> String {
+        String::from("simple")
+    }
+}
+
+/// Normalizer that lowercases all the tokens.
+pub struct Lowercase;
+
+impl Normalizer for Lowercase {
+    fn normalize(&self, token: String) -> Option<String> {
+        Some(token.to_lowercase())
+    }
+
+    fn fingerprint(&self) -> String {
+        String::from("lowercase")
+    }
+}
+
+/// Normalizer that drops the tokens that are in a list of words.
+///
+/// It must come after the normalizers that make tokens match the list, eg. `Lowercase`.
+pub struct StopWords {
+    words: &'static [&'static str],
+}
+
+impl StopWords {
+    pub const fn new(words: &'static [&'static str]) -> Self {
+        Self { words }
+    }
+}
+
+impl Normalizer for StopWords {
+    fn normalize(&self, token: String) -> Option<String> {
+        (!self.words.contains(&token.as_str())).then_some(token)
+    }
+
+    fn fingerprint(&self) -> String {
+        format!("stop-words{:?}", self.words)
+    }
+}
+
+/// Pipeline turning a text into the terms to index, along with their positions.
+///
+/// The same analyzer is used for the indexed texts and for the queries.
+pub struct Analyzer {
+    tokenizer: &'static dyn Tokenizer,
+    normalizers: &'static [&'static dyn Normalizer],
+}
+
+impl Analyzer {
+    /// `normalizers` are applied in order to each token produced by `tokenizer`
+    pub const fn new(
+        tokenizer: &'static dyn Tokenizer,
+        normalizers: &'static [&'static dyn Normalizer],
+    ) -> Self {
+        Self {
+            tokenizer,
+            normalizers,
+        }
+    }
+
+    /// Analyzer that splits on non-alphanumeric characters and lowercases the tokens
+    pub const fn simple() -> Self {
+        Self::new(&SimpleTokenizer, &[&Lowercase])
+    }
+
+    /// Describes the tokenizer and normalizers, for inclusion in the fingerprint of indexes
+    pub fn fingerprint(&self) -> String {
+        let normalizers = self
+            .normalizers
+            .iter()
+            .map(|n| n.fingerprint())
+            .collect::<Vec<_>>();
+        format!(
+            "{}[{}]",
+            self.tokenizer.fingerprint(),
+            normalizers.join(", ")
+        )
+    }
+
+    /// Returns the terms of `text`, along with their positions
+    ///
+    /// Dropped tokens leave a gap in the positions, so that phrases do not match over them.
+    pub fn analyze(&self, text: &str) -> Vec<(String, u32)> {
+        let mut tokens = Vec::new();
+        self.tokenizer.tokenize(text, &mut tokens);
+        tokens
+            .into_iter()
+            .zip(0..)
+            .filter_map(|(token, position)| {
+                let term = self
+                    .normalizers
+                    .iter()
+                    .try_fold(token.to_string(), |token, n| n.normalize(token))?;
+                Some((term, position))
+            })
+            .collect()
+    }
+}
@@ -0,0 +1,415 @@
+use std::collections::{BTreeMap, BTreeSet};
+
+use eyre::{WrapErr as _, eyre};
+use futures_util::{StreamExt as _, TryStreamExt as _, stream};
+use sakuhiki_core::{
+    Backend, CfOperationError, Datum, Index, Indexer,
+    backend::{BackendCf as _, Transaction as _},
+    indexer,
+};
+
+use crate::{
+    Analyzer, FtsQuery,
+    postings::{
+        DOCS, TERMS, decode_positions, decode_u32, decode_u64, encode_positions, posting_key,
+        stat_key, term_prefix,
+    },
+    query::{Plan, Scorer},
+};
+
+pub type TextExtractor<D> = for<'d> fn(&'d D) -> &'d str;
+
+/// Full-text search index, storing an inverted index in its CFs
+///
+/// Its CFs are, in order: the postings, the lengths of the objects, and the statistics used for
+/// ranking.
+///
+/// The statistics are global to the index, but sharded by object key: two transactions writing
+/// objects of the same index only conflict on them if the objects fall into the same shard.
+pub struct FtsIndex<D>
+where
+    D: Datum,
+{
+    cfs: &'static [&'static str; 3],
+    analyzer: Analyzer,
+    text: TextExtractor<D>,
+    version: u64,
+}
+
+impl<D> FtsIndex<D>
+where
+    D: Datum,
+{
+    /// `text` returns the text of `&D` to index, which gets analyzed by `analyzer`
+    pub const fn new(
+        cfs: &'static [&'static str; 3],
+        analyzer: Analyzer,
+        text: TextExtractor<D>,
+    ) -> Self {
+        Self::new_versioned(cfs, analyzer, text, 0)
+    }
+
+    /// Same as `new`, but with a version for the text analysis, which `new` sets to 0
+    ///
+    /// The version must be bumped whenever `analyzer` or `text` change the terms they produce,
+    /// so that the index gets rebuilt upon building the `Db`.
+    pub const fn new_versioned(
+        cfs: &'static [&'static str; 3],
+        analyzer: Analyzer,
+        text: TextExtractor<D>,
+        version: u64,
+    ) -> Self {
+        Self {
+            cfs,
+            analyzer,
+            text,
+            version,
+        }
+    }
+
+    /// Returns the key and score of all the objects matching `query`, best matches first
+    async fn search<'t, B>(
+        &self,
+        query: &FtsQuery,
+        transaction: &B::Transaction<'t>,
+        cfs: &[B::TransactionCf<'t>],
+    ) -> eyre::Result<Vec<(Vec<u8>, f64)>>
+    where
+        B: Backend,
+    {
+        // TODO(med): this loads all the postings of all the terms, which could be avoided for
+        // eg. conjunctions or top-k queries
+        let plan = Plan::new(query, &self.analyzer);
+        let mut terms = BTreeSet::new();
+        plan.terms(&mut terms);
+        let mut postings = BTreeMap::new();
+        for term in terms {
+            let prefix = term_prefix(term);
+            let mut term_postings = BTreeMap::new();
+            let mut entries = transaction.scan_prefix(&cfs[0], &prefix);
+            while let Some(entry) = entries.next().await {
+                let (key, positions) = entry.wrap_err_with(|| {
+                    CfOperationError::new("Failed scanning postings in", cfs[0].name())
+                })?;
+                term_postings.insert(
+                    key.as_ref()[prefix.len()..].to_vec(),
+                    decode_positions(positions.as_ref())?,
+                );
+            }
+            postings.insert(term.to_string(), term_postings);
+        }
+        let mut lengths = BTreeMap::new();
+        for object_key in postings.values().flat_map(|p| p.keys()) {
+            if lengths.contains_key(object_key) {
+                continue;
+            }
+            let length = transaction
+                .get(&cfs[1], object_key)
+                .await
+                .wrap_err_with(|| {
+                    CfOperationError::new("Failed getting length from", cfs[1].name())
+                })?
+                .map(|l| decode_u32(l.as_ref()))
+                .transpose()?
+                .unwrap_or(0);
+            lengths.insert(object_key.clone(), length);
+        }
+        let docs = get_stat::<B>(DOCS, transaction, &cfs[2]).await?;
+        let total_terms = get_stat::<B>(TERMS, transaction, &cfs[2]).await?;
+        let scorer = Scorer::new(postings, lengths, docs, total_terms);
+        let mut results = plan.evaluate(&scorer).into_iter().collect::<Vec<_>>();
+        results.sort_by(|(key_a, a), (key_b, b)| b.total_cmp(a).then_with(|| key_a.cmp(key_b)));
+        Ok(results)
+    }
+}
+
+/// Returns the positions of each term in `terms`
+fn group_terms(terms: &[(String, u32)]) -> BTreeMap<&str, Vec<u32>> {
+    let mut grouped = BTreeMap::<_, Vec<_>>::new();
+    for (term, position) in terms {
+        grouped.entry(term.as_str()).or_default().push(*position);
+    }
+    grouped
+}
+
+/// Returns the value of `stat`, summed over all its shards
+async fn get_stat<'t, B>(
+    stat: &[u8],
+    transaction: &B::Transaction<'t>,
+    cf: &B::TransactionCf<'t>,
+) -> eyre::Result<u64>
+where
+    B: Backend,
+{
+    transaction
+        .scan_prefix(cf, stat)
+        .map(|res| {
+            res.wrap_err_with(|| CfOperationError::new("Failed getting statistics from", cf.name()))
+        })
+        .try_fold(
+            0,
+            |sum, (_, v)| async move { Ok(sum + decode_u64(v.as_ref())?) },
+        )
+        .await
+}
+
+/// Adds `delta` to the shard of `stat` that `object_key` gets counted in
+async fn add_to_stat<'t, B>(
+    stat: &[u8],
+    object_key: &[u8],
+    delta: i64,
+    transaction: &B::Transaction<'t>,
+    cf: &B::TransactionCf<'t>,
+) -> eyre::Result<()>
+where
+    B: Backend,
+{
+    let key = stat_key(stat, object_key);
+    let value = transaction
+        .get(cf, &key)
+        .await
+        .wrap_err_with(|| CfOperationError::new("Failed getting statistics from", cf.name()))?
+        .map(|v| decode_u64(v.as_ref()))
+        .transpose()?
+        .unwrap_or(0)
+        .checked_add_signed(delta)
+        .ok_or_else(|| eyre::eyre!("Statistic {key:?} would become negative"))?;
+    transaction
+        .put(cf, &key, &value.to_be_bytes())
+        .await
+        .wrap_err_with(|| CfOperationError::new("Failed putting statistics into", cf.name()))?;
+    Ok(())
+}
+
+#[warn(clippy::missing_trait_methods)]
+impl<B, D> Indexer<B> for FtsIndex<D>
+where
+    B: Backend,
+    D: Datum,
+{
+    type Datum = D;
+
+    fn cfs(&self) -> &'static [&'static str] {
+        self.cfs
+    }
+
+    fn foreign_cfs(&self) -> Vec<&'static str> {
+        Vec::new()
+    }
+
+    fn fingerprint(&self) -> Vec<u8> {
+        format!("fts/{}/v{}", self.analyzer.fingerprint(), self.version).into_bytes()
+    }
+
+    fn check_write<'fut, 't>(
+        &'fut self,
+        _object_key: &'fut [u8],
+        _new: Option<&'fut Self::Datum>,
+        _transaction: &'fut B::Transaction<'t>,
+        _cfs: &'fut [B::TransactionCf<'t>],
+    ) -> waaa::BoxFuture<'fut, eyre::Result<()>> {
+        Box::pin(async { Ok(()) })
+    }
+
+    fn index<'fut, 't>(
+        &'fut self,
+        object_key: &'fut [u8],
+        datum: &'fut Self::Datum,
+        transaction: &'fut B::Transaction<'t>,
+        cfs: &'fut [B::TransactionCf<'t>],
+    ) -> waaa::BoxFuture<'fut, eyre::Result<()>> {
+        Box::pin(async move {
+            let terms = self.analyzer.analyze((self.text)(datum));
+            for (term, positions) in group_terms(&terms) {
+                transaction
+                    .put(
+                        &cfs[0],
+                        &posting_key(term, object_key),
+                        &encode_positions(&positions),
+                    )
+                    .await
+                    .wrap_err_with(|| {
+                        CfOperationError::new("Failed putting posting into", cfs[0].name())
+                    })?;
+            }
+            let length = u32::try_from(terms.len()).wrap_err("Text has too many terms")?;
+            transaction
+                .put(&cfs[1], object_key, &length.to_be_bytes())
+                .await
+                .wrap_err_with(|| {
+                    CfOperationError::new("Failed putting length into", cfs[1].name())
+                })?;
+            add_to_stat::<B>(DOCS, object_key, 1, transaction, &cfs[2]).await?;
+            add_to_stat::<B>(TERMS, object_key, i64::from(length), transaction, &cfs[2]).await?;
+            Ok(())
+        })
+    }
+
+    fn unindex<'fut, 't>(
+        &'fut self,
+        object_key: &'fut [u8],
+        datum: &'fut Self::Datum,
+        transaction: &'fut B::Transaction<'t>,
+        cfs: &'fut [B::TransactionCf<'t>],
+    ) -> waaa::BoxFuture<'fut, eyre::Result<()>> {
+        Box::pin(async move {
+            let terms = self.analyzer.analyze((self.text)(datum));
+            for term in group_terms(&terms).into_keys() {
+                transaction
+                    .delete(&cfs[0], &posting_key(term, object_key))
+                    .await
+                    .wrap_err_with(|| {
+                        CfOperationError::new("Failed deleting posting from", cfs[0].name())
+                    })?;
+            }
+            let length = u32::try_from(terms.len()).wrap_err("Text has too many terms")?;
+            transaction
+                .delete(&cfs[1], object_key)
+                .await
+                .wrap_err_with(|| {
+                    CfOperationError::new("Failed deleting length from", cfs[1].name())
+                })?;
+            add_to_stat::<B>(DOCS, object_key, -1, transaction, &cfs[2]).await?;
+            add_to_stat::<B>(TERMS, object_key, -i64::from(length), transaction, &cfs[2]).await?;
+            Ok(())
+        })
+    }
+
+    fn reindex<'fut, 't>(
+        &'fut self,
+        object_key: &'fut [u8],
+        old: &'fut Self::Datum,
+        new: &'fut Self::Datum,
+        transaction: &'fut B::Transaction<'t>,
+        cfs: &'fut [B::TransactionCf<'t>],
+    ) -> waaa::BoxFuture<'fut, eyre::Result<()>> {
+        Box::pin(async move {
+            // TODO(low): only rewrite the postings that changed
+            Indexer::<B>::unindex(self, object_key, old, transaction, cfs).await?;
+            Indexer::<B>::index(self, object_key, new, transaction, cfs).await
+        })
+    }
+
+    fn index_from_slice<'fut, 't>(
+        &'fut self,
+        object_key: &'fut [u8],
+        slice: &'fut [u8],
+        transaction: &'fut B::Transaction<'t>,
+        cfs: &'fut [B::TransactionCf<'t>],
+    ) -> waaa::BoxFuture<'fut, eyre::Result<()>> {
+        Box::pin(async move {
+            let datum = D::from_slice(slice).wrap_err("Failed to parse datum")?;
+            Indexer::<B>::index(self, object_key, &datum, transaction, cfs).await
+        })
+    }
+
+    fn unindex_from_slice<'fut, 't>(
+        &'fut self,
+        object_key: &'fut [u8],
+        slice: &'fut [u8],
+        transaction: &'fut B::Transaction<'t>,
+        cfs: &'fut [B::TransactionCf<'t>],
+    ) -> waaa::BoxFuture<'fut, eyre::Result<()>> {
+        Box::pin(async move {
+            let datum = D::from_slice(slice).wrap_err("Failed to parse datum")?;
+            Indexer::<B>::unindex(self, object_key, &datum, transaction, cfs).await
+        })
+    }
+
+    fn rebuild<'fut, 't>(
+        &'fut self,
+        transaction: &'fut B::Transaction<'t>,
+        index_cfs: &'fut [B::TransactionCf<'t>],
+        datum_cf: &'fut B::TransactionCf<'t>,
+    ) -> waaa::BoxFuture<'fut, eyre::Result<()>> {
+        Box::pin(async move {
+            indexer::default_rebuild::<B, Self>(self, transaction, index_cfs, datum_cf).await
+        })
+    }
+}
+
+/// Key of an object matching an `FtsQuery`, along with its score
+pub struct FtsQueryKey {
+    object_key: Vec<u8>,
+    score: f64,
+}
+
+impl FtsQueryKey {
+    /// BM25 score of the object for the query, higher being better
+    pub fn score(&self) -> f64 {
+        self.score
+    }
+}
+
+impl AsRef<[u8]> for FtsQueryKey {
+    fn as_ref(&self) -> &[u8] {
+        &self.object_key
+    }
+}
+
+#[warn(clippy::missing_trait_methods)]
+impl<B, D> Index<B> for FtsIndex<D>
+where
+    B: Backend,
+    D: Datum,
+{
+    type Query<'q> = FtsQuery;
+    type QueryKey<'k> = FtsQueryKey;
+
+    fn query<'q, 'op: 'q, 't: 'op>(
+        &'q self,
+        query: &'q Self::Query<'q>,
+        transaction: &'op B::Transaction<'t>,
+        object_cf: &'op B::TransactionCf<'t>,
+        cfs: &'op [B::TransactionCf<'t>],
+    ) -> waaa::BoxStream<'q, eyre::Result<(Self::QueryKey<'op>, B::Value<'op>)>> {
+        Box::pin(
+            Index::<B>::query_keys(self, query, transaction, object_cf, cfs).and_then(
+                async |object_key| {
+                    let object_value = transaction
+                        .get(object_cf, object_key.as_ref())
+                        .await
+                        .and_then(|value| {
+                            value.ok_or_else(|| {
+                                eyre!("Object was present in index but not in real table")
+                            })
+                        })
+                        .wrap_err_with(|| {
+                            CfOperationError::new("Failed getting object", object_cf.name())
+                        })?;
+                    Ok((object_key, object_value))
+                },
+            ),
+        )
+    }
+
+    fn query_keys<'q, 'op: 'q, 't: 'op>(
+        &'q self,
+        query: &'q Self::Query<'q>,
+        transaction: &'op B::Transaction<'t>,
+        _object_cf: &'op B::TransactionCf<'t>,
+        cfs: &'op [B::TransactionCf<'t>],
+    ) -> waaa::BoxStream<'q, eyre::Result<Self::QueryKey<'op>>> {
+        Box::pin(
+            stream::once(self.search::<B>(query, transaction, cfs))
+                .map_ok(|results| {
+                    stream::iter(
+                        results
+                            .into_iter()
+                            .map(|(object_key, score)| Ok(FtsQueryKey { object_key, score })),
+                    )
+                })
+                .try_flatten(),
+        )
+    }
+
+    fn count<'q, 'op: 'q, 't: 'op>(
+        &'q self,
+        query: &'q Self::Query<'q>,
+        transaction: &'op B::Transaction<'t>,
+        _object_cf: &'op B::TransactionCf<'t>,
+        cfs: &'op [B::TransactionCf<'t>],
+    ) -> waaa::BoxFuture<'q, eyre::Result<usize>> {
+        Box::pin(async move { Ok(self.search::<B>(query, transaction, cfs).await?.len()) })
+    }
+}
@@ -0,0 +1,13 @@
+mod analyzer;
+pub use analyzer::{Analyzer, Lowercase, Normalizer, SimpleTokenizer, StopWords, Tokenizer};
+
+mod index;
+pub use index::{FtsIndex, FtsQueryKey, TextExtractor};
+
+mod postings;
+
+mod query;
+pub use query::FtsQuery;
+
+#[cfg(test)]
+mod tests;
@@ -0,0 +1,71 @@
+//! Layout of the FTS index CFs
+//!
+//! - The postings CF maps `escape(term) ++ object_key` to the positions of `term` in the object,
+//!   each as a big-endian `u32`.
+//! - The lengths CF maps `object_key` to the number of terms in the object, as a big-endian `u32`.
+//! - The statistics CF maps `DOCS ++ shard` and `TERMS ++ shard` to the number of indexed objects
+//!   and the total number of terms in them, as big-endian `u64`s. Each statistic is the sum of
+//!   its `STAT_SHARDS` shards, see `stat_key`.
+
+use eyre::eyre;
+use sakuhiki_index_btree::escape;
+
+pub(crate) const DOCS: &[u8] = b"docs";
+pub(crate) const TERMS: &[u8] = b"terms";
+
+/// Number of keys each statistic is spread over
+pub(crate) const STAT_SHARDS: u8 = 16;
+
+/// Returns the key of the shard of statistic `stat` that `object_key` gets counted in
+///
+/// Spreading the statistics makes concurrent writes to different objects rarely conflict. Each
+/// object always gets counted in the same shard, so that no shard can become negative.
+pub(crate) fn stat_key(stat: &[u8], object_key: &[u8]) -> Vec<u8> {
+    // FNV-1a, as the shards are persisted and thus need a hash that never changes
+    let hash = object_key
+        .iter()
+        .fold(0xcbf2_9ce4_8422_2325_u64, |hash, &b| {
+            (hash ^ u64::from(b)).wrapping_mul(0x0100_0000_01b3)
+        });
+    let mut key = stat.to_vec();
+    key.push(hash.to_be_bytes()[7] % STAT_SHARDS);
+    key
+}
+
+/// Returns the prefix of all the postings of `term`
+pub(crate) fn term_prefix(term: &str) -> Vec<u8> {
+    escape(term.as_bytes())
+}
+
+/// Returns the key of the posting of `term` in `object_key`
+pub(crate) fn posting_key(term: &str, object_key: &[u8]) -> Vec<u8> {
+    let mut key = term_prefix(term);
+    key.extend_from_slice(object_key);
+    key
+}
+
+pub(crate) fn encode_positions(positions: &[u32]) -> Vec<u8> {
+    positions.iter().flat_map(|p| p.to_be_bytes()).collect()
+}
+
+pub(crate) fn decode_positions(value: &[u8]) -> eyre::Result<Vec<u32>> {
+    if value.len() % 4 != 0 {
+        return Err(eyre!("Posting has invalid length {}", value.len()));
+    }
+    Ok(value
+        .chunks_exact(4)
+        .map(|p| u32::from_be_bytes(p.try_into().unwrap()))
+        .collect())
+}
+
+pub(crate) fn decode_u32(value: &[u8]) -> eyre::Result<u32> {
+    Ok(u32::from_be_bytes(value.try_into().map_err(|_| {
+        eyre!("Expected a 4-byte value, got {value:?}")
+    })?))
+}
+
+pub(crate) fn decode_u64(value: &[u8]) -> eyre::Result<u64> {
+    Ok(u64::from_be_bytes(value.try_into().map_err(|_| {
+        eyre!("Expected an 8-byte value, got {value:?}")
+    })?))
+}
@@ -0,0 +1,215 @@
+use std::collections::{BTreeMap, BTreeSet};
+
+use crate::Analyzer;
+
+/// BM25 term frequency saturation
+const K1: f64 = 1.2;
+
+/// BM25 document length normalization
+const B: f64 = 0.75;
+
+/// Full-text query, whose texts get analyzed by the analyzer of the queried `FtsIndex`
+///
+/// Results are sorted by decreasing BM25 score, the score of a result being the sum of the scores
+/// of the terms that matched it.
+pub enum FtsQuery {
+    /// Matches the objects that contain all the terms of the text
+    Terms(String),
+
+    /// Matches the objects that contain all the terms of the text, next to each other and in order
+    Phrase(String),
+
+    /// Matches the objects that match all the queries
+    And(Vec<FtsQuery>),
+
+    /// Matches the objects that match any of the queries
+    Or(Vec<FtsQuery>),
+}
+
+impl FtsQuery {
+    pub fn terms(text: impl Into<String>) -> Self {
+        Self::Terms(text.into())
+    }
+
+    pub fn phrase(text: impl Into<String>) -> Self {
+        Self::Phrase(text.into())
+    }
+
+    pub fn and(queries: impl IntoIterator<Item = FtsQuery>) -> Self {
+        Self::And(queries.into_iter().collect())
+    }
+
+    pub fn or(queries: impl IntoIterator<Item = FtsQuery>) -> Self {
+        Self::Or(queries.into_iter().collect())
+    }
+}
+
+/// `FtsQuery` whose texts got analyzed
+///
+/// Empty `And`s and `Phrase`s match nothing.
+pub(crate) enum Plan {
+    Term(String),
+    Phrase(Vec<(String, u32)>),
+    And(Vec<Plan>),
+    Or(Vec<Plan>),
+}
+
+impl Plan {
+    pub(crate) fn new(query: &FtsQuery, analyzer: &Analyzer) -> Self {
+        match query {
+            FtsQuery::Terms(text) => Plan::And(
+                analyzer
+                    .analyze(text)
+                    .into_iter()
+                    .map(|(term, _)| Plan::Term(term))
+                    .collect(),
+            ),
+            FtsQuery::Phrase(text) => Plan::Phrase(analyzer.analyze(text)),
+            FtsQuery::And(queries) => {
+                Plan::And(queries.iter().map(|q| Plan::new(q, analyzer)).collect())
+            }
+            FtsQuery::Or(queries) => {
+                Plan::Or(queries.iter().map(|q| Plan::new(q, analyzer)).collect())
+            }
+        }
+    }
+
+    /// Adds to `terms` all the terms whose postings are needed to evaluate this plan
+    pub(crate) fn terms<'a>(&'a self, terms: &mut BTreeSet<&'a str>) {
+        match self {
+            Plan::Term(term) => {
+                terms.insert(term);
+            }
+            Plan::Phrase(phrase) => terms.extend(phrase.iter().map(|(term, _)| term.as_str())),
+            Plan::And(plans) | Plan::Or(plans) => plans.iter().for_each(|p| p.terms(terms)),
+        }
+    }
+
+    /// Returns the score of all the objects that match this plan
+    pub(crate) fn evaluate(&self, scorer: &Scorer) -> BTreeMap<Vec<u8>, f64> {
+        match self {
+            Plan::Term(term) => scorer.term_scores(term),
+            Plan::Phrase(phrase) => scorer.phrase_scores(phrase),
+            Plan::And(plans) => {
+                let mut results = plans.iter().map(|p| p.evaluate(scorer));
+                let Some(first) = results.next() else {
+                    return BTreeMap::new();
+                };
+                results.fold(first, |acc, scores| {
+                    acc.into_iter()
+                        .filter_map(|(key, score)| Some((key.clone(), score + scores.get(&key)?)))
+                        .collect()
+                })
+            }
+            Plan::Or(plans) => {
+                let mut acc = BTreeMap::new();
+                for scores in plans.iter().map(|p| p.evaluate(scorer)) {
+                    for (key, score) in scores {
+                        *acc.entry(key).or_insert(0.) += score;
+                    }
+                }
+                acc
+            }
+        }
+    }
+}
+
+/// Postings of each term: object key to positions of the term in the object
+pub(crate) type Postings = BTreeMap<String, BTreeMap<Vec<u8>, Vec<u32>>>;
+
+pub(crate) struct Scorer {
+    postings: Postings,
+    lengths: BTreeMap<Vec<u8>, u32>,
+    docs: u64,
+    avg_len: f64,
+}
+
+impl Scorer {
+    /// `lengths` must have the length of all the objects in `postings`, and `docs` and `terms` are
+    /// the number of indexed objects and of terms in them
+    pub(crate) fn new(
+        postings: Postings,
+        lengths: BTreeMap<Vec<u8>, u32>,
+        docs: u64,
+        terms: u64,
+    ) -> Self {
+        let avg_len = if docs == 0 {
+            1.
+        } else {
+            terms as f64 / docs as f64
+        };
+        Self {
+            postings,
+            lengths,
+            docs,
+            avg_len,
+        }
+    }
+
+    fn postings(&self, term: &str) -> Option<&BTreeMap<Vec<u8>, Vec<u32>>> {
+        self.postings.get(term)
+    }
+
+    fn idf(&self, term: &str) -> f64 {
+        let df = self.postings(term).map_or(0, |p| p.len()) as f64;
+        // `docs` can only be smaller than `df` if the statistics are out of sync
+        let docs = (self.docs as f64).max(df);
+        (1. + (docs - df + 0.5) / (df + 0.5)).ln()
+    }
+
+    fn score(&self, idf: f64, object_key: &[u8], positions: &[u32]) -> f64 {
+        let tf = positions.len() as f64;
+        let len = self.lengths.get(object_key).copied().unwrap_or(0) as f64;
+        idf * tf * (K1 + 1.) / (tf + K1 * (1. - B + B * len / self.avg_len))
+    }
+
+    fn term_scores(&self, term: &str) -> BTreeMap<Vec<u8>, f64> {
+        let Some(postings) = self.postings(term) else {
+            return BTreeMap::new();
+        };
+        let idf = self.idf(term);
+        postings
+            .iter()
+            .map(|(key, positions)| (key.clone(), self.score(idf, key, positions)))
+            .collect()
+    }
+
+    fn phrase_scores(&self, phrase: &[(String, u32)]) -> BTreeMap<Vec<u8>, f64> {
+        let Some(((first, first_pos), rest)) = phrase.split_first() else {
+            return BTreeMap::new();
+        };
+        let Some(candidates) = self.postings(first) else {
+            return BTreeMap::new();
+        };
+        let mut results = BTreeMap::new();
+        'candidates: for (key, starts) in candidates {
+            let mut rest_positions = Vec::with_capacity(rest.len());
+            for (term, _) in rest {
+                match self.postings(term).and_then(|p| p.get(key)) {
+                    Some(positions) => rest_positions.push(positions),
+                    None => continue 'candidates,
+                }
+            }
+            let matches = starts.iter().any(|start| {
+                rest.iter()
+                    .zip(&rest_positions)
+                    .all(|((_, pos), positions)| {
+                        positions
+                            .binary_search(&(start + (pos - first_pos)))
+                            .is_ok()
+                    })
+            });
+            if matches {
+                let score = phrase
+                    .iter()
+                    .map(|(term, _)| {
+                        let positions = &self.postings[term.as_str()][key];
+                        self.score(self.idf(term), key, positions)
+                    })
+                    .sum();
+                results.insert(key.clone(), score);
+            }
+        }
+        results
+    }
+}
@@ -0,0 +1,181 @@
+use futures_util::TryStreamExt as _;
+use sakuhiki_core::{Backend, Indexer, Mode};
+
+use crate::*;
+
+struct Doc(String);
+
+impl sakuhiki_core::Datum for Doc {
+    const CF: &'static str = "doc";
+
+    fn from_slice(datum: &[u8]) -> eyre::Result<Self> {
+        Ok(Self(String::from_utf8(datum.to_vec())?))
+    }
+
+    fn to_bytes(&self) -> eyre::Result<Vec<u8>> {
+        Ok(self.0.clone().into_bytes())
+    }
+}
+
+impl Doc {
+    const INDEX: &'static FtsIndex<Doc> = &FtsIndex::new(
+        &["doc-postings", "doc-lengths", "doc-stats"],
+        Analyzer::new(
+            &SimpleTokenizer,
+            &[&Lowercase, &StopWords::new(&["the", "a"])],
+        ),
+        |d| &d.0,
+    );
+}
+
+impl<B: Backend> sakuhiki_core::IndexedDatum<B> for Doc {
+    const INDEXES: &'static [&'static dyn Indexer<B, Datum = Self>] = &[Self::INDEX];
+}
+
+#[test]
+fn test_analyzer() {
+    const ANALYZER: Analyzer =
+        Analyzer::new(&SimpleTokenizer, &[&Lowercase, &StopWords::new(&["the"])]);
+    assert_eq!(
+        ANALYZER.analyze("The quick, brown fox!"),
+        [
+            (String::from("quick"), 1),
+            (String::from("brown"), 2),
+            (String::from("fox"), 3)
+        ]
+    );
+    assert!(Analyzer::simple().analyze(" ,;").is_empty());
+}
+
+#[test]
+fn test_stat_key() {
+    use crate::postings::{DOCS, STAT_SHARDS, TERMS, stat_key};
+
+    // The shards are persisted, so an object must always be counted in the same one
+    assert_eq!(stat_key(DOCS, b"1"), b"docs\x0c");
+    assert_eq!(stat_key(TERMS, b"1"), b"terms\x0c");
+    let shards = (0..100_u32)
+        .map(|i| *stat_key(DOCS, &i.to_be_bytes()).last().unwrap())
+        .collect::<std::collections::BTreeSet<_>>();
+    assert_eq!(shards.len(), usize::from(STAT_SHARDS));
+}
+
+#[test]
+fn test_fingerprint() {
+    assert_eq!(
+        Indexer::<sakuhiki_memdb::MemDb>::fingerprint(Doc::INDEX),
+        br#"fts/simple[lowercase, stop-words["the", "a"]]/v0"#
+    );
+}
+
+#[tokio::test]
+async fn test_fts_query() {
+    let db = sakuhiki_memdb::MemDb::builder()
+        .datum::<Doc>()
+        .build()
+        .await
+        .unwrap();
+    let doc = db.cf_handle::<Doc>().await.unwrap();
+    db.transaction(Mode::ReadWrite, &[&doc], |t, [doc]| {
+        Box::pin(async move {
+            for (key, text) in [
+                (b"1", "The quick brown fox jumps over the lazy dog"),
+                (b"2", "A brown dog"),
+                (b"3", "Quick thinking, quick acting"),
+                (b"4", "The dog is brown and quick"),
+            ] {
+                t.put_datum(&doc, key, &Doc(text.to_string())).await?;
+            }
+            let search = async |query: FtsQuery| -> Vec<Vec<u8>> {
+                t.query_keys(&doc, Doc::INDEX, &query)
+                    .unwrap()
+                    .map_ok(|k| k.as_ref().to_vec())
+                    .try_collect()
+                    .await
+                    .unwrap()
+            };
+            let sorted = async |query: FtsQuery| -> Vec<Vec<u8>> {
+                let mut keys = search(query).await;
+                keys.sort();
+                keys
+            };
+            // Terms are normalized, and the shortest text ranks first
+            assert_eq!(search(FtsQuery::terms("DOG")).await, [b"2", b"4", b"1"]);
+            assert_eq!(
+                sorted(FtsQuery::terms("brown dog")).await,
+                [b"1", b"2", b"4"]
+            );
+            assert_eq!(sorted(FtsQuery::terms("brown fox")).await, [b"1"]);
+            assert!(search(FtsQuery::terms("cat")).await.is_empty());
+            // Stop words are not indexed
+            assert!(search(FtsQuery::terms("the")).await.is_empty());
+            // Higher term frequency ranks first
+            assert_eq!(search(FtsQuery::terms("quick")).await[0], b"3");
+            assert_eq!(
+                sorted(FtsQuery::or([
+                    FtsQuery::terms("fox"),
+                    FtsQuery::terms("thinking")
+                ]))
+                .await,
+                [b"1", b"3"]
+            );
+            assert_eq!(
+                sorted(FtsQuery::and([
+                    FtsQuery::terms("quick"),
+                    FtsQuery::or([FtsQuery::terms("dog"), FtsQuery::terms("acting")])
+                ]))
+                .await,
+                [b"1", b"3", b"4"]
+            );
+            // Phrases need the terms in order and next to each other
+            assert_eq!(sorted(FtsQuery::phrase("brown dog")).await, [b"2"]);
+            assert_eq!(sorted(FtsQuery::phrase("quick brown")).await, [b"1"]);
+            assert!(search(FtsQuery::phrase("dog brown")).await.is_empty());
+            // Stop words leave gaps that phrases must match
+            assert_eq!(sorted(FtsQuery::phrase("over the lazy")).await, [b"1"]);
+            assert!(search(FtsQuery::phrase("over lazy")).await.is_empty());
+            assert_eq!(
+                t.count(&doc, Doc::INDEX, &FtsQuery::terms("brown")).await?,
+                3
+            );
+            // Scores are positive and sorted
+            let scores = t
+                .query(&doc, Doc::INDEX, &FtsQuery::terms("brown quick"))?
+                .map_ok(|(k, _)| k.score())
+                .try_collect::<Vec<_>>()
+                .await?;
+            assert_eq!(scores.len(), 2);
+            assert!(scores[0] >= scores[1] && scores[1] > 0.);
+            // Updating and deleting datums updates the index
+            t.put_datum(&doc, b"2", &Doc("A red cat".to_string()))
+                .await?;
+            assert_eq!(search(FtsQuery::terms("cat")).await, [b"2"]);
+            assert_eq!(sorted(FtsQuery::terms("dog")).await, [b"1", b"4"]);
+            t.delete::<Doc>(&doc, b"1").await?;
+            assert_eq!(search(FtsQuery::terms("dog")).await, [b"4"]);
+            Ok(())
+        })
+    })
+    .await
+    .unwrap();
+    // Rebuilding reindexes all the datums and resets the statistics
+    db.rebuild_index(Doc::INDEX).await.unwrap();
+    let doc = db.cf_handle::<Doc>().await.unwrap();
+    db.transaction(Mode::ReadWrite, &[&doc], |t, [doc]| {
+        Box::pin(async move {
+            let keys = t
+                .query_keys(&doc, Doc::INDEX, &FtsQuery::terms("quick"))?
+                .map_ok(|k| k.as_ref().to_vec())
+                .try_collect::<Vec<_>>()
+                .await?;
+            assert_eq!(keys, [b"3", b"4"]);
+            // The statistics were rebuilt along with the postings, so they do not underflow
+            for key in [b"2", b"3", b"4"] {
+                t.delete::<Doc>(&doc, key).await?;
+            }
+            Ok(())
+        })
+    })
+    .await
+    .unwrap();
+}
@@ -0,0 +1,89 @@
+//! Z-order cells
+//!
+//! Latitudes and longitudes are quantized to `u32`s, whose bits get interleaved into a `u64`.
+//! The cells at level `l` are the `2^l * 2^l` squares of the grid whose `l` upper bits of both
+//! coordinates are the same, and each of them spans a contiguous range of the `u64`s.
+
+use crate::Point;
+
+/// Maximum number of cells a query gets expanded into, before merging adjacent ones
+const MAX_CELLS: u64 = 16;
+
+fn quantize(value: f64, min: f64, max: f64) -> u32 {
+    let scaled = (value - min) / (max - min) * 2f64.powi(32);
+    // `as` saturates, which clamps out-of-range values to the edges of the grid
+    scaled as u32
+}
+
+fn quantize_lat(lat: f64) -> u32 {
+    quantize(lat, -90., 90.)
+}
+
+fn quantize_lon(lon: f64) -> u32 {
+    quantize(lon, -180., 180.)
+}
+
+/// Spreads the bits of `x` to the even bits of the result
+fn spread(x: u32) -> u64 {
+    let mut x = u64::from(x);
+    x = (x | (x << 16)) & 0x0000_FFFF_0000_FFFF;
+    x = (x | (x << 8)) & 0x00FF_00FF_00FF_00FF;
+    x = (x | (x << 4)) & 0x0F0F_0F0F_0F0F_0F0F;
+    x = (x | (x << 2)) & 0x3333_3333_3333_3333;
+    x = (x | (x << 1)) & 0x5555_5555_5555_5555;
+    x
+}
+
+fn interleave(lat: u32, lon: u32) -> u64 {
+    (spread(lat) << 1) | spread(lon)
+}
+
+/// Returns the cell of `point` at the finest level
+pub(crate) fn cell(point: &Point) -> u64 {
+    interleave(quantize_lat(point.lat), quantize_lon(point.lon))
+}
+
+/// Returns the inclusive ranges of finest-level cells that cover the box from `min` to `max`
+///
+/// `min` must be south-west of `max`, without crossing the antimeridian. If it is not, the box
+/// is empty and so is the cover.
+pub(crate) fn cover(min: &Point, max: &Point) -> Vec<(u64, u64)> {
+    let (lat0, lat1) = (quantize_lat(min.lat), quantize_lat(max.lat));
+    let (lon0, lon1) = (quantize_lon(min.lon), quantize_lon(max.lon));
+    if lat0 > lat1 || lon0 > lon1 {
+        return Vec::new();
+    }
+    // Pick the finest level at which the box spans at most `MAX_CELLS` cells
+    let shift = (0..32)
+        .find(|shift| {
+            let lats = u64::from((lat1 >> shift) - (lat0 >> shift)) + 1;
+            let lons = u64::from((lon1 >> shift) - (lon0 >> shift)) + 1;
+            lats.saturating_mul(lons) <= MAX_CELLS
+        })
+        .unwrap_or(31);
+    let cell_size = 1u64 << (2 * shift);
+    let mut ranges = Vec::new();
+    for lat in (lat0 >> shift)..=(lat1 >> shift) {
+        for lon in (lon0 >> shift)..=(lon1 >> shift) {
+            let start = interleave(lat << shift, lon << shift);
+            ranges.push((start, start + (cell_size - 1)));
+        }
+    }
+    merge(&mut ranges);
+    ranges
+}
+
+/// Sorts `ranges` and merges the overlapping or adjacent ones
+pub(crate) fn merge(ranges: &mut Vec<(u64, u64)>) {
+    ranges.sort_unstable();
+    let mut merged: Vec<(u64, u64)> = Vec::with_capacity(ranges.len());
+    for &(start, end) in ranges.iter() {
+        match merged.last_mut() {
+            Some((_, last_end)) if start <= last_end.saturating_add(1) => {
+                *last_end = (*last_end).max(end);
+            }
+            _ => merged.push((start, end)),
+        }
+    }
+    *ranges = merged;
+}
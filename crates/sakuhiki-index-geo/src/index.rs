@@ -0,0 +1,338 @@
+use std::ops::Bound;
+
+use eyre::{WrapErr as _, eyre};
+use futures_util::{StreamExt as _, TryStreamExt as _, future, stream};
+use sakuhiki_core::{
+    Backend, CfOperationError, Datum, Index, Indexer,
+    backend::{BackendCf as _, Transaction as _},
+    indexer,
+};
+
+use crate::{GeoQuery, Point, cell};
+
+pub type PointExtractor<D> = fn(&D) -> Option<Point>;
+pub type PointExtractorFromSlice = fn(&[u8]) -> eyre::Result<Option<Point>>;
+
+/// Length of the cell prefix of the index keys
+const CELL_LEN: usize = 8;
+
+/// Geospatial index, storing the Z-order cell of each point followed by the object key
+pub struct GeoIndex<D>
+where
+    D: Datum,
+{
+    cf: &'static [&'static str; 1],
+    extractor: PointExtractor<D>,
+    extractor_from_slice: Option<PointExtractorFromSlice>,
+    version: u64,
+}
+
+impl<D> GeoIndex<D>
+where
+    D: Datum,
+{
+    /// `extractor` returns the location of `&D`, or `None` if it must not be indexed
+    pub const fn new(
+        cf: &'static [&'static str; 1],
+        extractor: PointExtractor<D>,
+        extractor_from_slice: Option<PointExtractorFromSlice>,
+    ) -> Self {
+        Self::new_versioned(cf, extractor, extractor_from_slice, 0)
+    }
+
+    /// Same as `new`, but with a version for the point extraction, which `new` sets to 0
+    ///
+    /// The version must be bumped whenever `extractor` changes the points it extracts, so that
+    /// the index gets rebuilt upon building the `Db`.
+    pub const fn new_versioned(
+        cf: &'static [&'static str; 1],
+        extractor: PointExtractor<D>,
+        extractor_from_slice: Option<PointExtractorFromSlice>,
+        version: u64,
+    ) -> Self {
+        Self {
+            cf,
+            extractor,
+            extractor_from_slice,
+            version,
+        }
+    }
+
+    fn extract_from_slice(&self, slice: &[u8]) -> eyre::Result<Option<Point>> {
+        if let Some(extractor_from_slice) = self.extractor_from_slice {
+            (extractor_from_slice)(slice)
+        } else {
+            let datum = D::from_slice(slice).wrap_err("Failed to parse datum")?;
+            Ok((self.extractor)(&datum))
+        }
+    }
+
+    /// Returns the objects matching `query`, after filtering out the false positives of the cells
+    fn matches<'q, 'op: 'q, 't: 'op, B: Backend>(
+        &'q self,
+        query: &'q GeoQuery,
+        transaction: &'op B::Transaction<'t>,
+        object_cf: &'op B::TransactionCf<'t>,
+        cfs: &'op [B::TransactionCf<'t>],
+    ) -> waaa::BoxStream<'q, eyre::Result<(GeoQueryKey<'op, B>, B::Value<'op>)>> {
+        Box::pin(
+            stream::iter(query.cell_ranges())
+                .flat_map(move |(start, end)| {
+                    let end = match end.checked_add(1) {
+                        Some(end) => Bound::Excluded(end.to_be_bytes().to_vec()),
+                        None => Bound::Unbounded,
+                    };
+                    transaction.scan(
+                        &cfs[0],
+                        (Bound::Included(start.to_be_bytes().to_vec()), end),
+                    )
+                })
+                .map(|res| {
+                    res.wrap_err_with(|| CfOperationError::new("Failed scanning", cfs[0].name()))
+                })
+                .and_then(async |(key, _)| {
+                    let object_key = GeoQueryKey { key };
+                    let object_value = transaction
+                        .get(object_cf, object_key.as_ref())
+                        .await
+                        .and_then(|value| {
+                            value.ok_or_else(|| {
+                                eyre!("Object was present in index but not in real table")
+                            })
+                        })
+                        .wrap_err_with(|| {
+                            CfOperationError::new("Failed getting object", object_cf.name())
+                        })?;
+                    Ok((object_key, object_value))
+                })
+                .try_filter_map(async |(object_key, object_value)| {
+                    let point = self
+                        .extract_from_slice(object_value.as_ref())
+                        .wrap_err("Failed extracting point from slice")?;
+                    let matches = point.is_some_and(|p| query.contains(&p));
+                    Ok(matches.then_some((object_key, object_value)))
+                }),
+        )
+    }
+}
+
+fn index_key(point: &Point, object_key: &[u8]) -> Vec<u8> {
+    let mut key = Vec::with_capacity(CELL_LEN + object_key.len());
+    key.extend_from_slice(&cell::cell(point).to_be_bytes());
+    key.extend_from_slice(object_key);
+    key
+}
+
+async fn put_entry<'t, B>(
+    point: Option<Point>,
+    object_key: &[u8],
+    transaction: &B::Transaction<'t>,
+    cf: &B::TransactionCf<'t>,
+) -> eyre::Result<()>
+where
+    B: Backend,
+{
+    if let Some(point) = point {
+        transaction
+            .put(cf, &index_key(&point, object_key), &[])
+            .await
+            .wrap_err_with(|| CfOperationError::new("Failed putting key into", cf.name()))?;
+    }
+    Ok(())
+}
+
+async fn delete_entry<'t, B>(
+    point: Option<Point>,
+    object_key: &[u8],
+    transaction: &B::Transaction<'t>,
+    cf: &B::TransactionCf<'t>,
+) -> eyre::Result<()>
+where
+    B: Backend,
+{
+    if let Some(point) = point {
+        transaction
+            .delete(cf, &index_key(&point, object_key))
+            .await
+            .wrap_err_with(|| CfOperationError::new("Failed deleting key from", cf.name()))?;
+    }
+    Ok(())
+}
+
+#[warn(clippy::missing_trait_methods)]
+impl<B, D> Indexer<B> for GeoIndex<D>
+where
+    B: Backend,
+    D: Datum,
+{
+    type Datum = D;
+
+    fn cfs(&self) -> &'static [&'static str] {
+        self.cf
+    }
+
+    fn foreign_cfs(&self) -> Vec<&'static str> {
+        Vec::new()
+    }
+
+    fn fingerprint(&self) -> Vec<u8> {
+        format!("geo/z-order/v{}", self.version).into_bytes()
+    }
+
+    fn check_write<'fut, 't>(
+        &'fut self,
+        _object_key: &'fut [u8],
+        _new: Option<&'fut Self::Datum>,
+        _transaction: &'fut B::Transaction<'t>,
+        _cfs: &'fut [B::TransactionCf<'t>],
+    ) -> waaa::BoxFuture<'fut, eyre::Result<()>> {
+        Box::pin(async { Ok(()) })
+    }
+
+    fn index<'fut, 't>(
+        &'fut self,
+        object_key: &'fut [u8],
+        datum: &'fut Self::Datum,
+        transaction: &'fut B::Transaction<'t>,
+        cfs: &'fut [B::TransactionCf<'t>],
+    ) -> waaa::BoxFuture<'fut, eyre::Result<()>> {
+        Box::pin(async move {
+            put_entry::<B>((self.extractor)(datum), object_key, transaction, &cfs[0]).await
+        })
+    }
+
+    fn unindex<'fut, 't>(
+        &'fut self,
+        object_key: &'fut [u8],
+        datum: &'fut Self::Datum,
+        transaction: &'fut B::Transaction<'t>,
+        cfs: &'fut [B::TransactionCf<'t>],
+    ) -> waaa::BoxFuture<'fut, eyre::Result<()>> {
+        Box::pin(async move {
+            delete_entry::<B>((self.extractor)(datum), object_key, transaction, &cfs[0]).await
+        })
+    }
+
+    fn reindex<'fut, 't>(
+        &'fut self,
+        object_key: &'fut [u8],
+        old: &'fut Self::Datum,
+        new: &'fut Self::Datum,
+        transaction: &'fut B::Transaction<'t>,
+        cfs: &'fut [B::TransactionCf<'t>],
+    ) -> waaa::BoxFuture<'fut, eyre::Result<()>> {
+        Box::pin(async move {
+            let old = (self.extractor)(old);
+            let new = (self.extractor)(new);
+            if old.map(|p| cell::cell(&p)) != new.map(|p| cell::cell(&p)) {
+                delete_entry::<B>(old, object_key, transaction, &cfs[0]).await?;
+                put_entry::<B>(new, object_key, transaction, &cfs[0]).await?;
+            }
+            Ok(())
+        })
+    }
+
+    fn index_from_slice<'fut, 't>(
+        &'fut self,
+        object_key: &'fut [u8],
+        slice: &'fut [u8],
+        transaction: &'fut B::Transaction<'t>,
+        cfs: &'fut [B::TransactionCf<'t>],
+    ) -> waaa::BoxFuture<'fut, eyre::Result<()>> {
+        Box::pin(async move {
+            let point = self
+                .extract_from_slice(slice)
+                .wrap_err("Failed extracting point from slice")?;
+            put_entry::<B>(point, object_key, transaction, &cfs[0]).await
+        })
+    }
+
+    fn unindex_from_slice<'fut, 't>(
+        &'fut self,
+        object_key: &'fut [u8],
+        slice: &'fut [u8],
+        transaction: &'fut B::Transaction<'t>,
+        cfs: &'fut [B::TransactionCf<'t>],
+    ) -> waaa::BoxFuture<'fut, eyre::Result<()>> {
+        Box::pin(async move {
+            let point = self
+                .extract_from_slice(slice)
+                .wrap_err("Failed extracting point from slice")?;
+            delete_entry::<B>(point, object_key, transaction, &cfs[0]).await
+        })
+    }
+
+    fn rebuild<'fut, 't>(
+        &'fut self,
+        transaction: &'fut B::Transaction<'t>,
+        index_cfs: &'fut [B::TransactionCf<'t>],
+        datum_cf: &'fut B::TransactionCf<'t>,
+    ) -> waaa::BoxFuture<'fut, eyre::Result<()>> {
+        Box::pin(async move {
+            indexer::default_rebuild::<B, Self>(self, transaction, index_cfs, datum_cf).await
+        })
+    }
+}
+
+pub struct GeoQueryKey<'k, B>
+where
+    B: Backend,
+{
+    key: B::Key<'k>,
+}
+
+impl<B> AsRef<[u8]> for GeoQueryKey<'_, B>
+where
+    B: Backend,
+{
+    fn as_ref(&self) -> &[u8] {
+        &self.key.as_ref()[CELL_LEN..]
+    }
+}
+
+#[warn(clippy::missing_trait_methods)]
+impl<B, D> Index<B> for GeoIndex<D>
+where
+    B: Backend,
+    D: Datum,
+{
+    type Query<'q> = GeoQuery;
+    type QueryKey<'k> = GeoQueryKey<'k, B>;
+
+    fn query<'q, 'op: 'q, 't: 'op>(
+        &'q self,
+        query: &'q Self::Query<'q>,
+        transaction: &'op B::Transaction<'t>,
+        object_cf: &'op B::TransactionCf<'t>,
+        cfs: &'op [B::TransactionCf<'t>],
+    ) -> waaa::BoxStream<'q, eyre::Result<(Self::QueryKey<'op>, B::Value<'op>)>> {
+        self.matches::<B>(query, transaction, object_cf, cfs)
+    }
+
+    fn query_keys<'q, 'op: 'q, 't: 'op>(
+        &'q self,
+        query: &'q Self::Query<'q>,
+        transaction: &'op B::Transaction<'t>,
+        object_cf: &'op B::TransactionCf<'t>,
+        cfs: &'op [B::TransactionCf<'t>],
+    ) -> waaa::BoxStream<'q, eyre::Result<Self::QueryKey<'op>>> {
+        // Filtering out the false positives requires reading the objects anyway
+        Box::pin(
+            self.matches::<B>(query, transaction, object_cf, cfs)
+                .map_ok(|(key, _)| key),
+        )
+    }
+
+    fn count<'q, 'op: 'q, 't: 'op>(
+        &'q self,
+        query: &'q Self::Query<'q>,
+        transaction: &'op B::Transaction<'t>,
+        object_cf: &'op B::TransactionCf<'t>,
+        cfs: &'op [B::TransactionCf<'t>],
+    ) -> waaa::BoxFuture<'q, eyre::Result<usize>> {
+        Box::pin(
+            self.matches::<B>(query, transaction, object_cf, cfs)
+                .try_fold(0, |count, _| future::ready(Ok(count + 1))),
+        )
+    }
+}
@@ -0,0 +1,13 @@
+mod cell;
+
+mod index;
+pub use index::{GeoIndex, GeoQueryKey, PointExtractorFromSlice};
+
+mod point;
+pub use point::Point;
+
+mod query;
+pub use query::GeoQuery;
+
+#[cfg(test)]
+mod tests;
@@ -0,0 +1,30 @@
+/// Mean radius of the Earth, in meters
+const EARTH_RADIUS: f64 = 6_371_008.8;
+
+/// Location on Earth, in degrees
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Point {
+    pub lat: f64,
+    pub lon: f64,
+}
+
+impl Point {
+    /// `lat` must be in `-90.0..=90.0` and `lon` in `-180.0..=180.0`
+    pub const fn new(lat: f64, lon: f64) -> Self {
+        Self { lat, lon }
+    }
+
+    /// Great-circle distance to `other`, in meters
+    pub fn distance(&self, other: &Point) -> f64 {
+        let (lat1, lat2) = (self.lat.to_radians(), other.lat.to_radians());
+        let dlat = lat2 - lat1;
+        let dlon = (other.lon - self.lon).to_radians();
+        let a = (dlat / 2.).sin().powi(2) + lat1.cos() * lat2.cos() * (dlon / 2.).sin().powi(2);
+        2. * EARTH_RADIUS * a.sqrt().min(1.).asin()
+    }
+
+    /// Angle, in degrees of latitude, that spans `meters` on the surface of the Earth
+    pub(crate) fn lat_span(meters: f64) -> f64 {
+        (meters / EARTH_RADIUS).to_degrees()
+    }
+}
@@ -0,0 +1,97 @@
+use crate::{Point, cell};
+
+/// Query on a `GeoIndex`
+///
+/// Results are returned in the order of their cells, not sorted by distance.
+pub enum GeoQuery {
+    /// Matches the points between `min` and `max` in both latitude and longitude
+    ///
+    /// If `min.lon > max.lon`, the box crosses the antimeridian. If `min.lat > max.lat`, the box
+    /// is empty and matches no points.
+    BoundingBox { min: Point, max: Point },
+
+    /// Matches the points at most `meters` away from `center`
+    Radius { center: Point, meters: f64 },
+}
+
+impl GeoQuery {
+    pub fn bounding_box(min: Point, max: Point) -> Self {
+        Self::BoundingBox { min, max }
+    }
+
+    pub fn radius(center: Point, meters: f64) -> Self {
+        Self::Radius { center, meters }
+    }
+
+    /// Returns whether `point` matches the query
+    pub(crate) fn contains(&self, point: &Point) -> bool {
+        match self {
+            GeoQuery::BoundingBox { min, max } => {
+                let lon_matches = if min.lon <= max.lon {
+                    (min.lon..=max.lon).contains(&point.lon)
+                } else {
+                    point.lon >= min.lon || point.lon <= max.lon
+                };
+                (min.lat..=max.lat).contains(&point.lat) && lon_matches
+            }
+            GeoQuery::Radius { center, meters } => center.distance(point) <= *meters,
+        }
+    }
+
+    /// Returns the boxes that contain all the matching points, none crossing the antimeridian
+    fn boxes(&self) -> Vec<(Point, Point)> {
+        match self {
+            GeoQuery::BoundingBox { min, max } if min.lon > max.lon => vec![
+                (*min, Point::new(max.lat, 180.)),
+                (Point::new(min.lat, -180.), *max),
+            ],
+            GeoQuery::BoundingBox { min, max } => vec![(*min, *max)],
+            GeoQuery::Radius { center, meters } => {
+                let lat_span = Point::lat_span(*meters);
+                let min_lat = center.lat - lat_span;
+                let max_lat = center.lat + lat_span;
+                // Near the poles, all the longitudes are at most as far as the pole
+                let lon_span = if min_lat <= -90. || max_lat >= 90. {
+                    180.
+                } else {
+                    let sin_span = lat_span.to_radians().sin() / center.lat.to_radians().cos();
+                    sin_span.min(1.).asin().to_degrees()
+                };
+                let (min_lat, max_lat) = (min_lat.max(-90.), max_lat.min(90.));
+                let (min_lon, max_lon) = (center.lon - lon_span, center.lon + lon_span);
+                if lon_span >= 180. {
+                    vec![(Point::new(min_lat, -180.), Point::new(max_lat, 180.))]
+                } else if min_lon < -180. {
+                    vec![
+                        (
+                            Point::new(min_lat, min_lon + 360.),
+                            Point::new(max_lat, 180.),
+                        ),
+                        (Point::new(min_lat, -180.), Point::new(max_lat, max_lon)),
+                    ]
+                } else if max_lon > 180. {
+                    vec![
+                        (Point::new(min_lat, min_lon), Point::new(max_lat, 180.)),
+                        (
+                            Point::new(min_lat, -180.),
+                            Point::new(max_lat, max_lon - 360.),
+                        ),
+                    ]
+                } else {
+                    vec![(Point::new(min_lat, min_lon), Point::new(max_lat, max_lon))]
+                }
+            }
+        }
+    }
+
+    /// Returns the inclusive ranges of cells to scan, which may contain false positives
+    pub(crate) fn cell_ranges(&self) -> Vec<(u64, u64)> {
+        let mut ranges = self
+            .boxes()
+            .iter()
+            .flat_map(|(min, max)| cell::cover(min, max))
+            .collect::<Vec<_>>();
+        cell::merge(&mut ranges);
+        ranges
+    }
+}
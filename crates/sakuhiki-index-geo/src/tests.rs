@@ -0,0 +1,198 @@
+use futures_util::TryStreamExt as _;
+use sakuhiki_core::{Backend, Indexer, Mode};
+
+use crate::*;
+
+const PARIS: Point = Point::new(48.8566, 2.3522);
+const LONDON: Point = Point::new(51.5074, -0.1278);
+const BERLIN: Point = Point::new(52.52, 13.405);
+const NEW_YORK: Point = Point::new(40.7128, -74.006);
+const SUVA: Point = Point::new(-18.1416, 178.4419);
+const APIA: Point = Point::new(-13.8333, -171.75);
+
+struct Place(Option<Point>);
+
+impl sakuhiki_core::Datum for Place {
+    const CF: &'static str = "place";
+
+    fn from_slice(datum: &[u8]) -> eyre::Result<Self> {
+        if datum.is_empty() {
+            return Ok(Self(None));
+        }
+        let lat = f64::from_be_bytes(datum[..8].try_into()?);
+        let lon = f64::from_be_bytes(datum[8..].try_into()?);
+        Ok(Self(Some(Point::new(lat, lon))))
+    }
+
+    fn to_bytes(&self) -> eyre::Result<Vec<u8>> {
+        Ok(self
+            .0
+            .iter()
+            .flat_map(|p| [p.lat.to_be_bytes(), p.lon.to_be_bytes()].concat())
+            .collect())
+    }
+}
+
+impl Place {
+    const INDEX: &'static GeoIndex<Place> = &GeoIndex::new(&["place-location"], |p| p.0, None);
+}
+
+impl<B: Backend> sakuhiki_core::IndexedDatum<B> for Place {
+    const INDEXES: &'static [&'static dyn Indexer<B, Datum = Self>] = &[Self::INDEX];
+}
+
+#[test]
+fn test_distance() {
+    assert!((PARIS.distance(&LONDON) - 343_900.).abs() < 1_000.);
+    assert!((SUVA.distance(&APIA) - 1_150_000.).abs() < 20_000.);
+    assert_eq!(PARIS.distance(&PARIS), 0.);
+}
+
+#[test]
+fn test_fingerprint() {
+    assert_eq!(
+        Indexer::<sakuhiki_memdb::MemDb>::fingerprint(Place::INDEX),
+        b"geo/z-order/v0"
+    );
+}
+
+#[test]
+fn test_cell_cover() {
+    assert_eq!(
+        cell::cover(&Point::new(-90., -180.), &Point::new(90., 180.)),
+        [(0, u64::MAX)]
+    );
+    let (min, max) = (Point::new(48.8, 2.2), Point::new(48.9, 2.5));
+    let ranges = cell::cover(&min, &max);
+    assert!(!ranges.is_empty() && ranges.len() <= 16);
+    // All the points of the box are in the ranges
+    for lat in 0..=10 {
+        for lon in 0..=10 {
+            let point = Point::new(
+                min.lat + (max.lat - min.lat) * f64::from(lat) / 10.,
+                min.lon + (max.lon - min.lon) * f64::from(lon) / 10.,
+            );
+            let cell = cell::cell(&point);
+            assert!(
+                ranges
+                    .iter()
+                    .any(|(start, end)| (start..=end).contains(&&cell))
+            );
+        }
+    }
+    // The ranges are sorted and disjoint
+    assert!(ranges.windows(2).all(|w| w[0].1 < w[1].0));
+    // Inverted boxes are empty
+    assert!(cell::cover(&max, &min).is_empty());
+    assert!(cell::cover(&Point::new(10., 0.), &Point::new(-10., 1.)).is_empty());
+}
+
+#[tokio::test]
+async fn test_geo_query() {
+    let db = sakuhiki_memdb::MemDb::builder()
+        .datum::<Place>()
+        .build()
+        .await
+        .unwrap();
+    let place = db.cf_handle::<Place>().await.unwrap();
+    db.transaction(Mode::ReadWrite, &[&place], |t, [place]| {
+        Box::pin(async move {
+            for (key, point) in [
+                (b"paris", Some(PARIS)),
+                (b"londn", Some(LONDON)),
+                (b"berln", Some(BERLIN)),
+                (b"nyork", Some(NEW_YORK)),
+                (b"suvaa", Some(SUVA)),
+                (b"apiaa", Some(APIA)),
+                (b"nowhr", None),
+            ] {
+                t.put_datum(&place, key, &Place(point)).await?;
+            }
+            let search = async |query: GeoQuery| -> Vec<Vec<u8>> {
+                let mut keys: Vec<Vec<u8>> = t
+                    .query_keys(&place, Place::INDEX, &query)
+                    .unwrap()
+                    .map_ok(|k| k.as_ref().to_vec())
+                    .try_collect()
+                    .await
+                    .unwrap();
+                keys.sort();
+                keys
+            };
+            assert_eq!(
+                search(GeoQuery::bounding_box(
+                    Point::new(45., -5.),
+                    Point::new(55., 15.)
+                ))
+                .await,
+                [b"berln", b"londn", b"paris"]
+            );
+            assert_eq!(
+                search(GeoQuery::bounding_box(
+                    Point::new(45., -5.),
+                    Point::new(55., 10.)
+                ))
+                .await,
+                [b"londn", b"paris"]
+            );
+            assert_eq!(
+                search(GeoQuery::radius(PARIS, 500_000.)).await,
+                [b"londn", b"paris"]
+            );
+            assert_eq!(
+                search(GeoQuery::radius(PARIS, 1_000_000.)).await,
+                [b"berln", b"londn", b"paris"]
+            );
+            assert_eq!(search(GeoQuery::radius(PARIS, 1.)).await, [b"paris"]);
+            // Boxes with `min` north of `max` are empty
+            assert!(
+                search(GeoQuery::bounding_box(
+                    Point::new(55., -5.),
+                    Point::new(45., 15.)
+                ))
+                .await
+                .is_empty()
+            );
+            // Queries crossing the antimeridian
+            assert_eq!(
+                search(GeoQuery::bounding_box(
+                    Point::new(-20., 170.),
+                    Point::new(-10., -170.)
+                ))
+                .await,
+                [b"apiaa", b"suvaa"]
+            );
+            assert_eq!(
+                search(GeoQuery::radius(SUVA, 1_500_000.)).await,
+                [b"apiaa", b"suvaa"]
+            );
+            // Radiuses containing a pole
+            assert_eq!(
+                search(GeoQuery::radius(Point::new(80., 0.), 6_000_000.)).await,
+                [b"berln", b"londn", b"nyork", b"paris"]
+            );
+            assert_eq!(
+                t.count(&place, Place::INDEX, &GeoQuery::radius(LONDON, 1_000_000.))
+                    .await?,
+                3
+            );
+            // Moving and removing places updates the index
+            t.put_datum(&place, b"berln", &Place(Some(NEW_YORK)))
+                .await?;
+            assert_eq!(
+                search(GeoQuery::radius(NEW_YORK, 1_000.)).await,
+                [b"berln", b"nyork"]
+            );
+            t.put_datum(&place, b"nyork", &Place(None)).await?;
+            t.delete::<Place>(&place, b"paris").await?;
+            assert_eq!(search(GeoQuery::radius(NEW_YORK, 1_000.)).await, [b"berln"]);
+            assert_eq!(
+                search(GeoQuery::radius(PARIS, 1_000_000.)).await,
+                [b"londn"]
+            );
+            Ok(())
+        })
+    })
+    .await
+    .unwrap();
+}
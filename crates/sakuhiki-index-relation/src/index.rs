@@ -0,0 +1,365 @@
+use std::marker::PhantomData;
+
+use eyre::{WrapErr as _, eyre};
+use futures_util::{StreamExt as _, TryStreamExt as _, future};
+use sakuhiki_core::{
+    Backend, CfOperationError, Datum, Index, Indexer,
+    backend::{BackendCf as _, Transaction as _},
+    indexer,
+};
+use sakuhiki_index_btree::escape;
+
+use crate::RelationQuery;
+
+pub type TargetExtractor<S> = for<'d> fn(&'d S) -> Option<&'d [u8]>;
+
+/// Error returned when putting an object whose target does not exist, see `validate_target`
+#[derive(Debug, thiserror::Error)]
+#[error(
+    "Object {object_key:?} in CF {cf} references object {target_key:?} in CF {target_cf}, which does not exist"
+)]
+pub struct MissingRelationTarget {
+    pub cf: &'static str,
+    pub object_key: Vec<u8>,
+    pub target_cf: &'static str,
+    pub target_key: Vec<u8>,
+}
+
+/// Index of the objects of `S` referencing objects of `T`, eg. comments referencing their post
+///
+/// Each reference is stored as the escaped key of the target object, followed by the key of the
+/// referencing object. Registering a `ReferencedBy` on `T` enforces a policy upon deleting
+/// referenced objects.
+pub struct RelationIndex<S, T> {
+    cf: &'static [&'static str; 1],
+    target: TargetExtractor<S>,
+    validate_target: bool,
+    version: u64,
+    _target: PhantomData<fn() -> T>,
+}
+
+impl<S, T> RelationIndex<S, T>
+where
+    S: Datum,
+    T: Datum,
+{
+    /// `target` returns the key of the object of `T` that `&S` references, if any
+    pub const fn new(cf: &'static [&'static str; 1], target: TargetExtractor<S>) -> Self {
+        Self::new_versioned(cf, target, 0)
+    }
+
+    /// Same as `new`, but with a version for the target extraction, which `new` sets to 0
+    ///
+    /// The version must be bumped whenever `target` changes the keys it extracts, so that the
+    /// index gets rebuilt upon building the `Db`.
+    pub const fn new_versioned(
+        cf: &'static [&'static str; 1],
+        target: TargetExtractor<S>,
+        version: u64,
+    ) -> Self {
+        Self {
+            cf,
+            target,
+            validate_target: false,
+            version,
+            _target: PhantomData,
+        }
+    }
+
+    /// Make putting an object fail with `MissingRelationTarget` if its target does not exist
+    pub const fn validate_target(mut self) -> Self {
+        self.validate_target = true;
+        self
+    }
+
+    pub(crate) fn cf(&self) -> &'static [&'static str] {
+        self.cf
+    }
+
+    /// Checks that `target` exists, if `validate_target` was set
+    async fn check_target<'t, B>(
+        &self,
+        target: &[u8],
+        object_key: &[u8],
+        transaction: &B::Transaction<'t>,
+        cfs: &[B::TransactionCf<'t>],
+    ) -> eyre::Result<()>
+    where
+        B: Backend,
+    {
+        if !self.validate_target {
+            return Ok(());
+        }
+        let existing = transaction.get(&cfs[1], target).await.wrap_err_with(|| {
+            CfOperationError::new("Failed getting relation target from", cfs[1].name())
+        })?;
+        if existing.is_none() {
+            return Err(eyre::Report::from(MissingRelationTarget {
+                cf: S::CF,
+                object_key: object_key.to_vec(),
+                target_cf: T::CF,
+                target_key: target.to_vec(),
+            }));
+        }
+        Ok(())
+    }
+
+    async fn put_entry<'t, B>(
+        &self,
+        target: Option<&[u8]>,
+        object_key: &[u8],
+        transaction: &B::Transaction<'t>,
+        cfs: &[B::TransactionCf<'t>],
+    ) -> eyre::Result<()>
+    where
+        B: Backend,
+    {
+        let Some(target) = target else {
+            return Ok(());
+        };
+        // Already checked by `check_write` on puts, but not when rebuilding the index
+        self.check_target::<B>(target, object_key, transaction, cfs)
+            .await?;
+        transaction
+            .put(&cfs[0], &index_key(target, object_key), &[])
+            .await
+            .wrap_err_with(|| CfOperationError::new("Failed putting key into", cfs[0].name()))?;
+        Ok(())
+    }
+}
+
+fn index_key(target: &[u8], object_key: &[u8]) -> Vec<u8> {
+    let mut key = escape(target);
+    key.extend_from_slice(object_key);
+    key
+}
+
+async fn delete_entry<'t, B>(
+    target: Option<&[u8]>,
+    object_key: &[u8],
+    transaction: &B::Transaction<'t>,
+    cf: &B::TransactionCf<'t>,
+) -> eyre::Result<()>
+where
+    B: Backend,
+{
+    if let Some(target) = target {
+        transaction
+            .delete(cf, &index_key(target, object_key))
+            .await
+            .wrap_err_with(|| CfOperationError::new("Failed deleting key from", cf.name()))?;
+    }
+    Ok(())
+}
+
+#[warn(clippy::missing_trait_methods)]
+impl<B, S, T> Indexer<B> for RelationIndex<S, T>
+where
+    B: Backend,
+    S: Datum,
+    T: Datum,
+{
+    type Datum = S;
+
+    fn cfs(&self) -> &'static [&'static str] {
+        self.cf
+    }
+
+    fn foreign_cfs(&self) -> Vec<&'static str> {
+        match self.validate_target {
+            true => vec![T::CF],
+            false => Vec::new(),
+        }
+    }
+
+    fn fingerprint(&self) -> Vec<u8> {
+        format!("relation/{}/v{}", T::CF, self.version).into_bytes()
+    }
+
+    fn check_write<'fut, 't>(
+        &'fut self,
+        object_key: &'fut [u8],
+        new: Option<&'fut Self::Datum>,
+        transaction: &'fut B::Transaction<'t>,
+        cfs: &'fut [B::TransactionCf<'t>],
+    ) -> waaa::BoxFuture<'fut, eyre::Result<()>> {
+        Box::pin(async move {
+            match new.and_then(self.target) {
+                Some(target) => {
+                    self.check_target::<B>(target, object_key, transaction, cfs)
+                        .await
+                }
+                None => Ok(()),
+            }
+        })
+    }
+
+    fn index<'fut, 't>(
+        &'fut self,
+        object_key: &'fut [u8],
+        datum: &'fut Self::Datum,
+        transaction: &'fut B::Transaction<'t>,
+        cfs: &'fut [B::TransactionCf<'t>],
+    ) -> waaa::BoxFuture<'fut, eyre::Result<()>> {
+        Box::pin(async move {
+            self.put_entry::<B>((self.target)(datum), object_key, transaction, cfs)
+                .await
+        })
+    }
+
+    fn unindex<'fut, 't>(
+        &'fut self,
+        object_key: &'fut [u8],
+        datum: &'fut Self::Datum,
+        transaction: &'fut B::Transaction<'t>,
+        cfs: &'fut [B::TransactionCf<'t>],
+    ) -> waaa::BoxFuture<'fut, eyre::Result<()>> {
+        Box::pin(async move {
+            delete_entry::<B>((self.target)(datum), object_key, transaction, &cfs[0]).await
+        })
+    }
+
+    fn reindex<'fut, 't>(
+        &'fut self,
+        object_key: &'fut [u8],
+        old: &'fut Self::Datum,
+        new: &'fut Self::Datum,
+        transaction: &'fut B::Transaction<'t>,
+        cfs: &'fut [B::TransactionCf<'t>],
+    ) -> waaa::BoxFuture<'fut, eyre::Result<()>> {
+        Box::pin(async move {
+            let old = (self.target)(old);
+            let new = (self.target)(new);
+            if old != new {
+                delete_entry::<B>(old, object_key, transaction, &cfs[0]).await?;
+                self.put_entry::<B>(new, object_key, transaction, cfs)
+                    .await?;
+            }
+            Ok(())
+        })
+    }
+
+    fn index_from_slice<'fut, 't>(
+        &'fut self,
+        object_key: &'fut [u8],
+        slice: &'fut [u8],
+        transaction: &'fut B::Transaction<'t>,
+        cfs: &'fut [B::TransactionCf<'t>],
+    ) -> waaa::BoxFuture<'fut, eyre::Result<()>> {
+        Box::pin(async move {
+            let datum = S::from_slice(slice).wrap_err("Failed to parse datum")?;
+            self.put_entry::<B>((self.target)(&datum), object_key, transaction, cfs)
+                .await
+        })
+    }
+
+    fn unindex_from_slice<'fut, 't>(
+        &'fut self,
+        object_key: &'fut [u8],
+        slice: &'fut [u8],
+        transaction: &'fut B::Transaction<'t>,
+        cfs: &'fut [B::TransactionCf<'t>],
+    ) -> waaa::BoxFuture<'fut, eyre::Result<()>> {
+        Box::pin(async move {
+            let datum = S::from_slice(slice).wrap_err("Failed to parse datum")?;
+            delete_entry::<B>((self.target)(&datum), object_key, transaction, &cfs[0]).await
+        })
+    }
+
+    fn rebuild<'fut, 't>(
+        &'fut self,
+        transaction: &'fut B::Transaction<'t>,
+        index_cfs: &'fut [B::TransactionCf<'t>],
+        datum_cf: &'fut B::TransactionCf<'t>,
+    ) -> waaa::BoxFuture<'fut, eyre::Result<()>> {
+        Box::pin(async move {
+            indexer::default_rebuild::<B, Self>(self, transaction, index_cfs, datum_cf).await
+        })
+    }
+}
+
+pub struct RelationQueryKey<'k, B>
+where
+    B: Backend,
+{
+    key: B::Key<'k>,
+    prefix_len: usize,
+}
+
+impl<B> AsRef<[u8]> for RelationQueryKey<'_, B>
+where
+    B: Backend,
+{
+    fn as_ref(&self) -> &[u8] {
+        &self.key.as_ref()[self.prefix_len..]
+    }
+}
+
+#[warn(clippy::missing_trait_methods)]
+impl<B, S, T> Index<B> for RelationIndex<S, T>
+where
+    B: Backend,
+    S: Datum,
+    T: Datum,
+{
+    type Query<'q> = RelationQuery;
+    type QueryKey<'k> = RelationQueryKey<'k, B>;
+
+    fn query<'q, 'op: 'q, 't: 'op>(
+        &'q self,
+        query: &'q Self::Query<'q>,
+        transaction: &'op B::Transaction<'t>,
+        object_cf: &'op B::TransactionCf<'t>,
+        cfs: &'op [B::TransactionCf<'t>],
+    ) -> waaa::BoxStream<'q, eyre::Result<(Self::QueryKey<'op>, B::Value<'op>)>> {
+        Box::pin(
+            self.query_keys(query, transaction, object_cf, cfs)
+                .and_then(async |object_key| {
+                    let object_value = transaction
+                        .get(object_cf, object_key.as_ref())
+                        .await
+                        .and_then(|value| {
+                            value.ok_or_else(|| {
+                                eyre!("Object was present in index but not in real table")
+                            })
+                        })
+                        .wrap_err_with(|| {
+                            CfOperationError::new("Failed getting object", object_cf.name())
+                        })?;
+                    Ok((object_key, object_value))
+                }),
+        )
+    }
+
+    fn query_keys<'q, 'op: 'q, 't: 'op>(
+        &'q self,
+        query: &'q Self::Query<'q>,
+        transaction: &'op B::Transaction<'t>,
+        _object_cf: &'op B::TransactionCf<'t>,
+        cfs: &'op [B::TransactionCf<'t>],
+    ) -> waaa::BoxStream<'q, eyre::Result<Self::QueryKey<'op>>> {
+        let prefix_len = query.prefix.len();
+        Box::pin(
+            transaction
+                .scan_prefix(&cfs[0], &query.prefix)
+                .map(|res| {
+                    res.wrap_err_with(|| CfOperationError::new("Failed scanning", cfs[0].name()))
+                })
+                .map_ok(move |(key, _)| RelationQueryKey { key, prefix_len }),
+        )
+    }
+
+    fn count<'q, 'op: 'q, 't: 'op>(
+        &'q self,
+        query: &'q Self::Query<'q>,
+        transaction: &'op B::Transaction<'t>,
+        _object_cf: &'op B::TransactionCf<'t>,
+        cfs: &'op [B::TransactionCf<'t>],
+    ) -> waaa::BoxFuture<'q, eyre::Result<usize>> {
+        Box::pin(
+            transaction
+                .scan_prefix(&cfs[0], &query.prefix)
+                .try_fold(0, |count, _| future::ready(Ok(count + 1))),
+        )
+    }
+}
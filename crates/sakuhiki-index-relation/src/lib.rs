@@ -0,0 +1,11 @@
+mod index;
+pub use index::{MissingRelationTarget, RelationIndex, RelationQueryKey, TargetExtractor};
+
+mod query;
+pub use query::RelationQuery;
+
+mod referenced_by;
+pub use referenced_by::{OnDelete, ReferencedBy, RelationRestricted};
+
+#[cfg(test)]
+mod tests;
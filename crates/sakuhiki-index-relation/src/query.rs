@@ -0,0 +1,15 @@
+use sakuhiki_index_btree::escape;
+
+/// Query on a `RelationIndex`, for all the objects referencing one target object
+pub struct RelationQuery {
+    pub(crate) prefix: Vec<u8>,
+}
+
+impl RelationQuery {
+    /// Matches the objects that reference the object with key `target_key`
+    pub fn target(target_key: &[u8]) -> Self {
+        Self {
+            prefix: escape(target_key),
+        }
+    }
+}
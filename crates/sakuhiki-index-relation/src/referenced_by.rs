@@ -0,0 +1,352 @@
+use std::{
+    any::TypeId,
+    cell::RefCell,
+    collections::BTreeMap,
+    sync::{Arc, RwLock},
+};
+
+use eyre::WrapErr as _;
+use futures_util::{StreamExt as _, TryStreamExt as _};
+use sakuhiki_core::{
+    Backend, CfOperationError, Datum, IndexedDatum, Indexer,
+    backend::{BackendCf as _, Transaction as _},
+};
+use sakuhiki_index_btree::escape;
+
+use crate::RelationIndex;
+
+/// What to do with the objects referencing an object that gets deleted
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum OnDelete {
+    /// Fail the deletion with `RelationRestricted`
+    Restrict,
+
+    /// Delete the referencing objects too
+    ///
+    /// Cascading relations must not form cycles, as deleting an object requires access to the
+    /// CFs of all the objects the deletion can cascade to. Building a `Db` with such a cycle
+    /// panics.
+    // TODO(low): support cycles, eg. for comments referencing their parent comment
+    Cascade,
+}
+
+/// Error returned when deleting an object that is still referenced, see `OnDelete::Restrict`
+#[derive(Debug, thiserror::Error)]
+#[error(
+    "Object {object_key:?} in CF {cf} cannot be deleted, as object {source_key:?} in CF {source_cf} still references it"
+)]
+pub struct RelationRestricted {
+    pub cf: &'static str,
+    pub object_key: Vec<u8>,
+    pub source_cf: &'static str,
+    pub source_key: Vec<u8>,
+}
+
+/// Indexer enforcing the `OnDelete` policy of `relation`, to be registered on the target datum
+///
+/// It does not store anything by itself, and only reads the CF of `relation` upon deletion of
+/// the target objects.
+pub struct ReferencedBy<S, T>
+where
+    S: Datum,
+    T: Datum,
+{
+    relation: &'static RelationIndex<S, T>,
+    on_delete: OnDelete,
+}
+
+impl<S, T> ReferencedBy<S, T>
+where
+    S: Datum,
+    T: Datum,
+{
+    pub const fn new(relation: &'static RelationIndex<S, T>, on_delete: OnDelete) -> Self {
+        Self {
+            relation,
+            on_delete,
+        }
+    }
+
+    /// Returns the keys of the objects of `S` that reference the object at `object_key`
+    async fn sources<'t, B>(
+        &self,
+        object_key: &[u8],
+        transaction: &B::Transaction<'t>,
+        cf: &B::TransactionCf<'t>,
+    ) -> eyre::Result<Vec<Vec<u8>>>
+    where
+        B: Backend,
+    {
+        let prefix = escape(object_key);
+        transaction
+            .scan_prefix(cf, &prefix)
+            .map(|res| res.wrap_err_with(|| CfOperationError::new("Failed scanning", cf.name())))
+            .map_ok(|(key, _)| key.as_ref()[prefix.len()..].to_vec())
+            .try_collect()
+            .await
+    }
+
+    /// Checks that the `OnDelete` policy allows deleting the object at `object_key`
+    ///
+    /// For cascading deletions, this checks that all the indexes of the referencing objects allow
+    /// deleting them too, so that the whole cascade is validated before anything gets written.
+    async fn check_delete<'t, B>(
+        &self,
+        object_key: &[u8],
+        transaction: &B::Transaction<'t>,
+        cfs: &[B::TransactionCf<'t>],
+    ) -> eyre::Result<()>
+    where
+        B: Backend,
+        S: IndexedDatum<B>,
+    {
+        let sources = self.sources::<B>(object_key, transaction, &cfs[0]).await?;
+        match self.on_delete {
+            OnDelete::Restrict => match sources.into_iter().next() {
+                Some(source_key) => Err(eyre::Report::from(RelationRestricted {
+                    cf: T::CF,
+                    object_key: object_key.to_vec(),
+                    source_cf: S::CF,
+                    source_key,
+                })),
+                None => Ok(()),
+            },
+            OnDelete::Cascade => {
+                let indexes_lens = &cascade_cfs::<B, S>().indexes_lens;
+                for source_key in sources {
+                    // Skip the relation and source CFs, the indexes of `S` do not need them
+                    let mut indexes_cfs = &cfs[2..];
+                    for (i, &len) in S::INDEXES.iter().zip(indexes_lens) {
+                        let index_cfs;
+                        (index_cfs, indexes_cfs) = indexes_cfs.split_at(len);
+                        i.check_write(&source_key, None, transaction, index_cfs)
+                            .await
+                            .wrap_err_with(|| {
+                                format!("Failed checking cascading deletion to {source_key:?}")
+                            })?;
+                    }
+                }
+                Ok(())
+            }
+        }
+    }
+
+    /// Applies the `OnDelete` policy upon deleting the object at `object_key`
+    async fn deleted<'t, B>(
+        &self,
+        object_key: &[u8],
+        transaction: &B::Transaction<'t>,
+        cfs: &[B::TransactionCf<'t>],
+    ) -> eyre::Result<()>
+    where
+        B: Backend,
+        S: IndexedDatum<B>,
+    {
+        match self.on_delete {
+            OnDelete::Restrict => self.check_delete::<B>(object_key, transaction, cfs).await,
+            OnDelete::Cascade => {
+                // Collect first, as deleting the sources also removes them from the relation
+                let sources = self.sources::<B>(object_key, transaction, &cfs[0]).await?;
+                for source_key in sources {
+                    cascade::<B, S>(&source_key, transaction, &cfs[1..])
+                        .await
+                        .wrap_err_with(|| format!("Failed cascading deletion to {source_key:?}"))?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+/// CFs needed to cascade deletions to the objects of a datum
+struct CascadeCfs {
+    /// CF of the datum, followed by the CFs of all its indexes
+    cfs: Vec<&'static str>,
+
+    /// Number of CFs each index of the datum takes in `cfs`
+    indexes_lens: Vec<usize>,
+}
+
+/// `CascadeCfs` of each backend and datum, as computing them walks the whole cascade tree
+static CASCADE_CFS: RwLock<BTreeMap<TypeId, Arc<CascadeCfs>>> = RwLock::new(BTreeMap::new());
+
+thread_local! {
+    /// CFs of the datums whose `CascadeCfs` this thread is computing, to detect cycles
+    static COMPUTING_CASCADE_CFS: RefCell<Vec<&'static str>> = const { RefCell::new(Vec::new()) };
+}
+
+/// Returns the `CascadeCfs` of `S`, computing them upon first use
+///
+/// Panics if the cascading relations from `S` lead back to `S`.
+fn cascade_cfs<B, S>() -> Arc<CascadeCfs>
+where
+    B: Backend,
+    S: IndexedDatum<B>,
+{
+    let key = TypeId::of::<(B, S)>();
+    if let Some(cascade_cfs) = CASCADE_CFS.read().unwrap().get(&key) {
+        return cascade_cfs.clone();
+    }
+    COMPUTING_CASCADE_CFS.with_borrow_mut(|computing| {
+        if computing.contains(&S::CF) {
+            let cycle = std::mem::take(computing).join(" -> ");
+            panic!(
+                "Cascading deletions form a cycle through CFs {cycle} -> {}",
+                S::CF
+            );
+        }
+        computing.push(S::CF);
+    });
+    let mut cfs = vec![S::CF];
+    let mut indexes_lens = Vec::with_capacity(S::INDEXES.len());
+    for i in S::INDEXES {
+        let foreign_cfs = i.foreign_cfs();
+        indexes_lens.push(i.cfs().len() + foreign_cfs.len());
+        cfs.extend_from_slice(i.cfs());
+        cfs.extend(foreign_cfs);
+    }
+    COMPUTING_CASCADE_CFS.with_borrow_mut(|computing| computing.pop());
+    let cascade_cfs = Arc::new(CascadeCfs { cfs, indexes_lens });
+    CASCADE_CFS
+        .write()
+        .unwrap()
+        .entry(key)
+        .or_insert(cascade_cfs)
+        .clone()
+}
+
+/// Deletes `object_key` from the CF of `S`, and from all of its indexes
+///
+/// `cfs` are the CF of `S`, followed by the CFs of all the indexes of `S`. The indexes must
+/// already have accepted the deletion through `check_write`, see `ReferencedBy::check_delete`.
+async fn cascade<'t, B, S>(
+    object_key: &[u8],
+    transaction: &B::Transaction<'t>,
+    cfs: &[B::TransactionCf<'t>],
+) -> eyre::Result<()>
+where
+    B: Backend,
+    S: IndexedDatum<B>,
+{
+    let (datum_cf, mut indexes_cfs) = cfs.split_first().unwrap();
+    let old = transaction
+        .delete(datum_cf, object_key)
+        .await
+        .wrap_err_with(|| CfOperationError::new("Failed deleting from", datum_cf.name()))?;
+    let Some(old) = old else {
+        return Ok(());
+    };
+    let old = S::from_slice(old.as_ref()).wrap_err("Failed parsing old datum")?;
+    for (i, &len) in S::INDEXES.iter().zip(&cascade_cfs::<B, S>().indexes_lens) {
+        let index_cfs;
+        (index_cfs, indexes_cfs) = indexes_cfs.split_at(len);
+        i.unindex(object_key, &old, transaction, index_cfs)
+            .await
+            .wrap_err("Failed unindexing old value")?;
+    }
+    Ok(())
+}
+
+#[warn(clippy::missing_trait_methods)]
+impl<B, S, T> Indexer<B> for ReferencedBy<S, T>
+where
+    B: Backend,
+    S: IndexedDatum<B>,
+    T: Datum,
+{
+    type Datum = T;
+
+    fn cfs(&self) -> &'static [&'static str] {
+        &[]
+    }
+
+    fn foreign_cfs(&self) -> Vec<&'static str> {
+        let mut cfs = self.relation.cf().to_vec();
+        if self.on_delete == OnDelete::Cascade {
+            cfs.extend_from_slice(&cascade_cfs::<B, S>().cfs);
+        }
+        cfs
+    }
+
+    fn fingerprint(&self) -> Vec<u8> {
+        b"referenced-by".to_vec()
+    }
+
+    fn check_write<'fut, 't>(
+        &'fut self,
+        object_key: &'fut [u8],
+        new: Option<&'fut Self::Datum>,
+        transaction: &'fut B::Transaction<'t>,
+        cfs: &'fut [B::TransactionCf<'t>],
+    ) -> waaa::BoxFuture<'fut, eyre::Result<()>> {
+        Box::pin(async move {
+            // Cascading deletions happen in `unindex`, as they need to write, but are checked here
+            if new.is_some() {
+                return Ok(());
+            }
+            self.check_delete::<B>(object_key, transaction, cfs).await
+        })
+    }
+
+    fn index<'fut, 't>(
+        &'fut self,
+        _object_key: &'fut [u8],
+        _datum: &'fut Self::Datum,
+        _transaction: &'fut B::Transaction<'t>,
+        _cfs: &'fut [B::TransactionCf<'t>],
+    ) -> waaa::BoxFuture<'fut, eyre::Result<()>> {
+        Box::pin(async { Ok(()) })
+    }
+
+    fn unindex<'fut, 't>(
+        &'fut self,
+        object_key: &'fut [u8],
+        _datum: &'fut Self::Datum,
+        transaction: &'fut B::Transaction<'t>,
+        cfs: &'fut [B::TransactionCf<'t>],
+    ) -> waaa::BoxFuture<'fut, eyre::Result<()>> {
+        Box::pin(async move { self.deleted::<B>(object_key, transaction, cfs).await })
+    }
+
+    fn reindex<'fut, 't>(
+        &'fut self,
+        _object_key: &'fut [u8],
+        _old: &'fut Self::Datum,
+        _new: &'fut Self::Datum,
+        _transaction: &'fut B::Transaction<'t>,
+        _cfs: &'fut [B::TransactionCf<'t>],
+    ) -> waaa::BoxFuture<'fut, eyre::Result<()>> {
+        // The object still exists, so the references to it stay valid
+        Box::pin(async { Ok(()) })
+    }
+
+    fn index_from_slice<'fut, 't>(
+        &'fut self,
+        _object_key: &'fut [u8],
+        _slice: &'fut [u8],
+        _transaction: &'fut B::Transaction<'t>,
+        _cfs: &'fut [B::TransactionCf<'t>],
+    ) -> waaa::BoxFuture<'fut, eyre::Result<()>> {
+        Box::pin(async { Ok(()) })
+    }
+
+    fn unindex_from_slice<'fut, 't>(
+        &'fut self,
+        object_key: &'fut [u8],
+        _slice: &'fut [u8],
+        transaction: &'fut B::Transaction<'t>,
+        cfs: &'fut [B::TransactionCf<'t>],
+    ) -> waaa::BoxFuture<'fut, eyre::Result<()>> {
+        Box::pin(async move { self.deleted::<B>(object_key, transaction, cfs).await })
+    }
+
+    fn rebuild<'fut, 't>(
+        &'fut self,
+        _transaction: &'fut B::Transaction<'t>,
+        _index_cfs: &'fut [B::TransactionCf<'t>],
+        _datum_cf: &'fut B::TransactionCf<'t>,
+    ) -> waaa::BoxFuture<'fut, eyre::Result<()>> {
+        // Nothing is stored, so there is nothing to rebuild
+        Box::pin(async { Ok(()) })
+    }
+}
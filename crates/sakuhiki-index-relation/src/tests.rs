@@ -0,0 +1,425 @@
+use futures_util::TryStreamExt as _;
+use sakuhiki_core::{Backend, Datum as _, Indexer, Mode, Transaction, TransactionCf};
+
+use crate::*;
+
+struct User;
+
+impl sakuhiki_core::Datum for User {
+    const CF: &'static str = "user";
+
+    fn from_slice(_datum: &[u8]) -> eyre::Result<Self> {
+        Ok(Self)
+    }
+
+    fn to_bytes(&self) -> eyre::Result<Vec<u8>> {
+        Ok(Vec::new())
+    }
+}
+
+impl<B: Backend> sakuhiki_core::IndexedDatum<B> for User {
+    const INDEXES: &'static [&'static dyn Indexer<B, Datum = Self>] =
+        &[&ReferencedBy::new(Comment::AUTHOR, OnDelete::Restrict)];
+}
+
+struct Post;
+
+impl sakuhiki_core::Datum for Post {
+    const CF: &'static str = "post";
+
+    fn from_slice(_datum: &[u8]) -> eyre::Result<Self> {
+        Ok(Self)
+    }
+
+    fn to_bytes(&self) -> eyre::Result<Vec<u8>> {
+        Ok(Vec::new())
+    }
+}
+
+impl<B: Backend> sakuhiki_core::IndexedDatum<B> for Post {
+    const INDEXES: &'static [&'static dyn Indexer<B, Datum = Self>] =
+        &[&ReferencedBy::new(Comment::POST, OnDelete::Cascade)];
+}
+
+#[derive(Debug)]
+struct Comment {
+    post: Vec<u8>,
+    author: Option<Vec<u8>>,
+}
+
+impl sakuhiki_core::Datum for Comment {
+    const CF: &'static str = "comment";
+
+    fn from_slice(datum: &[u8]) -> eyre::Result<Self> {
+        let mut parts = datum.splitn(2, |&b| b == b'/');
+        Ok(Self {
+            post: parts.next().unwrap().to_vec(),
+            author: parts.next().map(|a| a.to_vec()),
+        })
+    }
+
+    fn to_bytes(&self) -> eyre::Result<Vec<u8>> {
+        let mut res = self.post.clone();
+        if let Some(author) = &self.author {
+            res.push(b'/');
+            res.extend_from_slice(author);
+        }
+        Ok(res)
+    }
+}
+
+impl Comment {
+    const POST: &'static RelationIndex<Comment, Post> =
+        &RelationIndex::<Comment, Post>::new(&["comment-post"], |c| Some(c.post.as_slice()))
+            .validate_target();
+    const AUTHOR: &'static RelationIndex<Comment, User> =
+        &RelationIndex::new(&["comment-author"], |c| c.author.as_deref());
+
+    fn new(post: &[u8], author: Option<&[u8]>) -> Self {
+        Self {
+            post: post.to_vec(),
+            author: author.map(|a| a.to_vec()),
+        }
+    }
+}
+
+impl<B: Backend> sakuhiki_core::IndexedDatum<B> for Comment {
+    const INDEXES: &'static [&'static dyn Indexer<B, Datum = Self>] = &[
+        Self::POST,
+        Self::AUTHOR,
+        &ReferencedBy::new(Reaction::COMMENT, OnDelete::Restrict),
+    ];
+}
+
+/// Reaction to a comment, stored as the key of the comment
+struct Reaction(Vec<u8>);
+
+impl sakuhiki_core::Datum for Reaction {
+    const CF: &'static str = "reaction";
+
+    fn from_slice(datum: &[u8]) -> eyre::Result<Self> {
+        Ok(Self(datum.to_vec()))
+    }
+
+    fn to_bytes(&self) -> eyre::Result<Vec<u8>> {
+        Ok(self.0.clone())
+    }
+}
+
+impl Reaction {
+    const COMMENT: &'static RelationIndex<Reaction, Comment> =
+        &RelationIndex::new(&["reaction-comment"], |r| Some(r.0.as_slice()));
+}
+
+impl<B: Backend> sakuhiki_core::IndexedDatum<B> for Reaction {
+    const INDEXES: &'static [&'static dyn Indexer<B, Datum = Self>] = &[Self::COMMENT];
+}
+
+/// Node of a tree, whose deletion cascades to its children
+struct Node(Option<Vec<u8>>);
+
+impl sakuhiki_core::Datum for Node {
+    const CF: &'static str = "node";
+
+    fn from_slice(datum: &[u8]) -> eyre::Result<Self> {
+        Ok(Self((!datum.is_empty()).then(|| datum.to_vec())))
+    }
+
+    fn to_bytes(&self) -> eyre::Result<Vec<u8>> {
+        Ok(self.0.clone().unwrap_or_default())
+    }
+}
+
+impl Node {
+    const PARENT: &'static RelationIndex<Node, Node> =
+        &RelationIndex::new(&["node-parent"], |n| n.0.as_deref());
+}
+
+impl<B: Backend> sakuhiki_core::IndexedDatum<B> for Node {
+    const INDEXES: &'static [&'static dyn Indexer<B, Datum = Self>] = &[
+        Self::PARENT,
+        &ReferencedBy::new(Self::PARENT, OnDelete::Cascade),
+    ];
+}
+
+async fn referencing<'t, B, T>(
+    t: &Transaction<'t, B>,
+    comment: &TransactionCf<'t, B>,
+    relation: &'static RelationIndex<Comment, T>,
+    target: &[u8],
+) -> Vec<Vec<u8>>
+where
+    B: Backend,
+    T: sakuhiki_core::Datum,
+{
+    t.query_keys(comment, relation, &RelationQuery::target(target))
+        .unwrap()
+        .map_ok(|k| k.as_ref().to_vec())
+        .try_collect()
+        .await
+        .unwrap()
+}
+
+#[tokio::test]
+async fn test_relation() {
+    let db = sakuhiki_memdb::MemDb::builder()
+        .datum::<User>()
+        .datum::<Post>()
+        .datum::<Comment>()
+        .datum::<Reaction>()
+        .build()
+        .await
+        .unwrap();
+    let (user, post, comment) = (
+        db.cf_handle::<User>().await.unwrap(),
+        db.cf_handle::<Post>().await.unwrap(),
+        db.cf_handle::<Comment>().await.unwrap(),
+    );
+    db.transaction(
+        Mode::ReadWrite,
+        &[&user, &post, &comment],
+        |t, [user, post, comment]| {
+            Box::pin(async move {
+                t.put_datum(&user, b"alice", &User).await?;
+                t.put_datum(&post, b"p1", &Post).await?;
+                t.put_datum(&post, b"p2", &Post).await?;
+                for (key, post, author) in [
+                    (b"c1", b"p1", Some(&b"alice"[..])),
+                    (b"c2", b"p1", None),
+                    (b"c3", b"p2", Some(&b"alice"[..])),
+                ] {
+                    t.put_datum(&comment, key, &Comment::new(post, author))
+                        .await?;
+                }
+                assert_eq!(
+                    referencing(&t, &comment, Comment::POST, b"p1").await,
+                    [b"c1", b"c2"]
+                );
+                assert_eq!(
+                    t.count(&comment, Comment::POST, &RelationQuery::target(b"p2"))
+                        .await?,
+                    1
+                );
+                // Moving a comment updates the relation
+                t.put_datum(&comment, b"c2", &Comment::new(b"p2", None))
+                    .await?;
+                assert_eq!(
+                    referencing(&t, &comment, Comment::POST, b"p1").await,
+                    [b"c1"]
+                );
+                let (key, value) = t
+                    .query(&comment, Comment::POST, &RelationQuery::target(b"p2"))?
+                    .try_next()
+                    .await?
+                    .unwrap();
+                assert_eq!(key.as_ref(), b"c2");
+                assert_eq!(Comment::from_slice(value.as_ref())?.post, b"p2");
+                Ok(())
+            })
+        },
+    )
+    .await
+    .unwrap();
+    // Referencing a missing post fails
+    let comment = db.cf_handle::<Comment>().await.unwrap();
+    let err = db
+        .transaction(Mode::ReadWrite, &[&comment], |t, [comment]| {
+            Box::pin(async move {
+                t.put_datum(&comment, b"c4", &Comment::new(b"p3", None))
+                    .await?;
+                Ok(())
+            })
+        })
+        .await
+        .unwrap_err();
+    match err.downcast_ref::<MissingRelationTarget>() {
+        Some(MissingRelationTarget {
+            cf,
+            object_key,
+            target_cf,
+            target_key,
+        }) => {
+            assert_eq!((*cf, *target_cf), ("comment", "post"));
+            assert_eq!((&object_key[..], &target_key[..]), (&b"c4"[..], &b"p3"[..]));
+        }
+        _ => panic!("unexpected error {err:?}"),
+    }
+    // Deleting a user with comments is restricted
+    let user = db.cf_handle::<User>().await.unwrap();
+    let err = db
+        .transaction(Mode::ReadWrite, &[&user], |t, [user]| {
+            Box::pin(async move {
+                t.delete::<User>(&user, b"alice").await?;
+                Ok(())
+            })
+        })
+        .await
+        .unwrap_err();
+    match err.downcast_ref::<RelationRestricted>() {
+        Some(RelationRestricted {
+            cf,
+            object_key,
+            source_cf,
+            source_key,
+        }) => {
+            assert_eq!((*cf, *source_cf), ("user", "comment"));
+            assert_eq!(
+                (&object_key[..], &source_key[..]),
+                (&b"alice"[..], &b"c1"[..])
+            );
+        }
+        _ => panic!("unexpected error {err:?}"),
+    }
+    // Recovering from refused writes and committing leaves the datums consistent
+    let (user, comment) = (
+        db.cf_handle::<User>().await.unwrap(),
+        db.cf_handle::<Comment>().await.unwrap(),
+    );
+    db.transaction(Mode::ReadWrite, &[&user, &comment], |t, [user, comment]| {
+        Box::pin(async move {
+            let err = t
+                .put_datum(&comment, b"c4", &Comment::new(b"p3", None))
+                .await
+                .unwrap_err();
+            assert!(err.downcast_ref::<MissingRelationTarget>().is_some());
+            let err = t.delete::<User>(&user, b"alice").await.unwrap_err();
+            assert!(err.downcast_ref::<RelationRestricted>().is_some());
+            Ok(())
+        })
+    })
+    .await
+    .unwrap();
+    let (user, comment) = (
+        db.cf_handle::<User>().await.unwrap(),
+        db.cf_handle::<Comment>().await.unwrap(),
+    );
+    db.transaction(Mode::ReadOnly, &[&user, &comment], |t, [user, comment]| {
+        Box::pin(async move {
+            assert!(t.get(&comment, b"c4").await?.is_none());
+            assert!(t.get(&user, b"alice").await?.is_some());
+            assert!(
+                referencing(&t, &comment, Comment::POST, b"p3")
+                    .await
+                    .is_empty()
+            );
+            Ok(())
+        })
+    })
+    .await
+    .unwrap();
+    // Deleting a post deletes its comments, and their other relations
+    let post = db.cf_handle::<Post>().await.unwrap();
+    db.transaction(Mode::ReadWrite, &[&post], |t, [post]| {
+        Box::pin(async move {
+            t.delete::<Post>(&post, b"p1").await?;
+            Ok(())
+        })
+    })
+    .await
+    .unwrap();
+    let comment = db.cf_handle::<Comment>().await.unwrap();
+    db.transaction(Mode::ReadOnly, &[&comment], |t, [comment]| {
+        Box::pin(async move {
+            assert!(t.get(&comment, b"c1").await?.is_none());
+            assert_eq!(
+                referencing(&t, &comment, Comment::AUTHOR, b"alice").await,
+                [b"c3"]
+            );
+            assert_eq!(
+                referencing(&t, &comment, Comment::POST, b"p2").await,
+                [b"c2", b"c3"]
+            );
+            Ok(())
+        })
+    })
+    .await
+    .unwrap();
+    // Rebuilding goes through the targets again
+    db.rebuild_index(Comment::POST).await.unwrap();
+    let (user, post) = (
+        db.cf_handle::<User>().await.unwrap(),
+        db.cf_handle::<Post>().await.unwrap(),
+    );
+    db.transaction(Mode::ReadWrite, &[&user, &post], |t, [user, post]| {
+        Box::pin(async move {
+            t.delete::<Post>(&post, b"p2").await?;
+            t.delete::<User>(&user, b"alice").await?;
+            Ok(())
+        })
+    })
+    .await
+    .unwrap();
+}
+
+#[tokio::test]
+async fn test_restrict_through_cascade() {
+    let db = sakuhiki_memdb::MemDb::builder()
+        .datum::<User>()
+        .datum::<Post>()
+        .datum::<Comment>()
+        .datum::<Reaction>()
+        .build()
+        .await
+        .unwrap();
+    let (post, comment, reaction) = (
+        db.cf_handle::<Post>().await.unwrap(),
+        db.cf_handle::<Comment>().await.unwrap(),
+        db.cf_handle::<Reaction>().await.unwrap(),
+    );
+    db.transaction(
+        Mode::ReadWrite,
+        &[&post, &comment, &reaction],
+        |t, [post, comment, reaction]| {
+            Box::pin(async move {
+                t.put_datum(&post, b"p1", &Post).await?;
+                t.put_datum(&comment, b"c1", &Comment::new(b"p1", None))
+                    .await?;
+                t.put_datum(&comment, b"c2", &Comment::new(b"p1", None))
+                    .await?;
+                t.put_datum(&reaction, b"r1", &Reaction(b"c2".to_vec()))
+                    .await?;
+                // Deleting the post would cascade to a comment that still has reactions
+                let err = t.delete::<Post>(&post, b"p1").await.unwrap_err();
+                match err.downcast_ref::<RelationRestricted>() {
+                    Some(RelationRestricted {
+                        cf,
+                        object_key,
+                        source_cf,
+                        source_key,
+                    }) => {
+                        assert_eq!((*cf, *source_cf), ("comment", "reaction"));
+                        assert_eq!((&object_key[..], &source_key[..]), (&b"c2"[..], &b"r1"[..]));
+                    }
+                    _ => panic!("unexpected error {err:?}"),
+                }
+                Ok(())
+            })
+        },
+    )
+    .await
+    .unwrap();
+    // The refused deletion did not write anything, not even the comments it reached first
+    let (post, comment) = (
+        db.cf_handle::<Post>().await.unwrap(),
+        db.cf_handle::<Comment>().await.unwrap(),
+    );
+    db.transaction(Mode::ReadOnly, &[&post, &comment], |t, [post, comment]| {
+        Box::pin(async move {
+            assert!(t.get(&post, b"p1").await?.is_some());
+            assert!(t.get(&comment, b"c1").await?.is_some());
+            assert_eq!(
+                referencing(&t, &comment, Comment::POST, b"p1").await,
+                [b"c1", b"c2"]
+            );
+            Ok(())
+        })
+    })
+    .await
+    .unwrap();
+}
+
+#[test]
+#[should_panic(expected = "Cascading deletions form a cycle through CFs node -> node")]
+fn test_cascade_cycle() {
+    sakuhiki_memdb::MemDb::builder().datum::<Node>();
+}
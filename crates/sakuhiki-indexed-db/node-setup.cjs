@@ -0,0 +1,11 @@
+// Loaded through `NODE_OPTIONS` when running the `wasm-bindgen-test` tests under node, which has
+// no IndexedDB of its own. See the `test-wasm` recipe of the Justfile.
+require("fake-indexeddb/auto");
+
+// `indexed-db` only looks IndexedDB up from a `Window` or a worker scope, so make the global
+// object pass for a `Window`
+globalThis.Window = class Window {
+  static [Symbol.hasInstance](obj) {
+    return obj === globalThis;
+  }
+};
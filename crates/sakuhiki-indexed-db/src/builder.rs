@@ -0,0 +1,84 @@
+use eyre::WrapErr as _;
+use indexed_db::Factory;
+use sakuhiki_core::{BackendBuilder, backend::BuilderConfig};
+
+use crate::{IndexedDb, error::from_idb};
+
+pub struct IndexedDbBuilder {
+    name: String,
+}
+
+impl IndexedDbBuilder {
+    pub(crate) fn new(name: String) -> Self {
+        IndexedDbBuilder { name }
+    }
+}
+
+impl BackendBuilder for IndexedDbBuilder {
+    type Target = IndexedDb;
+
+    /// Object stores have no options that matter to sakuhiki, as keys and values are both binary
+    type CfOptions = ();
+
+    type BuildFuture = waaa::BoxFuture<'static, eyre::Result<IndexedDb>>;
+
+    fn build(self, config: BuilderConfig<IndexedDb>) -> Self::BuildFuture {
+        Box::pin(async move {
+            let name = self.name;
+            let factory = Factory::<eyre::Report>::get()
+                .map_err(from_idb)
+                .wrap_err("Failed accessing IndexedDB")?;
+            let db = factory
+                .open_latest_version(&name)
+                .await
+                .map_err(from_idb)
+                .wrap_err_with(|| format!("Failed opening database {name}"))?;
+
+            // List the object stores to create and delete
+            let preexisting_cfs = db.object_store_names();
+            let created_cfs = config
+                .cfs
+                .keys()
+                .copied()
+                .filter(|cf| !preexisting_cfs.iter().any(|p| p == cf))
+                .collect::<Vec<_>>();
+            let dropped_cfs = if config.drop_unknown_cfs {
+                preexisting_cfs
+                    .into_iter()
+                    .filter(|cf| !config.cfs.contains_key(cf as &str))
+                    .collect::<Vec<_>>()
+            } else {
+                Vec::new()
+            };
+            if created_cfs.is_empty() && dropped_cfs.is_empty() {
+                return Ok(IndexedDb::new(db));
+            }
+
+            // Object stores can only be changed in the `versionchange` transaction of an upgrade
+            let version = db
+                .version()
+                .checked_add(1)
+                .ok_or_else(|| eyre::eyre!("Database {name} reached its maximum version"))?;
+            db.close();
+            let db = factory
+                .open(&name, version, move |evt| async move {
+                    let db = evt.database();
+                    for cf in &dropped_cfs {
+                        db.delete_object_store(cf)?;
+                    }
+                    for cf in &created_cfs {
+                        db.build_object_store(cf).create()?;
+                    }
+
+                    Ok(())
+                })
+                .await
+                .map_err(from_idb)
+                .wrap_err_with(|| {
+                    format!("Failed upgrading database {name} to version {version}")
+                })?;
+
+            Ok(IndexedDb::new(db))
+        })
+    }
+}
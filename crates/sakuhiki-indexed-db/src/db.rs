@@ -0,0 +1,99 @@
+use std::{
+    borrow::Borrow,
+    future::{Ready, ready},
+};
+
+use sakuhiki_core::{
+    Backend, Mode,
+    backend::{Builder, MaybeStatic},
+};
+
+use crate::{
+    Error, IndexedDbBuilder, Transaction, TransactionCf,
+    error::{IdbError, from_idb},
+};
+
+pub struct IndexedDb {
+    db: indexed_db::Database<eyre::Report>,
+}
+
+impl IndexedDb {
+    pub fn builder(name: impl Into<String>) -> Builder<IndexedDb> {
+        Builder::new(IndexedDbBuilder::new(name.into()))
+    }
+
+    pub(crate) fn new(db: indexed_db::Database<eyre::Report>) -> IndexedDb {
+        IndexedDb { db }
+    }
+}
+
+impl Drop for IndexedDb {
+    fn drop(&mut self) {
+        // Otherwise, the connection would stay open and block future database upgrades
+        self.db.close();
+    }
+}
+
+#[warn(clippy::missing_trait_methods)]
+impl Backend for IndexedDb {
+    type Builder = IndexedDbBuilder;
+
+    type Cf<'db> = &'static str;
+
+    type CfHandleFuture<'db> = Ready<eyre::Result<Self::Cf<'db>>>;
+
+    fn cf_handle<'db>(&'db self, name: &'static str) -> Self::CfHandleFuture<'db> {
+        if self.db.object_store_names().iter().any(|n| n == name) {
+            ready(Ok(name))
+        } else {
+            ready(Err(Error::NoSuchCf(name).into()))
+        }
+    }
+
+    type Transaction<'t> = Transaction;
+    type TransactionCf<'t> = TransactionCf;
+
+    fn transaction<'fut, 'db, Bcf, F, Ret>(
+        &'fut self,
+        mode: Mode,
+        cfs: &'fut [Bcf],
+        actions: F,
+    ) -> waaa::BoxFuture<'fut, eyre::Result<Ret>>
+    where
+        Bcf: 'fut + waaa::Send + waaa::Sync + Borrow<Self::Cf<'db>>,
+        F: 'fut
+            + waaa::Send
+            + MaybeStatic
+            + for<'t> FnOnce(
+                &'t Transaction,
+                Vec<TransactionCf>,
+            ) -> waaa::BoxFuture<'t, eyre::Result<Ret>>,
+        Ret: MaybeStatic,
+    {
+        Box::pin(async move {
+            let names = cfs.iter().map(|cf| *cf.borrow()).collect::<Vec<_>>();
+            let mut builder = self.db.transaction(&names);
+            // `versionchange` transactions can only happen upon opening the database, so index
+            // rebuilding at runtime uses `readwrite` transactions. These are already exclusive
+            // with all other transactions on the same object stores.
+            if mode != Mode::ReadOnly {
+                builder = builder.rw();
+            }
+            builder
+                .run(move |t| async move {
+                    let cfs = names
+                        .into_iter()
+                        .map(|name| Ok(TransactionCf::new(name, t.object_store(name)?)))
+                        .collect::<Result<Vec<_>, IdbError>>()?;
+                    let t = Transaction::new(mode);
+                    Ok((actions)(&t, cfs).await?)
+                })
+                .await
+                .map_err(from_idb)
+        })
+    }
+
+    // TODO(low): could these borrow from the JS values to avoid a copy?
+    type Key<'op> = Vec<u8>;
+    type Value<'op> = Vec<u8>;
+}
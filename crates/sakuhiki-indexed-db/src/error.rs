@@ -0,0 +1,20 @@
+#[derive(Debug, thiserror::Error)]
+#[non_exhaustive]
+pub enum Error {
+    #[error("CF {_0} does not exist")]
+    NoSuchCf(&'static str),
+
+    #[error("IndexedDB error: {_0}")]
+    IndexedDb(String),
+}
+
+/// Errors returned by `indexed-db`, with our own errors passing through as `User`
+pub(crate) type IdbError = indexed_db::Error<eyre::Report>;
+
+pub(crate) fn from_idb(err: IdbError) -> eyre::Report {
+    match err {
+        indexed_db::Error::User(err) => err,
+        // `eyre::Report` is not an `std::error::Error`, so neither is `IdbError`: stringify it
+        err => eyre::Report::from(Error::IndexedDb(err.to_string())),
+    }
+}
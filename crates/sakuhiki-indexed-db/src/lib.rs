@@ -1 +1,15 @@
-// TODO(high): implement
+// IndexedDB only exists in browsers, so this backend is only available on the web
+#![cfg(all(target_arch = "wasm32", target_os = "unknown"))]
+
+mod builder;
+mod db;
+mod error;
+mod transaction;
+
+pub use builder::IndexedDbBuilder;
+pub use db::IndexedDb;
+pub use error::Error;
+pub use transaction::{Transaction, TransactionCf};
+
+#[cfg(test)]
+mod tests;
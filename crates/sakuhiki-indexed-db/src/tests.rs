@@ -0,0 +1,112 @@
+use futures_util::TryStreamExt as _;
+use sakuhiki_core::{
+    Backend as _, BackendBuilder as _, Mode,
+    backend::{BuilderConfig, CfOptions, Transaction as _},
+    test_utils,
+};
+use wasm_bindgen_test::wasm_bindgen_test;
+
+use crate::{IndexedDb, IndexedDbBuilder};
+
+async fn open(name: &str, cfs: &[&'static str]) -> IndexedDb {
+    indexed_db::Factory::<()>::get()
+        .unwrap()
+        .delete_database(name)
+        .await
+        .unwrap();
+    let config = BuilderConfig {
+        cfs: cfs
+            .iter()
+            .map(|cf| (*cf, CfOptions::NotConfigured))
+            .collect(),
+        drop_unknown_cfs: false,
+    };
+    IndexedDbBuilder::new(name.to_string())
+        .build(config)
+        .await
+        .unwrap()
+}
+
+#[wasm_bindgen_test]
+async fn test_transaction_operations() {
+    let db = open("test_transaction_operations", &[test_utils::CF]).await;
+    assert!(db.cf_handle("bar").await.is_err());
+    test_utils::transaction_operations(&db).await;
+}
+
+#[wasm_bindgen_test]
+async fn test_scan_and_clear() {
+    test_utils::scan_and_clear(&open("test_scan_and_clear", &[test_utils::CF]).await).await;
+}
+
+#[wasm_bindgen_test]
+async fn test_scan_empty_range() {
+    let db = open("test_scan_empty_range", &["foo"]).await;
+    let cf = db.cf_handle("foo").await.unwrap();
+    db.transaction(Mode::ReadWrite, &[&cf], |t, cfs| {
+        Box::pin(async move {
+            t.put(&cfs[0], &10u32.to_be_bytes(), b"1").await?;
+            // IndexedDB refuses key ranges whose start is after their end
+            let empty = t
+                .scan(&cfs[0], 300u32.to_be_bytes()..10u32.to_be_bytes())
+                .try_collect::<Vec<_>>()
+                .await?;
+            assert!(empty.is_empty());
+            Ok(())
+        })
+    })
+    .await
+    .unwrap();
+}
+
+#[wasm_bindgen_test]
+async fn test_commit_and_rollback() {
+    let db = open("test_commit_and_rollback", &[test_utils::CF]).await;
+    test_utils::commit_and_rollback(&db).await;
+}
+
+#[wasm_bindgen_test]
+async fn test_mode_enforcement() {
+    test_utils::mode_enforcement(&open("test_mode_enforcement", &[test_utils::CF]).await).await;
+}
+
+#[wasm_bindgen_test]
+async fn test_upgrade() {
+    let db = open("test_upgrade", &["foo", "bar"]).await;
+    let cf = db.cf_handle("foo").await.unwrap();
+    db.transaction(Mode::ReadWrite, &[&cf], |t, cfs| {
+        Box::pin(async move {
+            t.put(&cfs[0], b"a", b"1").await?;
+            Ok(())
+        })
+    })
+    .await
+    .unwrap();
+    drop(db);
+
+    let config = BuilderConfig {
+        cfs: [
+            ("foo", CfOptions::NotConfigured),
+            ("baz", CfOptions::NotConfigured),
+        ]
+        .into_iter()
+        .collect(),
+        drop_unknown_cfs: true,
+    };
+    let db = IndexedDbBuilder::new(String::from("test_upgrade"))
+        .build(config)
+        .await
+        .unwrap();
+    assert!(db.cf_handle("bar").await.is_err());
+    let foo = db.cf_handle("foo").await.unwrap();
+    let baz = db.cf_handle("baz").await.unwrap();
+    db.transaction(Mode::ReadOnly, &[&foo, &baz], |t, cfs| {
+        Box::pin(async move {
+            assert_eq!(t.get(&cfs[0], b"a").await?, Some(b"1".to_vec()));
+            assert_eq!(t.get(&cfs[1], b"a").await?, None);
+            Ok(())
+        })
+    })
+    .await
+    .unwrap();
+}
@@ -0,0 +1,205 @@
+use std::ops::{Bound, RangeBounds};
+
+use eyre::WrapErr as _;
+use indexed_db::{CursorDirection, ObjectStore};
+use sakuhiki_core::{Backend, CfOperationError, Mode, backend::BackendCf};
+use web_sys::{js_sys::Uint8Array, wasm_bindgen::JsValue};
+
+use crate::{IndexedDb, error::from_idb};
+
+pub struct Transaction {
+    mode: Mode,
+}
+
+impl Transaction {
+    pub(crate) fn new(mode: Mode) -> Self {
+        Self { mode }
+    }
+}
+
+pub struct TransactionCf {
+    name: &'static str,
+    store: ObjectStore<eyre::Report>,
+}
+
+impl BackendCf for TransactionCf {
+    fn name(&self) -> &'static str {
+        self.name
+    }
+}
+
+impl TransactionCf {
+    pub(crate) fn new(name: &'static str, store: ObjectStore<eyre::Report>) -> Self {
+        Self { name, store }
+    }
+
+    async fn get(&self, key: &JsValue) -> eyre::Result<Option<Vec<u8>>> {
+        let value = self.store.get(key).await.map_err(from_idb)?;
+        Ok(value.as_ref().map(from_js))
+    }
+}
+
+/// Keys and values are both stored as binary, which IndexedDB sorts lexicographically
+fn to_js(bytes: &[u8]) -> JsValue {
+    Uint8Array::from(bytes).into()
+}
+
+fn from_js(value: &JsValue) -> Vec<u8> {
+    Uint8Array::new(value).to_vec()
+}
+
+/// Returns `true` iff no key can fit in between `start` and `end`
+///
+/// IndexedDB refuses to build such key ranges, instead of returning no results.
+fn is_empty_range(start: Bound<&[u8]>, end: Bound<&[u8]>) -> bool {
+    match (start, end) {
+        (Bound::Included(s), Bound::Included(e)) => s > e,
+        (Bound::Included(s) | Bound::Excluded(s), Bound::Included(e) | Bound::Excluded(e)) => {
+            s >= e
+        }
+        (Bound::Unbounded, _) | (_, Bound::Unbounded) => false,
+    }
+}
+
+/// Scan `keys` in `cf`, with a cursor going in `direction`
+fn scan_in<'op, 'keys, R>(
+    cf: &'op TransactionCf,
+    keys: impl 'keys + RangeBounds<R>,
+    direction: CursorDirection,
+) -> waaa::BoxStream<'keys, eyre::Result<(Vec<u8>, Vec<u8>)>>
+where
+    'op: 'keys,
+    R: ?Sized + AsRef<[u8]>,
+{
+    let start = keys.start_bound().map(|k| k.as_ref());
+    let end = keys.end_bound().map(|k| k.as_ref());
+    let is_empty = is_empty_range(start, end);
+    let is_full = matches!((start, end), (Bound::Unbounded, Bound::Unbounded));
+    let range = (start.map(to_js), end.map(to_js));
+    Box::pin(async_stream::try_stream! {
+        if !is_empty {
+            let scan_err = || CfOperationError::new("Failed scanning", cf.name());
+            let mut cursor = cf.store.cursor().direction(direction);
+            if !is_full {
+                cursor = cursor.range(range).map_err(from_idb).wrap_err_with(scan_err)?;
+            }
+            let mut cursor = cursor.open().await.map_err(from_idb).wrap_err_with(scan_err)?;
+            while let Some(key) = cursor.key() {
+                let value = cursor.value().expect("Cursor has a key but no value");
+                yield (from_js(&key), from_js(&value));
+                cursor.advance(1).await.map_err(from_idb).wrap_err_with(scan_err)?;
+            }
+        }
+    })
+}
+
+impl<'t> sakuhiki_core::backend::Transaction<'t, IndexedDb> for Transaction {
+    fn current_mode(&self) -> Mode {
+        self.mode
+    }
+
+    fn get<'op, 'key>(
+        &'op self,
+        cf: &'op TransactionCf,
+        key: &'key [u8],
+    ) -> waaa::BoxFuture<'key, eyre::Result<Option<Vec<u8>>>>
+    where
+        'op: 'key,
+    {
+        Box::pin(async move {
+            cf.get(&to_js(key))
+                .await
+                .wrap_err_with(|| CfOperationError::new("Failed reading from", cf.name()))
+        })
+    }
+
+    fn scan<'op, 'keys, R>(
+        &'op self,
+        cf: &'op TransactionCf,
+        keys: impl 'keys + RangeBounds<R>,
+    ) -> waaa::BoxStream<'keys, eyre::Result<(Vec<u8>, Vec<u8>)>>
+    where
+        't: 'op,
+        'op: 'keys,
+        R: ?Sized + AsRef<[u8]>,
+    {
+        scan_in(cf, keys, CursorDirection::Next)
+    }
+
+    fn scan_rev<'op, 'keys, R>(
+        &'op self,
+        cf: &'op TransactionCf,
+        keys: impl 'keys + RangeBounds<R>,
+    ) -> waaa::BoxStream<'keys, eyre::Result<(Vec<u8>, Vec<u8>)>>
+    where
+        't: 'op,
+        'op: 'keys,
+        R: ?Sized + AsRef<[u8]>,
+    {
+        scan_in(cf, keys, CursorDirection::Prev)
+    }
+
+    fn put<'op, 'kv>(
+        &'op self,
+        cf: &'op TransactionCf,
+        key: &'kv [u8],
+        value: &'kv [u8],
+    ) -> waaa::BoxFuture<'kv, eyre::Result<Option<Vec<u8>>>>
+    where
+        't: 'op,
+        'op: 'kv,
+    {
+        Box::pin(async move {
+            self.mode.check_writable()?;
+            async {
+                let key = to_js(key);
+                let old = cf.get(&key).await?;
+                cf.store
+                    .put_kv(&key, &to_js(value))
+                    .await
+                    .map_err(from_idb)?;
+                Ok::<_, eyre::Report>(old)
+            }
+            .await
+            .wrap_err_with(|| CfOperationError::new("Failed putting into", cf.name()))
+        })
+    }
+
+    fn delete<'op, 'key>(
+        &'op self,
+        cf: &'op TransactionCf,
+        key: &'key [u8],
+    ) -> waaa::BoxFuture<'key, eyre::Result<Option<Vec<u8>>>>
+    where
+        't: 'op,
+        'op: 'key,
+    {
+        Box::pin(async move {
+            self.mode.check_writable()?;
+            async {
+                let key = to_js(key);
+                let old = cf.get(&key).await?;
+                if old.is_some() {
+                    cf.store.delete(&key).await.map_err(from_idb)?;
+                }
+                Ok::<_, eyre::Report>(old)
+            }
+            .await
+            .wrap_err_with(|| CfOperationError::new("Failed deleting from", cf.name()))
+        })
+    }
+
+    fn clear<'op>(
+        &'op self,
+        cf: &'op <IndexedDb as Backend>::TransactionCf<'t>,
+    ) -> waaa::BoxFuture<'op, eyre::Result<()>> {
+        Box::pin(async move {
+            self.mode.check_index_rebuilding()?;
+            cf.store
+                .clear()
+                .await
+                .map_err(from_idb)
+                .wrap_err_with(|| CfOperationError::new("Failed clearing", cf.name()))
+        })
+    }
+}
@@ -1,23 +1,23 @@
 use std::{
     borrow::Borrow,
-    collections::BTreeMap,
+    collections::{BTreeMap, HashMap},
     future::{Ready, ready},
     ops::{Bound, RangeBounds},
     sync::Mutex,
 };
 
-use async_lock::{Mutex as AsyncMutex, MutexGuard as AsyncMutexGuard};
+use async_lock::Mutex as AsyncMutex;
 use eyre::{WrapErr as _, eyre};
 use futures_util::stream;
 use sakuhiki_core::{
     Backend, CfOperationError, Mode,
-    backend::{BackendBuilder, BackendCf, Builder, BuilderConfig},
+    backend::{BackendBuilder, BackendCf, Builder, BuilderConfig, MaybeStatic},
 };
 
 type ColumnFamily = BTreeMap<Vec<u8>, Vec<u8>>;
 
 pub struct TransactionCf<'t> {
-    cf: Mutex<AsyncMutexGuard<'t, ColumnFamily>>,
+    cf: &'t ColumnFamily,
     name: &'static str,
 }
 
@@ -62,36 +62,64 @@ impl Backend for MemDb {
         Bcf: 'fut + waaa::Send + waaa::Sync + Borrow<Self::Cf<'db>>,
         F: 'fut
             + waaa::Send
-            + for<'t> FnOnce(&'t (), Transaction, Vec<TransactionCf<'t>>) -> waaa::BoxFuture<'t, Ret>,
+            + MaybeStatic
+            + for<'t> FnOnce(
+                &'t Transaction,
+                Vec<TransactionCf<'t>>,
+            ) -> waaa::BoxFuture<'t, eyre::Result<Ret>>,
+        Ret: MaybeStatic,
     {
         // Ignore the mode: we always lock it all here anyway
         Box::pin(async move {
-            let t = Transaction { mode };
+            let t = Transaction {
+                mode,
+                writes: Mutex::new(HashMap::new()),
+            };
             let mut cfs = cfs
                 .iter()
                 .map(|cf| cf.borrow())
                 .enumerate()
                 .collect::<Vec<_>>();
             cfs.sort_by_key(|e| e.1);
-            let mut transaction_cfs = Vec::with_capacity(cfs.len());
+            let cfs_len = cfs.len();
+            let mut locked_cfs: Vec<(Vec<usize>, _, _)> = Vec::with_capacity(cfs.len());
             for (i, &name) in cfs {
-                // TODO(med): this ok_or_else should definitely be a proper error type
-                let cf = self
-                    .db
-                    .get(name)
-                    .ok_or_else(|| eyre!("Column family does not exist"))
-                    .wrap_err_with(|| {
-                        CfOperationError::new("Column family does not exist:", name)
-                    })?;
-                let cf = Mutex::new(cf.lock().await);
-                transaction_cfs.push((i, TransactionCf { name, cf }));
+                // The same CF can be requested multiple times, but must only be locked once
+                match locked_cfs.last_mut() {
+                    Some((indices, last, _)) if *last == name => indices.push(i),
+                    _ => {
+                        // TODO(med): this ok_or_else should definitely be a proper error type
+                        let cf = self
+                            .db
+                            .get(name)
+                            .ok_or_else(|| eyre!("Column family does not exist"))
+                            .wrap_err_with(|| {
+                                CfOperationError::new("Column family does not exist:", name)
+                            })?;
+                        locked_cfs.push((vec![i], name, cf.lock().await));
+                    }
+                }
+            }
+            let mut transaction_cfs = Vec::with_capacity(cfs_len);
+            for (indices, name, cf) in &locked_cfs {
+                for i in indices {
+                    transaction_cfs.push((*i, TransactionCf { name, cf }));
+                }
             }
             transaction_cfs.sort_by_key(|e| e.0);
             let transaction_cfs = transaction_cfs
                 .into_iter()
                 .map(|(_, cf)| cf)
                 .collect::<Vec<_>>();
-            Ok(actions(&(), t, transaction_cfs).await)
+            let res = actions(&t, transaction_cfs).await?;
+            // Commit the buffered writes. Not reached on errors or panics, thus rolling back.
+            let mut writes = t.writes.into_inner().unwrap();
+            for (_, name, cf) in &mut locked_cfs {
+                if let Some(writes) = writes.remove(name) {
+                    writes.apply_to(cf);
+                }
+            }
+            Ok(res)
         })
     }
 
@@ -99,8 +127,97 @@ impl Backend for MemDb {
     type Value<'op> = Vec<u8>;
 }
 
+/// Writes buffered by a transaction on a single CF, until it gets committed
+#[derive(Default)]
+struct CfWrites {
+    cleared: bool,
+    /// `None` values are deletions
+    writes: BTreeMap<Vec<u8>, Option<Vec<u8>>>,
+}
+
+impl CfWrites {
+    fn get<'a>(&'a self, cf: &'a ColumnFamily, key: &[u8]) -> Option<&'a Vec<u8>> {
+        match self.writes.get(key) {
+            Some(value) => value.as_ref(),
+            None if self.cleared => None,
+            None => cf.get(key),
+        }
+    }
+
+    fn apply_to(self, cf: &mut ColumnFamily) {
+        if self.cleared {
+            cf.clear();
+        }
+        for (key, value) in self.writes {
+            match value {
+                Some(value) => cf.insert(key, value),
+                None => cf.remove(&key),
+            };
+        }
+    }
+}
+
 pub struct Transaction {
     mode: Mode,
+    writes: Mutex<HashMap<&'static str, CfWrites>>,
+}
+
+impl Transaction {
+    /// Returns the value `key` would have in `cf` if the transaction were committed now
+    fn read(&self, cf: &TransactionCf<'_>, key: &[u8]) -> Option<Vec<u8>> {
+        match self.writes.lock().unwrap().get(cf.name) {
+            Some(writes) => writes.get(cf.cf, key).cloned(),
+            None => cf.cf.get(key).cloned(),
+        }
+    }
+
+    /// Returns the key-value pairs `keys` would contain in `cf` if the transaction were committed
+    /// now, in ascending order
+    fn read_range<R>(
+        &self,
+        cf: &TransactionCf<'_>,
+        keys: impl RangeBounds<R>,
+    ) -> Vec<eyre::Result<(Vec<u8>, Vec<u8>)>>
+    where
+        R: ?Sized + AsRef<[u8]>,
+    {
+        let start: Bound<&[u8]> = keys.start_bound().map(|k| k.as_ref());
+        let end: Bound<&[u8]> = keys.end_bound().map(|k| k.as_ref());
+        let all_writes = self.writes.lock().unwrap();
+        let cf_writes = all_writes.get(cf.name);
+        let mut result = BTreeMap::new();
+        if !cf_writes.is_some_and(|w| w.cleared) {
+            result.extend(
+                cf.cf
+                    .range::<[u8], _>((start, end))
+                    .map(|(k, v)| (k.to_owned(), Some(v.to_owned()))),
+            );
+        }
+        if let Some(cf_writes) = cf_writes {
+            result.extend(
+                cf_writes
+                    .writes
+                    .range::<[u8], _>((start, end))
+                    .map(|(k, v)| (k.to_owned(), v.to_owned())),
+            );
+        }
+        result
+            .into_iter()
+            .filter_map(|(k, v)| v.map(|v| Ok((k, v))))
+            .collect()
+    }
+
+    fn write(&self, cf: &TransactionCf<'_>, key: &[u8], value: Option<&[u8]>) -> Option<Vec<u8>> {
+        let old = self.read(cf, key);
+        self.writes
+            .lock()
+            .unwrap()
+            .entry(cf.name)
+            .or_default()
+            .writes
+            .insert(key.to_vec(), value.map(|v| v.to_vec()));
+        old
+    }
 }
 
 // #[warn(clippy::missing_trait_methods)] // MemDb is used only for tests, we can use default impls
@@ -117,12 +234,7 @@ impl<'t> sakuhiki_core::backend::Transaction<'t, MemDb> for Transaction {
     where
         'op: 'key,
     {
-        Box::pin(ready(Ok(cf
-            .cf
-            .lock()
-            .unwrap()
-            .get(key)
-            .map(|v| v.to_owned()))))
+        Box::pin(ready(Ok(self.read(cf, key))))
     }
 
     fn scan<'op, 'keys, R>(
@@ -135,16 +247,22 @@ impl<'t> sakuhiki_core::backend::Transaction<'t, MemDb> for Transaction {
         'op: 'keys,
         R: ?Sized + AsRef<[u8]>,
     {
-        let start: Bound<&[u8]> = keys.start_bound().map(|k| k.as_ref());
-        let end: Bound<&[u8]> = keys.end_bound().map(|k| k.as_ref());
-        Box::pin(stream::iter(
-            cf.cf
-                .lock()
-                .unwrap()
-                .range::<[u8], _>((start, end))
-                .map(|(k, v)| Ok((k.to_owned(), v.to_owned())))
-                .collect::<Vec<_>>(),
-        ))
+        Box::pin(stream::iter(self.read_range(cf, keys)))
+    }
+
+    fn scan_rev<'op, 'keys, R>(
+        &'op self,
+        cf: &'op TransactionCf<'t>,
+        keys: impl 'keys + RangeBounds<R>,
+    ) -> waaa::BoxStream<'keys, eyre::Result<(Vec<u8>, Vec<u8>)>>
+    where
+        't: 'op,
+        'op: 'keys,
+        R: ?Sized + AsRef<[u8]>,
+    {
+        let mut result = self.read_range(cf, keys);
+        result.reverse();
+        Box::pin(stream::iter(result))
     }
 
     fn put<'op, 'kv>(
@@ -157,8 +275,11 @@ impl<'t> sakuhiki_core::backend::Transaction<'t, MemDb> for Transaction {
         't: 'op,
         'op: 'kv,
     {
-        let data = cf.cf.lock().unwrap().insert(key.to_vec(), value.to_vec());
-        Box::pin(ready(Ok(data)))
+        let res = self
+            .mode
+            .check_writable()
+            .map(|()| self.write(cf, key, Some(value)));
+        Box::pin(ready(res.map_err(eyre::Report::from)))
     }
 
     fn delete<'op, 'key>(
@@ -170,15 +291,24 @@ impl<'t> sakuhiki_core::backend::Transaction<'t, MemDb> for Transaction {
         't: 'op,
         'op: 'key,
     {
-        let data = cf.cf.lock().unwrap().remove(key);
-        Box::pin(ready(Ok(data)))
+        let res = self
+            .mode
+            .check_writable()
+            .map(|()| self.write(cf, key, None));
+        Box::pin(ready(res.map_err(eyre::Report::from)))
     }
 
     fn clear<'op>(
         &'op self,
         cf: &'op <MemDb as Backend>::TransactionCf<'t>,
     ) -> waaa::BoxFuture<'op, eyre::Result<()>> {
-        cf.cf.lock().unwrap().clear();
+        if let Err(err) = self.mode.check_index_rebuilding() {
+            return Box::pin(ready(Err(eyre::Report::from(err))));
+        }
+        let mut writes = self.writes.lock().unwrap();
+        let cf_writes = writes.entry(cf.name).or_default();
+        cf_writes.cleared = true;
+        cf_writes.writes.clear();
         Box::pin(ready(Ok(())))
     }
 }
@@ -203,24 +333,10 @@ impl BackendBuilder for MemDbBuilder {
                     .insert(cf.to_string(), AsyncMutex::new(ColumnFamily::new()));
             }
             // Note: drop_unknown_cfs currently has no impact as we're always starting from scratch, though it could be useful in tests to check db recovery
-            for i in config.index_rebuilders {
-                let mut index_cfs = Vec::with_capacity(i.index_cfs.len());
-                for cf in i.index_cfs {
-                    index_cfs.push(TransactionCf {
-                        name: cf,
-                        cf: Mutex::new(db.db.get(*cf).unwrap().lock().await),
-                    });
-                }
-                let datum_cf = TransactionCf {
-                    name: i.datum_cf,
-                    cf: Mutex::new(db.db.get(i.datum_cf).unwrap().lock().await),
-                };
-                let t = Transaction {
-                    mode: Mode::IndexRebuilding,
-                };
-                (i.rebuilder)(&t, &index_cfs, &datum_cf).await?;
-            }
             Ok(db)
         })
     }
 }
+
+#[cfg(test)]
+mod tests;
@@ -0,0 +1,137 @@
+use futures_util::TryStreamExt as _;
+use sakuhiki_core::{
+    BackendBuilder as _, Mode,
+    backend::{BuilderConfig, CfOptions, Transaction as _},
+    test_utils,
+};
+
+use crate::*;
+
+async fn open(cfs: &[&'static str]) -> MemDb {
+    let config = BuilderConfig {
+        cfs: cfs
+            .iter()
+            .map(|cf| (*cf, CfOptions::NotConfigured))
+            .collect(),
+        drop_unknown_cfs: false,
+    };
+    MemDbBuilder { _private: () }.build(config).await.unwrap()
+}
+
+#[tokio::test]
+async fn test_transaction_operations() {
+    test_utils::transaction_operations(&open(&[test_utils::CF]).await).await;
+}
+
+#[tokio::test]
+async fn test_scan_and_clear() {
+    test_utils::scan_and_clear(&open(&[test_utils::CF]).await).await;
+}
+
+#[tokio::test]
+async fn test_commit_and_rollback() {
+    test_utils::commit_and_rollback(&open(&[test_utils::CF]).await).await;
+}
+
+#[tokio::test]
+async fn test_mode_enforcement() {
+    test_utils::mode_enforcement(&open(&[test_utils::CF]).await).await;
+}
+
+#[tokio::test]
+async fn test_scan() {
+    let db = open(&["foo"]).await;
+    db.transaction(Mode::ReadWrite, &["foo"], |t, cfs| {
+        Box::pin(async move {
+            for k in [b"a", b"b", b"c", b"d"] {
+                t.put(&cfs[0], k, b"committed").await?;
+            }
+            Ok(())
+        })
+    })
+    .await
+    .unwrap();
+    db.transaction(Mode::ReadWrite, &["foo"], |t, cfs| {
+        Box::pin(async move {
+            // Scans must see the uncommitted writes of the transaction
+            t.delete(&cfs[0], b"b").await?;
+            t.put(&cfs[0], b"c", b"pending").await?;
+            t.put(&cfs[0], b"e", b"pending").await?;
+            let all = t.scan::<[u8]>(&cfs[0], ..).try_collect::<Vec<_>>().await?;
+            let expected = [
+                (b"a".to_vec(), b"committed".to_vec()),
+                (b"c".to_vec(), b"pending".to_vec()),
+                (b"d".to_vec(), b"committed".to_vec()),
+                (b"e".to_vec(), b"pending".to_vec()),
+            ];
+            assert_eq!(all, expected);
+            let mut all_rev = t
+                .scan_rev::<[u8]>(&cfs[0], ..)
+                .try_collect::<Vec<_>>()
+                .await?;
+            all_rev.reverse();
+            assert_eq!(all_rev, expected);
+            let some_rev = t
+                .scan_rev(&cfs[0], &b"b"[..]..&b"e"[..])
+                .map_ok(|(k, _)| k)
+                .try_collect::<Vec<_>>()
+                .await?;
+            assert_eq!(some_rev, [b"d", b"c"]);
+            Ok(())
+        })
+    })
+    .await
+    .unwrap();
+}
+
+#[tokio::test]
+async fn test_repeated_cf() {
+    let db = open(&["foo", "bar"]).await;
+    db.transaction(Mode::ReadWrite, &["foo", "bar", "foo"], |t, cfs| {
+        Box::pin(async move {
+            // Both handles on the same CF see the same writes
+            t.put(&cfs[0], b"a", b"1").await?;
+            assert_eq!(t.get(&cfs[2], b"a").await?, Some(b"1".to_vec()));
+            t.delete(&cfs[2], b"a").await?;
+            t.put(&cfs[2], b"b", b"2").await?;
+            assert_eq!(t.get(&cfs[0], b"a").await?, None);
+            assert_eq!(t.get(&cfs[1], b"b").await?, None);
+            Ok(())
+        })
+    })
+    .await
+    .unwrap();
+    db.transaction(Mode::ReadOnly, &["foo"], |t, cfs| {
+        Box::pin(async move {
+            assert_eq!(t.get(&cfs[0], b"b").await?, Some(b"2".to_vec()));
+            Ok(())
+        })
+    })
+    .await
+    .unwrap();
+}
+
+#[tokio::test]
+async fn test_borrowing_actions() {
+    let db = open(&["foo"]).await;
+    let values = vec![b"1".to_vec(), b"2".to_vec()];
+    db.transaction(Mode::ReadWrite, &["foo"], |t, cfs| {
+        // The actions can borrow from their environment
+        let values = values.clone();
+        Box::pin(async move {
+            for (i, v) in values.iter().enumerate() {
+                t.put(&cfs[0], &[u8::try_from(i).unwrap()], v).await?;
+            }
+            Ok(())
+        })
+    })
+    .await
+    .unwrap();
+    let read = db
+        .transaction(Mode::ReadOnly, &["foo"], |t, cfs| {
+            Box::pin(async move { t.get(&cfs[0], &[1]).await })
+        })
+        .await
+        .unwrap();
+    assert_eq!(read.as_ref(), Some(&values[1]));
+}
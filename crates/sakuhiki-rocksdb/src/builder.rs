@@ -6,7 +6,7 @@ use std::{
 use eyre::WrapErr as _;
 use rocksdb::{ColumnFamilyDescriptor, SingleThreaded};
 use sakuhiki_core::{
-    Backend as _, BackendBuilder, Mode,
+    BackendBuilder,
     backend::{BuilderConfig, CfOptions},
 };
 use tokio::task::spawn_blocking;
@@ -28,6 +28,9 @@ impl RocksDbBuilder {
         }
     }
 
+    /// Sets the options of the whole database
+    ///
+    /// `create_if_missing` is always enabled, as building a `Db` at a fresh path creates it.
     pub fn global_opts(&mut self, opts: rocksdb::Options) -> &mut Self {
         assert!(
             self.global_opts.is_none(),
@@ -46,21 +49,26 @@ impl RocksDbBuilder {
         self
     }
 
-    fn blocking_build_without_index_rebuilding(
+    fn blocking_build(
         self,
         mut cfs: HashMap<&'static str, CfOptions<RocksDb>>,
         drop_unknown_cfs: bool,
-    ) -> eyre::Result<(RocksDb, HashSet<&'static str>)> {
+    ) -> eyre::Result<RocksDb> {
         let path_d = self.path.display();
 
         // List pre-existing CFs
-        let (options, mut preexisting_cfs) = rocksdb::Options::load_latest(
+        let loaded = rocksdb::Options::load_latest(
             &self.path,
             rocksdb::Env::new()?,
             true,
             rocksdb::Cache::new_lru_cache(1024),
-        )
-        .wrap_err_with(|| format!("Failed listing CFs for {path_d}"))?;
+        );
+        let mut preexisting_cfs = match loaded {
+            Ok((_, preexisting_cfs)) => preexisting_cfs,
+            // The database does not exist yet, and will be created upon opening it
+            Err(e) if e.kind() == rocksdb::ErrorKind::NotFound => Vec::new(),
+            Err(e) => return Err(e).wrap_err_with(|| format!("Failed listing CFs for {path_d}")),
+        };
 
         // Prepare opening configuration
         let mut opened_unknown_cfs = HashSet::new();
@@ -75,7 +83,8 @@ impl RocksDbBuilder {
         }
 
         // Open the database
-        let opts = self.global_opts.unwrap_or_default();
+        let mut opts = self.global_opts.unwrap_or_default();
+        opts.create_if_missing(true);
         let txn_db_opts = self.txn_db_opts.unwrap_or_default();
         let mut db = rocksdb::TransactionDB::<SingleThreaded>::open_cf_descriptors(
             &opts,
@@ -94,7 +103,6 @@ impl RocksDbBuilder {
         }
 
         // Create missing CFs
-        let mut created_cfs = HashSet::new();
         for (cf, options) in cfs {
             let options = match options {
                 CfOptions::Configured(options) => options,
@@ -105,10 +113,9 @@ impl RocksDbBuilder {
             };
             db.create_cf(cf, &options)
                 .wrap_err_with(|| format!("Creating new CF {cf}"))?;
-            created_cfs.insert(cf);
         }
 
-        Ok((RocksDb::new(db), created_cfs))
+        Ok(RocksDb::new(db))
     }
 }
 
@@ -118,44 +125,14 @@ impl BackendBuilder for RocksDbBuilder {
 
     type BuildFuture = waaa::BoxFuture<'static, eyre::Result<RocksDb>>;
 
-    fn build(self, mut config: BuilderConfig<RocksDb>) -> Self::BuildFuture {
+    fn build(self, config: BuilderConfig<RocksDb>) -> Self::BuildFuture {
         Box::pin(async move {
             let path_d = self.path.display().to_string();
-            let (db, created_cfs) = spawn_blocking(move || {
-                self.blocking_build_without_index_rebuilding(config.cfs, config.drop_unknown_cfs)
-            })
-            .await
-            .wrap_err_with(|| {
-                format!("Failed joining task that builds the database for {path_d}")
-            })??;
-
-            // Rebuild indexes if needed
-            for i in config.index_rebuilders {
-                if created_cfs.contains(i.datum_cf)
-                    || i.index_cfs.iter().any(|cf| created_cfs.contains(cf))
-                {
-                    let mut cfs = Vec::with_capacity(i.index_cfs.len() + 1);
-                    cfs.push(
-                        db.cf_handle(i.datum_cf)
-                            .await
-                            .wrap_err_with(|| format!("Failed opening CF {}", i.datum_cf))?,
-                    );
-                    for cf in i.index_cfs {
-                        cfs.push(
-                            db.cf_handle(cf)
-                                .await
-                                .wrap_err_with(|| format!("Failed opening CF {}", i.datum_cf))?,
-                        );
-                    }
-                    db.transaction(Mode::IndexRebuilding, &cfs, |_, t, cfs| {
-                        Box::pin(async move { (i.rebuilder)(&t, &cfs[1..], &cfs[0]).await })
-                    })
-                    .await
-                    .wrap_err_with(|| format!("Rebuilding index with CFs {:?}", i.index_cfs))??;
-                }
-            }
-
-            Ok(db)
+            spawn_blocking(move || self.blocking_build(config.cfs, config.drop_unknown_cfs))
+                .await
+                .wrap_err_with(|| {
+                    format!("Failed joining task that builds the database for {path_d}")
+                })?
         })
     }
 }
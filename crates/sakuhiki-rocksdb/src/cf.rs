@@ -17,4 +17,8 @@ impl<'t> Cf<'t> {
     pub(crate) fn new(name: &'static str, cf: &'t ColumnFamily) -> Self {
         Cf { cf, name }
     }
+
+    pub(crate) fn cf(&self) -> &'t ColumnFamily {
+        self.cf
+    }
 }
@@ -4,13 +4,18 @@ use std::{
     path::Path,
 };
 
-use sakuhiki_core::{Backend, Mode, backend::Builder};
+use eyre::WrapErr as _;
+use sakuhiki_core::{
+    Backend, Mode,
+    backend::{BackendCf as _, Builder, MaybeStatic},
+};
 use tokio::task::block_in_place;
 
-use crate::{Cf, Error, RocksDbBuilder, Transaction};
+use crate::{Cf, Error, RocksDbBuilder, Transaction, locks::CfLocks};
 
 pub struct RocksDb {
     db: rocksdb::TransactionDB<rocksdb::SingleThreaded>,
+    locks: CfLocks,
 }
 
 impl RocksDb {
@@ -19,7 +24,10 @@ impl RocksDb {
     }
 
     pub(crate) fn new(db: rocksdb::TransactionDB<rocksdb::SingleThreaded>) -> RocksDb {
-        RocksDb { db }
+        RocksDb {
+            db,
+            locks: CfLocks::default(),
+        }
     }
 }
 
@@ -51,14 +59,35 @@ impl Backend for RocksDb {
         Bcf: 'fut + waaa::Send + waaa::Sync + Borrow<Cf<'db>>,
         F: 'fut
             + waaa::Send
-            + for<'t> FnOnce(&'t (), Transaction<'t>, Vec<Cf<'t>>) -> waaa::BoxFuture<'t, Ret>,
+            + MaybeStatic
+            + for<'t> FnOnce(
+                &'t Transaction<'t>,
+                Vec<Cf<'t>>,
+            ) -> waaa::BoxFuture<'t, eyre::Result<Ret>>,
+        Ret: MaybeStatic,
     {
-        // TODO(high): IndexRebuilding should exclusively lock the requested CFs
         Box::pin(async move {
+            let _locks = self
+                .locks
+                .lock(mode, cfs.iter().map(|cf| cf.borrow().name()))
+                .await;
             let t = block_in_place(|| self.db.transaction());
             let t = Transaction::new(t, mode);
             let cfs = cfs.iter().map(|cf| cf.borrow().clone()).collect();
-            Ok((actions)(&(), t, cfs).await)
+            // If `actions` panics or this future gets dropped, dropping `t` rolls it back
+            match (actions)(&t, cfs).await {
+                Ok(res) => {
+                    block_in_place(|| t.commit()).wrap_err("Failed committing transaction")?;
+                    Ok(res)
+                }
+                Err(err) => {
+                    if let Err(rollback_err) = block_in_place(|| t.rollback()) {
+                        return Err(err
+                            .wrap_err(format!("Failed rolling back transaction: {rollback_err}")));
+                    }
+                    Err(err)
+                }
+            }
         })
     }
 
@@ -1,9 +1,8 @@
-#![allow(dead_code, unused_mut, unused_variables)]
-
 mod builder;
 mod cf;
 mod db;
 mod error;
+mod locks;
 mod transaction;
 
 pub use builder::RocksDbBuilder;
@@ -12,4 +11,7 @@ pub use db::RocksDb;
 pub use error::Error;
 pub use transaction::Transaction;
 
+#[cfg(test)]
+mod tests;
+
 // TODO(med): comparative testing and fuzzing
@@ -0,0 +1,54 @@
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+};
+
+use async_lock::{RwLock, RwLockReadGuardArc, RwLockWriteGuardArc};
+use sakuhiki_core::Mode;
+
+/// Per-CF reader/writer locks, on top of the RocksDB transactions
+///
+/// RocksDB transactions only lock the keys they touch. This makes sure that `IndexRebuilding`
+/// transactions, which rewrite whole CFs, have exclusive access to the CFs they operate on.
+#[derive(Default)]
+pub(crate) struct CfLocks {
+    locks: Mutex<HashMap<&'static str, Arc<RwLock<()>>>>,
+}
+
+/// Lock on a CF, released upon being dropped
+#[allow(dead_code)] // The guards are never read, only held until dropped
+pub(crate) enum CfLockGuard {
+    Shared(RwLockReadGuardArc<()>),
+    Exclusive(RwLockWriteGuardArc<()>),
+}
+
+impl CfLocks {
+    /// Lock all of `cfs` for a transaction in `mode`
+    ///
+    /// `IndexRebuilding` transactions lock exclusively, all other transactions lock shared. The
+    /// locks are released when the returned guards are dropped.
+    pub(crate) async fn lock(
+        &self,
+        mode: Mode,
+        cfs: impl IntoIterator<Item = &'static str>,
+    ) -> Vec<CfLockGuard> {
+        // Always lock in the same order, to avoid deadlocks
+        let mut cfs = cfs.into_iter().collect::<Vec<_>>();
+        cfs.sort_unstable();
+        cfs.dedup();
+        let locks = {
+            let mut all_locks = self.locks.lock().unwrap();
+            cfs.into_iter()
+                .map(|cf| all_locks.entry(cf).or_default().clone())
+                .collect::<Vec<_>>()
+        };
+        let mut guards = Vec::with_capacity(locks.len());
+        for lock in locks {
+            guards.push(match mode {
+                Mode::IndexRebuilding => CfLockGuard::Exclusive(lock.write_arc().await),
+                Mode::ReadOnly | Mode::ReadWrite => CfLockGuard::Shared(lock.read_arc().await),
+            });
+        }
+        guards
+    }
+}
@@ -0,0 +1,349 @@
+use std::sync::Arc;
+
+use futures_util::{FutureExt as _, TryStreamExt as _};
+use sakuhiki_core::{
+    Backend as _, BackendBuilder as _, Datum, Error, IndexedDatum, Indexer, Migration, Mode,
+    backend::{BuilderConfig, CfOptions, Transaction as _},
+    test_utils,
+};
+
+use sakuhiki_index_btree::{BTreeIndex, BTreeQuery, FixedLenKey};
+
+use crate::{RocksDb, RocksDbBuilder, locks::CfLocks};
+
+async fn open(dir: &tempfile::TempDir, cfs: &[&'static str]) -> RocksDb {
+    let config = BuilderConfig {
+        cfs: cfs
+            .iter()
+            .map(|cf| (*cf, CfOptions::NotConfigured))
+            .collect(),
+        drop_unknown_cfs: false,
+    };
+    RocksDbBuilder::new(dir.path()).build(config).await.unwrap()
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_transaction_operations() {
+    let dir = tempfile::tempdir().unwrap();
+    test_utils::transaction_operations(&open(&dir, &[test_utils::CF]).await).await;
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_fresh_path_with_global_opts() {
+    let dir = tempfile::tempdir().unwrap();
+    let config = BuilderConfig {
+        cfs: [(test_utils::CF, CfOptions::NotConfigured)]
+            .into_iter()
+            .collect(),
+        drop_unknown_cfs: false,
+    };
+    // The options do not enable `create_if_missing`, but the database still gets created
+    let mut builder = RocksDbBuilder::new(dir.path().join("db"));
+    builder.global_opts(rocksdb::Options::default());
+    let db = builder.build(config).await.unwrap();
+    test_utils::transaction_operations(&db).await;
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_scan_and_clear() {
+    let dir = tempfile::tempdir().unwrap();
+    test_utils::scan_and_clear(&open(&dir, &[test_utils::CF]).await).await;
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_commit_and_rollback() {
+    let dir = tempfile::tempdir().unwrap();
+    test_utils::commit_and_rollback(&open(&dir, &[test_utils::CF]).await).await;
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_index_rebuilding_locks() {
+    let locks = CfLocks::default();
+    let rebuilding = locks.lock(Mode::IndexRebuilding, ["datum", "index"]).await;
+    assert!(
+        locks
+            .lock(Mode::ReadWrite, ["datum"])
+            .now_or_never()
+            .is_none()
+    );
+    assert!(
+        locks
+            .lock(Mode::ReadOnly, ["other", "index"])
+            .now_or_never()
+            .is_none()
+    );
+    let other = locks.lock(Mode::ReadWrite, ["other"]).now_or_never();
+    assert!(other.is_some());
+    let readonly = locks.lock(Mode::ReadOnly, ["other"]).now_or_never();
+    assert!(readonly.is_some());
+    drop(rebuilding);
+    assert!(
+        locks
+            .lock(Mode::ReadWrite, ["datum", "index"])
+            .now_or_never()
+            .is_some()
+    );
+    assert!(
+        locks
+            .lock(Mode::IndexRebuilding, ["other"])
+            .now_or_never()
+            .is_none()
+    );
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_index_rebuilding_transaction_locks() {
+    let dir = tempfile::tempdir().unwrap();
+    let db = open(&dir, &["datum", "index", "other"]).await;
+    let datum = db.cf_handle("datum").await.unwrap();
+    let index = db.cf_handle("index").await.unwrap();
+    let other = db.cf_handle("other").await.unwrap();
+    // Keep the rebuilding transaction running until `release` gets unlocked
+    let release = Arc::new(async_lock::Mutex::new(()));
+    let held = release.lock().await;
+    let (both, only_datum, only_index) = ([&datum, &index], [&datum], [&index]);
+    let mut rebuilding = db.transaction(Mode::IndexRebuilding, &both, |t, cfs| {
+        let release = release.clone();
+        Box::pin(async move {
+            t.put(&cfs[0], b"a", b"rebuilt").await?;
+            drop(release.lock().await);
+            Ok(())
+        })
+    });
+    assert!((&mut rebuilding).now_or_never().is_none());
+    let mut readwrite = db.transaction(Mode::ReadWrite, &only_datum, |t, cfs| {
+        Box::pin(async move { t.get(&cfs[0], b"a").await })
+    });
+    assert!((&mut readwrite).now_or_never().is_none());
+    let mut readonly = db.transaction(Mode::ReadOnly, &only_index, |_, _| {
+        Box::pin(async { Ok(()) })
+    });
+    assert!((&mut readonly).now_or_never().is_none());
+    // Transactions on other CFs are not blocked
+    db.transaction(Mode::ReadWrite, &[&other], |t, cfs| {
+        Box::pin(async move {
+            t.put(&cfs[0], b"a", b"1").await?;
+            Ok(())
+        })
+    })
+    .now_or_never()
+    .unwrap()
+    .unwrap();
+    // Once the rebuild commits, the blocked transactions proceed and see its changes
+    drop(held);
+    rebuilding.await.unwrap();
+    assert_eq!(readwrite.await.unwrap(), Some(b"rebuilt".to_vec()));
+    readonly.await.unwrap();
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_mode_enforcement() {
+    let dir = tempfile::tempdir().unwrap();
+    test_utils::mode_enforcement(&open(&dir, &[test_utils::CF]).await).await;
+}
+
+struct NameV0(String);
+
+impl Datum for NameV0 {
+    const CF: &'static str = "names";
+
+    fn from_slice(datum: &[u8]) -> eyre::Result<Self> {
+        Ok(NameV0(String::from_utf8(datum.to_vec())?))
+    }
+
+    fn to_bytes(&self) -> eyre::Result<Vec<u8>> {
+        Ok(self.0.as_bytes().to_vec())
+    }
+}
+
+impl IndexedDatum<RocksDb> for NameV0 {
+    const INDEXES: &'static [&'static dyn Indexer<RocksDb, Datum = Self>] = &[];
+}
+
+/// Same as `NameV0`, but uppercased and stored with a trailing `!`
+struct NameV2(String);
+
+impl Datum for NameV2 {
+    const CF: &'static str = "names";
+    const MIGRATIONS: &'static [Migration] =
+        &[|d| Ok(d.to_ascii_uppercase()), |d| Ok([d, b"!"].concat())];
+
+    fn from_slice(datum: &[u8]) -> eyre::Result<Self> {
+        let datum = datum
+            .strip_suffix(b"!")
+            .ok_or_else(|| eyre::eyre!("Missing trailing !"))?;
+        Ok(NameV2(String::from_utf8(datum.to_vec())?))
+    }
+
+    fn to_bytes(&self) -> eyre::Result<Vec<u8>> {
+        Ok([self.0.as_bytes(), b"!"].concat())
+    }
+}
+
+impl IndexedDatum<RocksDb> for NameV2 {
+    const INDEXES: &'static [&'static dyn Indexer<RocksDb, Datum = Self>] = &[];
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_schema_migrations() {
+    let dir = tempfile::tempdir().unwrap();
+    {
+        let db = RocksDb::builder(dir.path())
+            .datum::<NameV0>()
+            .build()
+            .await
+            .unwrap();
+        let cf = db.cf_handle::<NameV0>().await.unwrap();
+        db.transaction(Mode::ReadWrite, &[&cf], |t, [cf]| {
+            Box::pin(async move {
+                t.put_datum(&cf, b"1", &NameV0(String::from("alice")))
+                    .await?;
+                Ok(())
+            })
+        })
+        .await
+        .unwrap();
+    }
+
+    // Reopening runs the pending migrations
+    {
+        let db = RocksDb::builder(dir.path())
+            .datum::<NameV2>()
+            .build()
+            .await
+            .unwrap();
+        let cf = db.cf_handle::<NameV2>().await.unwrap();
+        db.transaction(Mode::ReadOnly, &[&cf], |t, [cf]| {
+            Box::pin(async move {
+                let name = t.get_datum::<NameV2>(&cf, b"1").await?.unwrap();
+                assert_eq!(name.0, "ALICE");
+                Ok(())
+            })
+        })
+        .await
+        .unwrap();
+    }
+
+    // Opening with an older schema version fails
+    let err = RocksDb::builder(dir.path())
+        .datum::<NameV0>()
+        .build()
+        .await
+        .err()
+        .unwrap();
+    assert!(matches!(
+        err.downcast_ref::<Error>(),
+        Some(Error::UnknownSchemaVersion {
+            cf: "names",
+            stored: 2,
+            latest: 0,
+        })
+    ));
+}
+
+/// Same as `NameV0`, but indexed by first letter
+struct NameByFirstLetter(String);
+
+const BY_FIRST_LETTER: BTreeIndex<FixedLenKey<NameByFirstLetter>> = BTreeIndex::new(
+    &["names-by-letter"],
+    FixedLenKey::new(
+        1,
+        |d, k| {
+            k[0] = d.0.as_bytes()[0];
+            true
+        },
+        None,
+    ),
+);
+
+impl Datum for NameByFirstLetter {
+    const CF: &'static str = "names";
+
+    fn from_slice(datum: &[u8]) -> eyre::Result<Self> {
+        Ok(NameByFirstLetter(String::from_utf8(datum.to_vec())?))
+    }
+
+    fn to_bytes(&self) -> eyre::Result<Vec<u8>> {
+        Ok(self.0.as_bytes().to_vec())
+    }
+}
+
+impl IndexedDatum<RocksDb> for NameByFirstLetter {
+    const INDEXES: &'static [&'static dyn Indexer<RocksDb, Datum = Self>] = &[&BY_FIRST_LETTER];
+}
+
+/// Same as `NameV0`, but indexed by last letter in the same CF as `NameByFirstLetter`
+struct NameByLastLetter(String);
+
+const BY_LAST_LETTER: BTreeIndex<FixedLenKey<NameByLastLetter>> = BTreeIndex::new_versioned(
+    &["names-by-letter"],
+    FixedLenKey::new(
+        1,
+        |d, k| {
+            k[0] = *d.0.as_bytes().last().unwrap();
+            true
+        },
+        None,
+    ),
+    1,
+);
+
+impl Datum for NameByLastLetter {
+    const CF: &'static str = "names";
+
+    fn from_slice(datum: &[u8]) -> eyre::Result<Self> {
+        Ok(NameByLastLetter(String::from_utf8(datum.to_vec())?))
+    }
+
+    fn to_bytes(&self) -> eyre::Result<Vec<u8>> {
+        Ok(self.0.as_bytes().to_vec())
+    }
+}
+
+impl IndexedDatum<RocksDb> for NameByLastLetter {
+    const INDEXES: &'static [&'static dyn Indexer<RocksDb, Datum = Self>] = &[&BY_LAST_LETTER];
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_index_fingerprint_change() {
+    let dir = tempfile::tempdir().unwrap();
+    {
+        let db = RocksDb::builder(dir.path())
+            .datum::<NameByFirstLetter>()
+            .build()
+            .await
+            .unwrap();
+        let cf = db.cf_handle::<NameByFirstLetter>().await.unwrap();
+        db.transaction(Mode::ReadWrite, &[&cf], |t, [cf]| {
+            Box::pin(async move {
+                t.put_datum(&cf, b"1", &NameByFirstLetter(String::from("alice")))
+                    .await?;
+                Ok(())
+            })
+        })
+        .await
+        .unwrap();
+    }
+
+    // Reopening with a different index version rebuilds the index
+    let db = RocksDb::builder(dir.path())
+        .datum::<NameByLastLetter>()
+        .build()
+        .await
+        .unwrap();
+    let cf = db.cf_handle::<NameByLastLetter>().await.unwrap();
+    db.transaction(Mode::ReadOnly, &[&cf], |t, [cf]| {
+        Box::pin(async move {
+            for (letter, expected) in [(b"a", 0), (b"e", 1)] {
+                let found = t
+                    .query(&cf, &BY_LAST_LETTER, &BTreeQuery::equal(letter))?
+                    .try_collect::<Vec<_>>()
+                    .await?;
+                assert_eq!(found.len(), expected);
+            }
+            Ok(())
+        })
+    })
+    .await
+    .unwrap();
+}
@@ -1,26 +1,164 @@
-use std::{ops::RangeBounds, sync::Mutex};
+use std::{
+    ops::{Bound, RangeBounds},
+    sync::Mutex,
+};
 
-use sakuhiki_core::{Backend, Mode};
+use eyre::WrapErr as _;
+use sakuhiki_core::{Backend, CfOperationError, Mode, backend::BackendCf as _};
+use tokio::task::block_in_place;
 
 use crate::{Cf, RocksDb};
 
+/// Number of entries read from RocksDB at once while scanning
+///
+/// The transaction lock is released between each batch, so that other operations can make
+/// progress while a scan stream is only partially consumed.
+const SCAN_BATCH_SIZE: usize = 128;
+
+/// Key-value pairs read by a single scan batch
+type Batch = Vec<(Vec<u8>, Vec<u8>)>;
+
 pub struct Transaction<'t> {
-    transaction: Mutex<rocksdb::Transaction<'t, rocksdb::TransactionDB>>,
+    /// Must be held while using `transaction`
+    ///
+    /// This is not a `Mutex<rocksdb::Transaction>`, because `Transaction` must stay covariant
+    /// in `'t` for `RocksDb::transaction` to be able to lend it to the user.
+    lock: Mutex<()>,
+    transaction: rocksdb::Transaction<'t, rocksdb::TransactionDB>,
     mode: Mode,
 }
 
+// SAFETY: `rocksdb::Transaction` is `Send` but not `Sync` as it does not support concurrent use.
+// All uses of `transaction` go through `Transaction::lock`, which prevents concurrent use.
+unsafe impl Sync for Transaction<'_> {}
+
 impl<'t> Transaction<'t> {
     pub(crate) fn new(t: rocksdb::Transaction<'t, rocksdb::TransactionDB>, mode: Mode) -> Self {
         Self {
-            transaction: Mutex::new(t),
+            lock: Mutex::new(()),
+            transaction: t,
             mode,
         }
     }
+
+    /// Runs `f` with exclusive access to the underlying RocksDB transaction
+    fn with_transaction<Ret>(
+        &self,
+        f: impl FnOnce(&rocksdb::Transaction<'t, rocksdb::TransactionDB>) -> Ret,
+    ) -> Ret {
+        let _lock = self.lock.lock().unwrap();
+        f(&self.transaction)
+    }
+
+    pub(crate) fn commit(self) -> Result<(), rocksdb::Error> {
+        self.transaction.commit()
+    }
+
+    pub(crate) fn rollback(self) -> Result<(), rocksdb::Error> {
+        self.transaction.rollback()
+    }
+
+    /// Read the value of `key` inside `t`, locking it if this transaction could write it
+    fn get_locked(
+        &self,
+        t: &rocksdb::Transaction<'t, rocksdb::TransactionDB>,
+        cf: &Cf<'t>,
+        key: &[u8],
+    ) -> Result<Option<Vec<u8>>, rocksdb::Error> {
+        match self.mode {
+            Mode::ReadOnly => t.get_cf(cf.cf(), key),
+            Mode::ReadWrite | Mode::IndexRebuilding => t.get_for_update_cf(cf.cf(), key, true),
+        }
+    }
+
+    /// Read at most `SCAN_BATCH_SIZE` entries of `cf` starting at `start`
+    ///
+    /// If `keys_only` is set, the returned values are all empty.
+    fn scan_batch(
+        &self,
+        cf: &Cf<'t>,
+        start: &Bound<Vec<u8>>,
+        end: &Bound<Vec<u8>>,
+        keys_only: bool,
+    ) -> Result<Batch, rocksdb::Error> {
+        let _lock = self.lock.lock().unwrap();
+        let mut it = self.transaction.raw_iterator_cf(cf.cf());
+        match start {
+            Bound::Unbounded => it.seek_to_first(),
+            Bound::Included(k) | Bound::Excluded(k) => it.seek(k),
+        }
+        if let Bound::Excluded(k) = start {
+            if it.key() == Some(k.as_slice()) {
+                it.next();
+            }
+        }
+        let mut batch = Vec::with_capacity(SCAN_BATCH_SIZE);
+        while batch.len() < SCAN_BATCH_SIZE {
+            let Some((key, value)) = it.item() else {
+                break;
+            };
+            let before_end = match end {
+                Bound::Unbounded => true,
+                Bound::Included(e) => key <= e.as_slice(),
+                Bound::Excluded(e) => key < e.as_slice(),
+            };
+            if !before_end {
+                break;
+            }
+            let value = if keys_only {
+                Vec::new()
+            } else {
+                value.to_vec()
+            };
+            batch.push((key.to_vec(), value));
+            it.next();
+        }
+        it.status()?;
+        Ok(batch)
+    }
+
+    /// Read at most `SCAN_BATCH_SIZE` entries of `cf` ending at `end`, in descending order
+    fn scan_batch_rev(
+        &self,
+        cf: &Cf<'t>,
+        start: &Bound<Vec<u8>>,
+        end: &Bound<Vec<u8>>,
+    ) -> Result<Batch, rocksdb::Error> {
+        let _lock = self.lock.lock().unwrap();
+        let mut it = self.transaction.raw_iterator_cf(cf.cf());
+        match end {
+            Bound::Unbounded => it.seek_to_last(),
+            Bound::Included(k) | Bound::Excluded(k) => it.seek_for_prev(k),
+        }
+        if let Bound::Excluded(k) = end {
+            if it.key() == Some(k.as_slice()) {
+                it.prev();
+            }
+        }
+        let mut batch = Vec::with_capacity(SCAN_BATCH_SIZE);
+        while batch.len() < SCAN_BATCH_SIZE {
+            let Some((key, value)) = it.item() else {
+                break;
+            };
+            let after_start = match start {
+                Bound::Unbounded => true,
+                Bound::Included(s) => key >= s.as_slice(),
+                Bound::Excluded(s) => key > s.as_slice(),
+            };
+            if !after_start {
+                break;
+            }
+            batch.push((key.to_vec(), value.to_vec()));
+            it.prev();
+        }
+        it.status()?;
+        Ok(batch)
+    }
 }
 
 impl<'t> sakuhiki_core::backend::Transaction<'t, RocksDb> for Transaction<'t> {
     fn current_mode(&self) -> Mode {
-        todo!() // TODO(high)
+        self.mode
     }
 
     fn get<'op, 'key>(
@@ -31,7 +169,10 @@ impl<'t> sakuhiki_core::backend::Transaction<'t, RocksDb> for Transaction<'t> {
     where
         'op: 'key,
     {
-        todo!() // TODO(high)
+        Box::pin(async move {
+            block_in_place(|| self.with_transaction(|t| self.get_locked(t, cf, key)))
+                .wrap_err_with(|| CfOperationError::new("Failed reading from", cf.name()))
+        })
     }
 
     fn scan<'op, 'keys, R>(
@@ -44,7 +185,54 @@ impl<'t> sakuhiki_core::backend::Transaction<'t, RocksDb> for Transaction<'t> {
         'op: 'keys,
         R: ?Sized + AsRef<[u8]>,
     {
-        todo!() // TODO(high)
+        let mut start = keys.start_bound().map(|k| k.as_ref().to_vec());
+        let end = keys.end_bound().map(|k| k.as_ref().to_vec());
+        Box::pin(async_stream::try_stream! {
+            loop {
+                let batch = block_in_place(|| self.scan_batch(cf, &start, &end, false))
+                    .wrap_err_with(|| CfOperationError::new("Failed scanning", cf.name()))?;
+                let is_last_batch = batch.len() < SCAN_BATCH_SIZE;
+                if let Some((last_key, _)) = batch.last() {
+                    start = Bound::Excluded(last_key.clone());
+                }
+                for entry in batch {
+                    yield entry;
+                }
+                if is_last_batch {
+                    break;
+                }
+            }
+        })
+    }
+
+    fn scan_rev<'op, 'keys, R>(
+        &'op self,
+        cf: &'op Cf<'t>,
+        keys: impl 'keys + RangeBounds<R>,
+    ) -> waaa::BoxStream<'keys, eyre::Result<(Vec<u8>, Vec<u8>)>>
+    where
+        't: 'op,
+        'op: 'keys,
+        R: ?Sized + AsRef<[u8]>,
+    {
+        let start = keys.start_bound().map(|k| k.as_ref().to_vec());
+        let mut end = keys.end_bound().map(|k| k.as_ref().to_vec());
+        Box::pin(async_stream::try_stream! {
+            loop {
+                let batch = block_in_place(|| self.scan_batch_rev(cf, &start, &end))
+                    .wrap_err_with(|| CfOperationError::new("Failed scanning", cf.name()))?;
+                let is_last_batch = batch.len() < SCAN_BATCH_SIZE;
+                if let Some((last_key, _)) = batch.last() {
+                    end = Bound::Excluded(last_key.clone());
+                }
+                for entry in batch {
+                    yield entry;
+                }
+                if is_last_batch {
+                    break;
+                }
+            }
+        })
     }
 
     fn put<'op, 'kv>(
@@ -57,7 +245,17 @@ impl<'t> sakuhiki_core::backend::Transaction<'t, RocksDb> for Transaction<'t> {
         't: 'op,
         'op: 'kv,
     {
-        todo!() // TODO(high)
+        Box::pin(async move {
+            self.mode.check_writable()?;
+            block_in_place(|| {
+                self.with_transaction(|t| {
+                    let old = self.get_locked(t, cf, key)?;
+                    t.put_cf(cf.cf(), key, value)?;
+                    Ok::<_, rocksdb::Error>(old)
+                })
+            })
+            .wrap_err_with(|| CfOperationError::new("Failed putting into", cf.name()))
+        })
     }
 
     fn delete<'op, 'key>(
@@ -69,13 +267,46 @@ impl<'t> sakuhiki_core::backend::Transaction<'t, RocksDb> for Transaction<'t> {
         't: 'op,
         'op: 'key,
     {
-        todo!() // TODO(high)
+        Box::pin(async move {
+            self.mode.check_writable()?;
+            block_in_place(|| {
+                self.with_transaction(|t| {
+                    let old = self.get_locked(t, cf, key)?;
+                    if old.is_some() {
+                        t.delete_cf(cf.cf(), key)?;
+                    }
+                    Ok::<_, rocksdb::Error>(old)
+                })
+            })
+            .wrap_err_with(|| CfOperationError::new("Failed deleting from", cf.name()))
+        })
     }
 
     fn clear<'op>(
         &'op self,
         cf: &'op <RocksDb as Backend>::TransactionCf<'t>,
     ) -> waaa::BoxFuture<'op, eyre::Result<()>> {
-        todo!() // TODO(high)
+        // Transactions do not support range deletions, so we need to delete keys one by one.
+        // Do so by batches, to avoid loading the whole CF in memory at once.
+        Box::pin(async move {
+            self.mode.check_index_rebuilding()?;
+            block_in_place(|| {
+                let mut start = Bound::Unbounded;
+                loop {
+                    let batch = self.scan_batch(cf, &start, &Bound::Unbounded, true)?;
+                    self.with_transaction(|t| {
+                        for (key, _) in &batch {
+                            t.delete_cf(cf.cf(), key)?;
+                        }
+                        Ok::<_, rocksdb::Error>(())
+                    })?;
+                    match batch.into_iter().last() {
+                        Some((last_key, _)) => start = Bound::Excluded(last_key),
+                        None => return Ok::<_, rocksdb::Error>(()),
+                    }
+                }
+            })
+            .wrap_err_with(|| CfOperationError::new("Failed clearing", cf.name()))
+        })
     }
 }
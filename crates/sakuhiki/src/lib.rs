@@ -1,4 +1,19 @@
 pub use sakuhiki_core::*;
 
+#[cfg(feature = "index-aggregate")]
+pub use sakuhiki_index_aggregate::*;
+
 #[cfg(feature = "index-btree")]
 pub use sakuhiki_index_btree::*;
+
+#[cfg(feature = "index-fts")]
+pub use sakuhiki_index_fts::*;
+
+#[cfg(feature = "index-geo")]
+pub use sakuhiki_index_geo::*;
+
+#[cfg(feature = "index-relation")]
+pub use sakuhiki_index_relation::*;
+
+#[cfg(feature = "derive")]
+pub use sakuhiki_derive::{Datum, IndexedDatum};
@@ -9,7 +9,9 @@ pkgs.mkShell.override {
     cargo-nextest
     just
     niv
+    nodejs
     pkg-config
+    wasm-bindgen-cli
 
     (fenix.combine (with fenix; [
       minimal.cargo